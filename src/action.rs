@@ -1,8 +1,16 @@
-use crate::{constants, helper};
+use crate::{cli, constants, helper, runlog};
 use anyhow::Result;
-use log::debug;
-use std::io::Write;
-use std::{env, fs, io, process};
+use chrono::Utc;
+use log::{debug, error};
+use std::fmt::Display;
+use std::io::{BufRead, BufReader, Read};
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::path::Path;
+use std::process::Stdio;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+use std::{env, fs, process};
 
 // TODO requires validation on any supported Linux distro (endorsed distros)
 // If TMUX isn't available install it. If installation isn't possible make the user aware that tmux is required if the action 'chroot-cli' is selected
@@ -59,12 +67,186 @@ pub(crate) fn execute_chroot_cli() -> Result<()> {
     Ok(())
 }
 
+// Ordered from best to worst, the worst status of all actions determines the exit code of ALAR
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) enum ActionStatus {
+    Success,
+    Failed,
+    Signaled,
+    TimedOut,
+}
+
+impl ActionStatus {
+    pub(crate) fn exit_code(&self) -> i32 {
+        match self {
+            ActionStatus::Success => 0,
+            ActionStatus::Failed => constants::EXIT_ACTION_FAILED,
+            ActionStatus::Signaled => constants::EXIT_ACTION_SIGNALED,
+            ActionStatus::TimedOut => constants::EXIT_ACTION_TIMEOUT,
+        }
+    }
+}
+
+impl Display for ActionStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ActionStatus::Success => write!(f, "success"),
+            ActionStatus::Failed => write!(f, "failed"),
+            ActionStatus::Signaled => write!(f, "terminated by signal"),
+            ActionStatus::TimedOut => write!(f, "timed out"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct ActionResult {
+    pub(crate) name: String,
+    pub(crate) exit_code: Option<i32>,
+    pub(crate) signal: Option<i32>,
+    pub(crate) duration: Duration,
+    pub(crate) output: String,
+    pub(crate) timed_out: bool,
+}
+
+impl ActionResult {
+    // Used if the action couldn't be started at all
+    pub(crate) fn not_started(action_name: &str, error: &anyhow::Error) -> ActionResult {
+        ActionResult {
+            name: action_name.to_string(),
+            exit_code: None,
+            signal: None,
+            duration: Duration::default(),
+            output: format!("The action could not be started: {error}"),
+            timed_out: false,
+        }
+    }
+
+    pub(crate) fn output_tail(&self, lines: usize) -> Vec<&str> {
+        let all_lines: Vec<&str> = self.output.lines().collect();
+        all_lines[all_lines.len().saturating_sub(lines)..].to_vec()
+    }
+
+    pub(crate) fn status(&self) -> ActionStatus {
+        if self.timed_out {
+            return ActionStatus::TimedOut;
+        }
+        match (self.exit_code, self.signal) {
+            (Some(0), _) => ActionStatus::Success,
+            (_, Some(_)) => ActionStatus::Signaled,
+            _ => ActionStatus::Failed,
+        }
+    }
+}
+
+impl Display for ActionResult {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.name, self.status())?;
+        if let Some(code) = self.exit_code {
+            write!(f, " (exit code {code})")?;
+        }
+        if let Some(signal) = self.signal {
+            write!(f, " (signal {signal})")?;
+        }
+        write!(f, " after {:.1}s", self.duration.as_secs_f64())
+    }
+}
+
+// The action runs in its own process group. Killing the group also stops anything the action started,
+// like a hanging dracut or package manager.
+fn kill_process_group(child: &process::Child) {
+    let pgid = child.id();
+    match process::Command::new("kill")
+        .args(["-KILL", "--", &format!("-{pgid}")])
+        .status()
+    {
+        Ok(status) if status.success() => debug!("Process group {pgid} killed"),
+        Ok(status) => error!("Killing the process group {pgid} returned {status}"),
+        Err(e) => error!("Killing the process group {pgid} was not possible: {e}"),
+    }
+}
+
+// Prints the output of the action line by line as it arrives, prefixed with a timestamp and the action name.
+// Each line also goes to the run log. The combined stdout and stderr output is returned.
+// If the deadline passes the process group of the action gets killed and the second value is true.
+fn stream_output(
+    action_name: &str,
+    child: &mut process::Child,
+    deadline: Option<Instant>,
+) -> (String, bool) {
+    let (sender, receiver) = mpsc::channel::<(bool, String)>();
+
+    fn forward(reader: impl Read + Send + 'static, is_stderr: bool, sender: mpsc::Sender<(bool, String)>) {
+        thread::spawn(move || {
+            for line in BufReader::new(reader).lines().map_while(Result::ok) {
+                if sender.send((is_stderr, line)).is_err() {
+                    break;
+                }
+            }
+        });
+    }
+
+    if let Some(stdout) = child.stdout.take() {
+        forward(stdout, false, sender.clone());
+    }
+    if let Some(stderr) = child.stderr.take() {
+        forward(stderr, true, sender.clone());
+    }
+    // Only the reader threads hold a sender now. The loop ends once both pipes are closed.
+    drop(sender);
+
+    let mut output = String::new();
+    let mut timed_out = false;
+    loop {
+        let received = match deadline {
+            Some(deadline) if !timed_out => {
+                receiver.recv_timeout(deadline.saturating_duration_since(Instant::now()))
+            }
+            // After the kill the pipes get closed. A process which left the process group may still
+            // hold them open though, so we don't wait forever for that.
+            _ if timed_out => receiver.recv_timeout(constants::KILL_GRACE_PERIOD),
+            _ => receiver.recv().map_err(|_| mpsc::RecvTimeoutError::Disconnected),
+        };
+        let (is_stderr, line) = match received {
+            Ok(message) => message,
+            Err(mpsc::RecvTimeoutError::Timeout) if !timed_out => {
+                error!("The action {action_name} exceeded its time limit and gets terminated.");
+                kill_process_group(child);
+                timed_out = true;
+                continue;
+            }
+            Err(_) => break,
+        };
+        let prefixed = format!(
+            "{} [{action_name}] {line}",
+            Utc::now().format("%Y-%m-%dT%H:%M:%SZ")
+        );
+        if is_stderr {
+            eprintln!("{prefixed}");
+        } else {
+            println!("{prefixed}");
+        }
+        runlog::write_line(&prefixed);
+        output.push_str(&line);
+        output.push('\n');
+    }
+    (output, timed_out)
+}
+
+// The deadline of an action started at the given time. The earlier of both limits applies.
+pub(crate) fn deadline(start: Instant, action_timeout: Option<Duration>, run_deadline: Option<Instant>) -> Option<Instant> {
+    let action_deadline = action_timeout.and_then(|timeout| start.checked_add(timeout));
+    match (action_deadline, run_deadline) {
+        (Some(action_deadline), Some(run_deadline)) => Some(action_deadline.min(run_deadline)),
+        (action_deadline, run_deadline) => action_deadline.or(run_deadline),
+    }
+}
+
 fn is_action_python_script(action_name: &str) -> bool {
     let file_name = format!("{}/{}-impl.py", constants::ACTION_IMPL_DIR, action_name);
     fs::metadata(file_name).is_ok()
 }
 
-pub(crate) fn run_repair_script(action_name: &str) -> Result<()> {
+pub(crate) fn run_repair_script(action_name: &str, deadline: Option<Instant>) -> Result<ActionResult> {
     match env::set_current_dir(constants::RESCUE_ROOT) {
         Ok(_) => {}
         Err(e) => println!("Error in set current dir : {e}"),
@@ -80,15 +262,21 @@ pub(crate) fn run_repair_script(action_name: &str) -> Result<()> {
     helper::run_cmd(&command)?;
 
     println!("--- Action script output start ---");
-    let output = process::Command::new("chroot")
+    let start = Instant::now();
+    // The action runs the LVM commands of the recovered system with its own configuration
+    let mut child = process::Command::new("chroot")
         .arg(constants::RESCUE_ROOT)
         .arg("/bin/bash")
         .arg("-c")
         .arg(file_name)
-        .output()?;
-
-    io::stdout().write_all(&output.stdout).unwrap();
-    io::stderr().write_all(&output.stderr).unwrap();
+        .env_remove("LVM_SYSTEM_DIR")
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .process_group(0)
+        .spawn()?;
+    let (output, timed_out) = stream_output(action_name, &mut child, deadline);
+    let status = child.wait()?;
+    let duration = start.elapsed();
     println!("--- Action script output end ---");
 
     // Get out of constants::RESCUE_ROOT, otherwise umount isn't possible for RESCUE_ROOT
@@ -97,12 +285,19 @@ pub(crate) fn run_repair_script(action_name: &str) -> Result<()> {
         Err(e) => println!("Error in set current dir : {e}"),
     }
 
-    Ok(())
+    Ok(ActionResult {
+        name: action_name.to_string(),
+        exit_code: status.code(),
+        signal: status.signal(),
+        duration,
+        output,
+        timed_out,
+    })
 }
 
 pub(crate) fn is_action_available(action_name: &str) -> Result<bool> {
     let action_name = action_name.to_lowercase();
-    if action_name == constants::CHROOT_CLI {
+    if action_name == constants::CHROOT_CLI || action_name == constants::LVMDEVICES {
         return Ok(true);
     }
 
@@ -116,3 +311,249 @@ pub(crate) fn is_action_available(action_name: &str) -> Result<bool> {
     }
     Ok(false)
 }
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum ActionSource {
+    Builtin,
+    Directory(String),
+    Download,
+}
+
+impl Display for ActionSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ActionSource::Builtin => write!(f, "builtin"),
+            ActionSource::Directory(directory) => write!(f, "directory {directory}"),
+            ActionSource::Download => write!(f, "download"),
+        }
+    }
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct ScriptHeader {
+    pub(crate) version: Option<String>,
+    pub(crate) latest_update: Option<String>,
+    pub(crate) purpose: Option<String>,
+}
+
+#[derive(Debug)]
+pub(crate) struct ActionInfo {
+    pub(crate) name: String,
+    pub(crate) source: ActionSource,
+    pub(crate) header: ScriptHeader,
+}
+
+// Reads the standard header block of an action script:
+// # Version: 1.1.0
+// # Latest update: 2025-12-16
+// # Purpose: ALAR implementation for fixing ...
+// #          continuation of the purpose
+// Not all of the action scripts have such a header. Missing details stay None.
+pub(crate) fn parse_script_header(content: &str) -> ScriptHeader {
+    let mut header = ScriptHeader::default();
+    let mut in_purpose = false;
+
+    for line in content.lines().take_while(|line| line.starts_with('#')) {
+        let detail = line.trim_start_matches('#');
+        if in_purpose && detail.starts_with("  ") && !detail.trim().is_empty() {
+            if let Some(purpose) = header.purpose.as_mut() {
+                purpose.push(' ');
+                purpose.push_str(detail.trim());
+            }
+            continue;
+        }
+        in_purpose = false;
+
+        let detail = detail.trim();
+        if let Some(version) = detail.strip_prefix("Version:") {
+            header.version = Some(version.trim().to_string());
+        } else if let Some(latest_update) = detail.strip_prefix("Latest update:") {
+            header.latest_update = Some(latest_update.trim().to_string());
+        } else if let Some(purpose) = detail.strip_prefix("Purpose:") {
+            header.purpose = Some(purpose.trim().to_string());
+            in_purpose = true;
+        }
+    }
+    header
+}
+
+fn action_name_of(file_name: &str) -> Option<&str> {
+    file_name
+        .strip_suffix("-impl.sh")
+        .or_else(|| file_name.strip_suffix("-impl.py"))
+}
+
+fn read_actions_from_directory(directory: &str, source: ActionSource) -> Result<Vec<ActionInfo>> {
+    let mut actions = Vec::new();
+    for item in fs::read_dir(directory)? {
+        let path = item?.path();
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+        if let Some(name) = action_name_of(&file_name) {
+            actions.push(ActionInfo {
+                name: name.to_string(),
+                source: source.clone(),
+                header: parse_script_header(&fs::read_to_string(&path).unwrap_or_default()),
+            });
+        }
+    }
+    actions.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(actions)
+}
+
+// The builtin actions are always listed. Actions of a local directory or of the downloaded
+// tarball are added if the related option is passed over.
+pub(crate) fn list_actions(cli_info: &cli::CliInfo) -> Result<Vec<ActionInfo>> {
+    let mut actions: Vec<ActionInfo> = constants::BUILTIN_ACTION_FILES
+        .iter()
+        .filter_map(|(file_name, content)| {
+            action_name_of(file_name).map(|name| ActionInfo {
+                name: name.to_string(),
+                source: ActionSource::Builtin,
+                header: parse_script_header(content),
+            })
+        })
+        .collect();
+    actions.push(ActionInfo {
+        name: constants::CHROOT_CLI.to_string(),
+        source: ActionSource::Builtin,
+        header: ScriptHeader {
+            purpose: Some("Interactive shell in the chroot environment (tmux required)".to_string()),
+            ..ScriptHeader::default()
+        },
+    });
+    actions.push(ActionInfo {
+        name: constants::LVMDEVICES.to_string(),
+        source: ActionSource::Builtin,
+        header: ScriptHeader {
+            purpose: Some("Repair the LVM devices file and filter of the recovered system if they don't match its PVs".to_string()),
+            ..ScriptHeader::default()
+        },
+    });
+    actions.sort_by(|a, b| a.name.cmp(&b.name));
+
+    if !cli_info.local_action_directory.is_empty() {
+        actions.extend(read_actions_from_directory(
+            &cli_info.local_action_directory,
+            ActionSource::Directory(cli_info.local_action_directory.clone()),
+        )?);
+    }
+
+    if cli_info.download_action_scripts {
+        // Expanded into its own directory to not overwrite the scripts in ACTION_IMPL_DIR
+        if Path::new(constants::LIST_ACTIONS_DIR).exists() {
+            fs::remove_dir_all(constants::LIST_ACTIONS_DIR)?;
+        }
+        fs::create_dir_all(constants::LIST_ACTIONS_DIR)?;
+        helper::fetch_action_scripts_tarball(constants::LIST_ACTIONS_DIR)?;
+        let downloaded = read_actions_from_directory(
+            &format!("{}/action_implementation", constants::LIST_ACTIONS_DIR),
+            ActionSource::Download,
+        );
+        fs::remove_dir_all(constants::LIST_ACTIONS_DIR)?;
+        actions.extend(downloaded?);
+    }
+
+    Ok(actions)
+}
+
+pub(crate) fn print_action_list(actions: &[ActionInfo]) {
+    let name_width = actions.iter().map(|a| a.name.len()).max().unwrap_or(0).max(6);
+    let source_width = actions
+        .iter()
+        .map(|a| a.source.to_string().len())
+        .max()
+        .unwrap_or(0)
+        .max(6);
+
+    println!(
+        "{:name_width$}  {:source_width$}  {:8}  {:13}  PURPOSE",
+        "ACTION", "SOURCE", "VERSION", "LATEST UPDATE"
+    );
+    for action in actions {
+        println!(
+            "{:name_width$}  {:source_width$}  {:8}  {:13}  {}",
+            action.name,
+            action.source.to_string(),
+            action.header.version.as_deref().unwrap_or("-"),
+            action.header.latest_update.as_deref().unwrap_or("-"),
+            action.header.purpose.as_deref().unwrap_or("-"),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn script_header_sudo() {
+        let header = parse_script_header(constants::SUDO_IMPL_FILE);
+        assert_eq!(header.version.as_deref(), Some("1.1.0"));
+        assert_eq!(header.latest_update.as_deref(), Some("2025-12-16"));
+        assert_eq!(
+            header.purpose.as_deref(),
+            Some("ALAR implementation for fixing common issues with the sudo configurations")
+        );
+    }
+
+    fn result(exit_code: Option<i32>, signal: Option<i32>, timed_out: bool) -> ActionResult {
+        ActionResult {
+            name: "test".to_string(),
+            exit_code,
+            signal,
+            duration: Duration::default(),
+            output: String::new(),
+            timed_out,
+        }
+    }
+
+    #[test]
+    fn action_status_and_exit_code() {
+        assert!(ActionStatus::Success < ActionStatus::Failed);
+        assert!(ActionStatus::Failed < ActionStatus::Signaled);
+        assert!(ActionStatus::Signaled < ActionStatus::TimedOut);
+
+        let results = [
+            result(Some(0), None, false),
+            result(Some(1), None, false),
+            result(None, Some(9), false),
+            result(None, Some(9), true),
+            ActionResult::not_started("test", &anyhow::anyhow!("missing")),
+        ];
+        let statuses: Vec<ActionStatus> = results.iter().map(ActionResult::status).collect();
+        assert_eq!(
+            statuses,
+            vec![
+                ActionStatus::Success,
+                ActionStatus::Failed,
+                ActionStatus::Signaled,
+                ActionStatus::TimedOut,
+                ActionStatus::Failed
+            ]
+        );
+        let exit_codes: Vec<i32> = statuses.iter().map(ActionStatus::exit_code).collect();
+        assert_eq!(exit_codes, vec![0, 2, 3, 4, 2]);
+        // The worst result decides the exit code of ALAR
+        assert_eq!(statuses[..3].iter().max(), Some(&ActionStatus::Signaled));
+    }
+
+    #[test]
+    fn action_deadline() {
+        let start = Instant::now();
+        let minute = Duration::from_secs(60);
+        assert_eq!(deadline(start, None, None), None);
+        assert_eq!(deadline(start, Some(minute), None), Some(start + minute));
+        assert_eq!(deadline(start, None, Some(start + minute)), Some(start + minute));
+        // The run ends before the action would
+        assert_eq!(deadline(start, Some(2 * minute), Some(start + minute)), Some(start + minute));
+        assert_eq!(deadline(start, Some(minute), Some(start + 2 * minute)), Some(start + minute));
+    }
+
+    #[test]
+    fn script_header_missing() {
+        assert_eq!(
+            parse_script_header(constants::TEST_IMPL_FILE),
+            ScriptHeader::default()
+        );
+    }
+}
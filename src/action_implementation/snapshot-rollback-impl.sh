@@ -0,0 +1,53 @@
+#!/usr/bin/bash
+# -----------------------------------------------------------------------------
+# Version: 1.0.0
+# Initial release: 2026-10-17
+# Latest update: 2026-10-17
+# Author: Azure Support
+#
+# Copyright (c) Microsoft Corporation. All rights reserved.
+# Licensed under the terms found in the LICENSE file in the root of this source tree.
+# -----------------------------------------------------------------------------
+# Purpose: ALAR implementation for booting an earlier snapper snapshot of a
+#          btrfs root filesystem
+#
+# The snapshot is selected by ALAR and passed over in ROLLBACK_SNAPSHOT. It is
+# the one given with '--snapshot', otherwise the newest snapshot older than the
+# one booted so far.
+# - with snapper available 'snapper rollback' creates a writable copy of the
+#   snapshot and makes it the default subvolume
+# - otherwise the snapshot itself becomes the default subvolume
+# -----------------------------------------------------------------------------
+# Variables are set by prepare_chroot.rs
+
+if [[ ${isBtrfs} != "true" ]]; then
+	echo "The root filesystem is not btrfs. There is no snapshot to roll back to."
+	exit 1
+fi
+
+if [[ -z ${ROLLBACK_SNAPSHOT} ]]; then
+	echo "No earlier snapshot is available. Use the option '--snapshot' to select one."
+	exit 1
+fi
+
+SNAPSHOT_PATH="/.snapshots/${ROLLBACK_SNAPSHOT}/snapshot"
+if [[ ! -d ${SNAPSHOT_PATH} ]]; then
+	echo "The snapshot ${ROLLBACK_SNAPSHOT} doesn't exist below /.snapshots"
+	exit 1
+fi
+
+echo "Default subvolume before the rollback: $(btrfs subvolume get-default /)"
+
+if command -v snapper >/dev/null 2>&1; then
+	snapper --no-dbus rollback --description "ALAR rollback to snapshot ${ROLLBACK_SNAPSHOT}" "${ROLLBACK_SNAPSHOT}" || exit 1
+else
+	SNAPSHOT_ID=$(btrfs subvolume show "${SNAPSHOT_PATH}" | awk '/Subvolume ID:/ { print $3 }')
+	if [[ -z ${SNAPSHOT_ID} ]]; then
+		echo "The subvolume ID of ${SNAPSHOT_PATH} can not be determined"
+		exit 1
+	fi
+	echo "snapper is not installed. The read-only snapshot ${ROLLBACK_SNAPSHOT} becomes the default subvolume."
+	btrfs subvolume set-default "${SNAPSHOT_ID}" / || exit 1
+fi
+
+echo "Default subvolume after the rollback: $(btrfs subvolume get-default /)"
@@ -1,4 +1,3 @@
-use std::env;
 use std::fs;
 use std::path::Path;
 use std::process;
@@ -9,9 +8,11 @@ use crate::constants;
 use crate::distro;
 use crate::distro::PartInfo;
 use crate::helper;
+use crate::lvm;
 use crate::mount;
 use crate::telemetry;
-use anyhow::Result;
+use crate::teardown::{self, Step};
+use anyhow::{Context, Result, anyhow};
 use log::debug;
 use log::error;
 use log::info;
@@ -56,10 +57,7 @@ pub(crate) fn prepare_ade_environment(
                 mount_ade_manually(partitions, cli_info)?;
                 Ok(true)
             }
-            Err(e) => {
-                error!("Error checking mountpoint: {e}");
-                process::exit(1);
-            }
+            Err(e) => Err(e.context("Error checking mountpoint")),
         }
     } else {
         // This is the case when another VM is used to recover the encrypted disk
@@ -71,11 +69,9 @@ pub(crate) fn prepare_ade_environment(
                     mount_ade_manually(partitions, cli_info)?;
                     Ok(true)
                 }
-                Err(e) => {
-                    error!("Error reading the pass phrase file  {e} from the BEK disk");
-                    error!("Please provide the password in base64 format to decrypt the disk.");
-                    process::exit(1);
-                }
+                Err(e) => Err(e.context(
+                    "Error reading the pass phrase file from the BEK disk. Please provide the password in base64 format to decrypt the disk",
+                )),
             }
         } else {
             // The password is passed over to ALAR. We can use the password to mount the disk and proceed with the recovery process
@@ -85,16 +81,42 @@ pub(crate) fn prepare_ade_environment(
     }
 }
 
+// Describes which way prepare_ade_environment() would take to unlock the disk. Nothing gets opened or mounted.
+pub(crate) fn describe_unlock_method(cli_info: &CliInfo) -> String {
+    let is_repair_vm = helper::is_repair_vm_imds().unwrap_or(false);
+    let key_source = if cli_info.ade_password.is_empty() {
+        format!("pass phrase file {} from the BEK volume", constants::RESCUE_BEK_LINUX_PASS_PHRASE_FILE_NAME)
+    } else {
+        "pass phrase passed over with --ade-password".to_string()
+    };
+
+    match (is_repair_vm, is_mountpoint(constants::INVESTIGATEROOT_DIR)) {
+        (true, Ok(Mountpoint::Mounted)) => format!(
+            "repair VM: umount {}, deactivate the VG on {}, close osencrypt and reopen the root partition as {} with the {key_source}",
+            constants::INVESTIGATEROOT_DIR,
+            constants::REPAIR_OSENCRYPT_PATH,
+            constants::ADE_OSENCRYPT_PATH
+        ),
+        _ => format!(
+            "cryptsetup luksOpen of the root partition as {} using the header {}/luks/osluksheader and the {key_source}",
+            constants::ADE_OSENCRYPT_PATH,
+            constants::RESCUE_BEK_BOOT
+        ),
+    }
+}
+
 /**
  The function modify_existing_ade_setup is used when ALAR is running in a repair VM context.
  This function relies on an existent BEK partition from which the password can be read.
 */
 fn modify_existing_ade_setup(partitions: &[PartInfo], cli_info: &mut CliInfo) -> Result<()> {
     mount::umount(constants::INVESTIGATEROOT_DIR, true)?;
+    // The VG within the container opened by the repair extension, whatever name it carries
     if has_lvm_partition(partitions) {
+        let vg_name = mount::vg_name_of(constants::REPAIR_OSENCRYPT_PATH)?;
         process::Command::new("vgchange")
             .arg("-an")
-            .arg(constants::RESCUE_ROOTVG)
+            .arg(vg_name)
             .status()?;
     }
     process::Command::new("cryptsetup")
@@ -133,47 +155,36 @@ fn create_rescue_bek_boot() -> Result<()> {
     Ok(())
 }
 
-fn find_root_partition_number(partitions: &[PartInfo]) -> i32 {
+fn find_root_partition(partitions: &[PartInfo]) -> &PartInfo {
     let root_device = partitions
         .iter()
         .find(|part| part.fstype.contains("crypt?"));
     // unwrap is safe here because we know that there is a root partition
-    root_device.unwrap().number
+    root_device.unwrap()
 }
 
-fn find_boot_partition_number(partitions: &[PartInfo]) -> i32 {
+fn find_boot_partition(partitions: &[PartInfo]) -> &PartInfo {
     let boot_partition = partitions
         .iter()
         .filter(|part| part.part_type != "EF00")
         .find(|part| part.fstype != "crypt?");
     // unwrap is safe here because we know that there is a boot partition
-    boot_partition.unwrap().number
+    boot_partition.unwrap()
 }
 
 fn mount_bek_volume() -> Result<()> {
     create_rescue_bek_dir()?;
-    let bek_volume = match helper::run_fun("blkid -t LABEL='BEK VOLUME' -o device") {
-        Ok(device) => {
-            debug!("BEK volume details: {device}");
-            device
-        }
-        Err(e) => {
-            error!("blkid raised an error : {e}");
-            error!("Please set the password manually and run ALAR with the option :  --ade-password <password>");
-            process::exit(1);
-        }
-    };
+    let bek_volume = helper::run_fun("blkid -t LABEL='BEK VOLUME' -o device")
+        .context("blkid raised an error. Please set the password manually and run ALAR with the option :  --ade-password <password>")?;
+    debug!("BEK volume details: {bek_volume}");
     if bek_volume.is_empty() {
-        error!("There is no BEK VOLUME attached to the VM");
-        error!("Please get the password manually and run ALAR with the option :  --ade-password <password>");
-        process::exit(1);
+        return Err(anyhow!("There is no BEK VOLUME attached to the VM. Please get the password manually and run ALAR with the option :  --ade-password <password>"));
     };
 
     mount::mount(bek_volume.trim(), constants::RESCUE_BEK, "", false)?;
     if !Path::new(constants::RESCUE_BEK_LINUX_PASS_PHRASE_FILE_NAME).exists() {
-        error!("The pass phrase file doesn't exist. Please restart the VM to get the file LinuxPassPhraseFileName automatically created.");
         mount::umount(constants::RESCUE_BEK, false)?;
-        process::exit(1);
+        return Err(anyhow!("The pass phrase file doesn't exist. Please restart the VM to get the file LinuxPassPhraseFileName automatically created."));
     }
     Ok(())
 }
@@ -190,16 +201,10 @@ fn read_pass_phrase_file() -> Result<String> {
     Ok(pass_phrase_file)
 }
 
-fn mount_boot_partition(cli_info: &CliInfo, partitions: &[distro::PartInfo]) -> Result<()> {
-    let boot_partition_number = find_boot_partition_number(partitions);
-    let boot_partition_path = helper::get_recovery_disk_path(cli_info);
+fn mount_boot_partition(partitions: &[distro::PartInfo]) -> Result<()> {
+    let boot_partition = find_boot_partition(partitions);
     create_rescue_bek_boot()?;
-    mount::mount(
-        &format!("{}{}", boot_partition_path, boot_partition_number),
-        constants::RESCUE_BEK_BOOT,
-        "",
-        false,
-    )?;
+    mount::mount(&boot_partition.device, constants::RESCUE_BEK_BOOT, "", false)?;
     Ok(())
 }
 
@@ -208,38 +213,47 @@ fn umount_boot_partition() -> Result<()> {
     Ok(())
 }
 
+// The plaintext pass phrase must never be left behind. The teardown removes it if ALAR stops before it got used.
 fn create_pass_phrase_file(phrase: &str) -> Result<()> {
+    teardown::register(Step::RemoveFile(
+        constants::RESCUE_TMP_LINUX_PASS_PHRASE_FILE_NAME.to_string(),
+    ));
     fs::write(constants::RESCUE_TMP_LINUX_PASS_PHRASE_FILE_NAME, phrase)?;
     Ok(())
 }
 
+fn remove_pass_phrase_file() -> Result<()> {
+    fs::remove_file(constants::RESCUE_TMP_LINUX_PASS_PHRASE_FILE_NAME)?;
+    teardown::forget(&Step::RemoveFile(
+        constants::RESCUE_TMP_LINUX_PASS_PHRASE_FILE_NAME.to_string(),
+    ));
+    Ok(())
+}
+
 fn enable_encrypted_partition(
     cli_info: &mut CliInfo,
     partitions: &[distro::PartInfo],
 ) -> Result<()> {
-    let partition_path = helper::get_recovery_disk_path(cli_info);
-    let root_partiton_number = find_root_partition_number(partitions);
+    let root_partition = find_root_partition(partitions);
 
     let command: String = if cli_info.ade_password.is_empty() {
         // we verified earlier that the BEK does exists and is readable
         mount_bek_volume()?;
-        mount_boot_partition(cli_info, partitions)?;
+        mount_boot_partition(partitions)?;
         format!(
-            "cryptsetup luksOpen --key-file {} --header {}/luks/osluksheader {}{} rescueencrypt",
+            "cryptsetup luksOpen --key-file {} --header {}/luks/osluksheader {} rescueencrypt",
             constants::RESCUE_BEK_LINUX_PASS_PHRASE_FILE_NAME,
             constants::RESCUE_BEK_BOOT,
-            partition_path,
-            root_partiton_number
+            root_partition.device
         )
     } else {
         create_pass_phrase_file(&cli_info.ade_password)?;
-        mount_boot_partition(cli_info, partitions)?;
+        mount_boot_partition(partitions)?;
         format!(
-            "cryptsetup luksOpen --key-file {} --header {}/luks/osluksheader {}{} rescueencrypt",
+            "cryptsetup luksOpen --key-file {} --header {}/luks/osluksheader {} rescueencrypt",
             constants::RESCUE_TMP_LINUX_PASS_PHRASE_FILE_NAME,
             constants::RESCUE_BEK_BOOT,
-            partition_path,
-            root_partiton_number
+            root_partition.device
         )
     };
 
@@ -248,13 +262,10 @@ fn enable_encrypted_partition(
             debug!("luksopen status: {}", &status);
             if status.success() {
                 debug!("luksopen success");
+                teardown::register(Step::CloseCrypt("rescueencrypt".to_string()));
             } else {
                 debug!("luksopen failed");
-                if cli_info.ade_password.is_empty() {
-                    umount_bek_volume()?;
-                }
-                umount_boot_partition()?;
-                close_rescueencrypt()?;
+                // The BEK volume, the boot partition and the pass phrase file are cleaned up by the teardown
                 telemetry::send_envelope(&telemetry::create_exception_envelope(telemetry::SeverityLevel::Error,
                     "ALAR EXCEPTION",
                      "Enabeling the encrypted device isn't possible.",
@@ -262,15 +273,10 @@ fn enable_encrypted_partition(
                      cli_info,
                      &distro::Distro::default(),
                 )).ok();
-                error!("Error: Enabeling the encrypted device isn't possible. Please verify that the passphrase is correct. ALAR needs to stop.");
-                process::exit(1);
+                return Err(anyhow!("Enabeling the encrypted device isn't possible. Please verify that the passphrase is correct."));
             }
         }
         Err(e) => {
-            umount_bek_volume()?;
-            umount_boot_partition()?;
-            fs::remove_file(constants::RESCUE_TMP_LINUX_PASS_PHRASE_FILE_NAME)?;
-            error!("Error: Enabeling the encrypted device isn't possible. ALAR needs to stop. Error detail is: {e}");
             telemetry::send_envelope(&telemetry::create_exception_envelope(telemetry::SeverityLevel::Error,
                 "ALAR EXCEPTION",
                  "Enabeling the encrypted device isn't possible.",
@@ -278,7 +284,7 @@ fn enable_encrypted_partition(
                  cli_info,
                  &distro::Distro::default(),
             )).ok();
-            process::exit(1);
+            return Err(anyhow!("Enabeling the encrypted device isn't possible. Error detail is: {e}"));
         }
     }
     umount_boot_partition()?;
@@ -287,75 +293,100 @@ fn enable_encrypted_partition(
     } else {
         // for security reasons we have to clear the ADE password
         cli_info.clear_password();
-        fs::remove_file(constants::RESCUE_TMP_LINUX_PASS_PHRASE_FILE_NAME)?;
+        remove_pass_phrase_file()?;
     }
 
     Ok(())
 }
 
-pub(crate) fn ade_importvg() -> Result<()> {
+// A LUKS container on the whole disk carries its header itself. It gets opened under the same name as an ADE
+// partition, thus everything after the unlock is the same for both.
+pub(crate) fn open_whole_disk_luks(cli_info: &mut CliInfo, device: &str) -> Result<()> {
+    let key_file = if cli_info.ade_password.is_empty() {
+        mount_bek_volume()?;
+        constants::RESCUE_BEK_LINUX_PASS_PHRASE_FILE_NAME
+    } else {
+        create_pass_phrase_file(&cli_info.ade_password)?;
+        constants::RESCUE_TMP_LINUX_PASS_PHRASE_FILE_NAME
+    };
+
+    let status = process::Command::new("cryptsetup")
+        .args(["luksOpen", "--key-file", key_file, device, "rescueencrypt"])
+        .status();
+    if cli_info.ade_password.is_empty() {
+        umount_bek_volume()?;
+    } else {
+        // for security reasons we have to clear the ADE password
+        cli_info.clear_password();
+        remove_pass_phrase_file()?;
+    }
+
+    match status {
+        Ok(status) if status.success() => {
+            teardown::register(Step::CloseCrypt("rescueencrypt".to_string()));
+            Ok(())
+        }
+        Ok(status) => Err(anyhow!(
+            "cryptsetup luksOpen of {device} returned {status}. Please verify that the passphrase is correct."
+        )),
+        Err(e) => Err(anyhow!("cryptsetup luksOpen of {device} isn't possible: {e}")),
+    }
+}
+
+pub(crate) fn ade_importvg(cli_info: &CliInfo) -> Result<lvm::VolumeGroup> {
     debug!("Inside ade_importvg");
 
-    // Does the recover VM use LVM as well?
-    if Path::new("/dev/rootvg").is_dir() {
-        info!("Importing the rescuevg");
+    lvm::allow_device(constants::ADE_OSENCRYPT_PATH)?;
+    let volume_group =
+        lvm::volume_group_of(constants::ADE_OSENCRYPT_PATH, &helper::get_recovery_disk_paths(cli_info)?, false)?;
+    lvm::verify_complete(&volume_group, cli_info.allow_partial_vg)?;
+
+    // Does the VG collide with one of the repair VM?
+    if let Some(clone_name) = &volume_group.import.clone_name {
+        mount::verify_renames_undoable(&volume_group)?;
+        info!("Importing the VG {} as {clone_name}", volume_group.name);
 
         let vgimportclone = format!(
-            "vgimportclone -n rescuevg {}; vgchange -ay rescuevg;vgscan --mknodes",
+            "vgimportclone -n {clone_name} {}; vgchange -ay {clone_name};vgscan --mknodes",
             constants::ADE_OSENCRYPT_PATH
         );
 
         helper::run_cmd(&vgimportclone).inspect_err(|open_error| {
             error!("Failed to import the VG: {open_error}");
         })?;
-
-        ade_rename_rootvg()?;
+        if volume_group.import.keeps_clone_name {
+            // Registered first, thus it is undone after the deactivation and before the container is closed
+            teardown::register(Step::RestoreVgName {
+                from: clone_name.clone(),
+                to: volume_group.name.clone(),
+            });
+            teardown::register(Step::DeactivateVg(clone_name.clone()));
+        } else {
+            teardown::register(Step::DeactivateVg(clone_name.clone()));
+            ade_rename_vgs(&volume_group, clone_name, cli_info)?;
+        }
     } else {
-        let command = "vgchange -ay rootvg;vgscan --mknodes";
-        helper::run_cmd(command).inspect_err(|open_error| {
-            error!("Failed to activate the rootvg VG : {open_error}");
+        let command = format!("vgchange -ay {};vgscan --mknodes", volume_group.name);
+        helper::run_cmd(&command).inspect_err(|open_error| {
+            error!("Failed to activate the VG {} : {open_error}", volume_group.name);
         })?;
+        teardown::register(Step::DeactivateVg(volume_group.name.clone()));
     };
 
-    Ok(())
-}
-
-pub(crate) fn ade_rename_rootvg() -> Result<()> {
-    debug!("Renaming the rootvg to oldvg and the rescuevg to rootvg");
-    let command = "vgrename rootvg oldvg; vgrename rescuevg rootvg";
-    helper::run_cmd(command).inspect_err(|open_error| {
-        error!("Failed to rename the ADE VG: {open_error}");
-    })?;
-
-    Ok(())
+    Ok(volume_group)
 }
 
-pub(crate) fn ade_lvm_cleanup() -> Result<()> {
-    let command = if Path::new("/dev/oldvg").is_dir() {
-        "vgchange -an rootvg; cryptsetup close rescueencrypt;vgrename oldvg rootvg"
-    } else {
-        "vgchange -an rootvg; cryptsetup close rescueencrypt"
-    };
-
-    helper::run_cmd(command).inspect_err(|open_error| {
-        error!("Failed to cleanup the ADE VG: {open_error}");
+// The VGs of the repair VM get their names back after the encrypted device is closed and the recovery disk is detached
+pub(crate) fn ade_rename_vgs(volume_group: &lvm::VolumeGroup, clone_name: &str, cli_info: &CliInfo) -> Result<()> {
+    mount::rename_repair_vm_vgs(volume_group, &helper::get_recovery_disk_path(cli_info)?).inspect_err(|open_error| {
+        error!("Failed to rename the VG of the repair VM: {open_error}");
     })?;
 
-    Ok(())
-}
-
-pub(crate) fn close_rescueencrypt() -> Result<()> {
-    // Get out of constants::RESCUE_ROOT, otherwise umount isn't possible for RESCUE_ROOT
-    match env::set_current_dir("/") {
-        Ok(_) => {}
-        Err(e) => println!("Error in set current dir : {e}"),
-    }
-
-    //mount::umount(constants::RESCUE_ROOT, true)?;
-    let command = "cryptsetup close rescueencrypt";
-    helper::run_cmd(command).inspect_err(|open_error| {
-        error!("Failed to close rescueencrypt: {open_error}");
+    helper::run_cmd(&format!("vgrename {clone_name} {}", volume_group.name)).inspect_err(|open_error| {
+        error!("Failed to rename the ADE VG: {open_error}");
     })?;
+    teardown::forget(&Step::DeactivateVg(clone_name.to_string()));
+    teardown::register(Step::DeactivateVg(volume_group.name.clone()));
 
     Ok(())
 }
@@ -0,0 +1,358 @@
+use anyhow::{Context, Result, anyhow};
+use log::debug;
+use std::fs;
+use std::path::Path;
+
+// The partitions of a disk as the kernel knows them, taken from sysfs. Device paths are never constructed
+// from the disk name, as the naming differs between sd*, vd*, nvme*n*p*, loop*p* and device-mapper disks.
+
+pub(crate) const SYS_CLASS_BLOCK: &str = "/sys/class/block";
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct KernelPartition {
+    pub(crate) number: u32,
+    // The device node, i.e. /dev/sdc1, /dev/nvme1n1p15 or /dev/mapper/mpatha1
+    pub(crate) device: String,
+    // major:minor
+    pub(crate) dev: String,
+    pub(crate) sysfs_path: String,
+}
+
+fn read_trimmed(path: &Path) -> Result<String> {
+    Ok(fs::read_to_string(path)
+        .with_context(|| format!("{} can not be read", path.display()))?
+        .trim()
+        .to_string())
+}
+
+// The name of the device node as udev creates it, from the DEVNAME line of the uevent file
+fn devname(sysfs_path: &Path) -> Option<String> {
+    fs::read_to_string(sysfs_path.join("uevent"))
+        .ok()?
+        .lines()
+        .find_map(|line| line.strip_prefix("DEVNAME=").map(str::to_string))
+}
+
+// The kernel name of a disk, i.e. sdc for /dev/disk/azure/scsi1/lun0. A partition is refused.
+pub(crate) fn disk_name(disk_path: &str) -> Result<String> {
+    disk_name_in(Path::new(SYS_CLASS_BLOCK), disk_path)
+}
+
+pub(crate) fn disk_name_in(sys_class_block: &Path, disk_path: &str) -> Result<String> {
+    let device = fs::canonicalize(disk_path).with_context(|| format!("{disk_path} can not be resolved"))?;
+    let name = device
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .ok_or_else(|| anyhow!("{disk_path} is not a device"))?;
+    let sysfs_path = sys_class_block.join(&name);
+    if !sysfs_path.exists() {
+        return Err(anyhow!("{disk_path} is not a block device known to the kernel"));
+    }
+    if sysfs_path.join("partition").exists() {
+        return Err(anyhow!("{disk_path} is a partition, not a disk"));
+    }
+    Ok(name)
+}
+
+fn kernel_partition(sysfs_path: &Path, number: u32, device: String) -> Result<KernelPartition> {
+    Ok(KernelPartition {
+        number,
+        device,
+        dev: read_trimmed(&sysfs_path.join("dev"))?,
+        sysfs_path: sysfs_path.display().to_string(),
+    })
+}
+
+// Regular disks list their partitions as subdirectories with a partition file. The partitions of a device-mapper disk,
+// i.e. multipath, are device-mapper devices themselves. They show up as holders with a dm UUID of partN-<uuid of the disk>.
+fn partitions_in(sys_class_block: &Path, disk: &str) -> Result<Vec<KernelPartition>> {
+    let disk_path = sys_class_block.join(disk);
+    let mut partitions = Vec::new();
+
+    for entry in fs::read_dir(&disk_path).with_context(|| format!("{} can not be read", disk_path.display()))? {
+        let path = entry?.path();
+        let partition_file = path.join("partition");
+        if !partition_file.is_file() {
+            continue;
+        }
+        let name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+        let number = read_trimmed(&partition_file)?.parse()?;
+        let device = format!("/dev/{}", devname(&path).unwrap_or_else(|| name.clone()));
+        partitions.push(kernel_partition(&sys_class_block.join(&name), number, device)?);
+    }
+
+    if partitions.is_empty() && disk_path.join("dm").is_dir() {
+        for entry in fs::read_dir(disk_path.join("holders"))? {
+            let holder = sys_class_block.join(entry?.file_name());
+            let Ok(dm_uuid) = read_trimmed(&holder.join("dm/uuid")) else {
+                continue;
+            };
+            let Some(number) = dm_uuid
+                .strip_prefix("part")
+                .and_then(|rest| rest.split('-').next())
+                .and_then(|number| number.parse().ok())
+            else {
+                continue;
+            };
+            let device = format!("/dev/mapper/{}", read_trimmed(&holder.join("dm/name"))?);
+            partitions.push(kernel_partition(&holder, number, device)?);
+        }
+    }
+
+    partitions.sort_by_key(|partition| partition.number);
+    debug!("Partitions of {disk} known to the kernel: {partitions:#?}");
+    Ok(partitions)
+}
+
+pub(crate) fn partitions(disk_path: &str) -> Result<Vec<KernelPartition>> {
+    partitions_in(Path::new(SYS_CLASS_BLOCK), &disk_name(disk_path)?)
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name().unwrap_or_default().to_string_lossy().to_string()
+}
+
+fn dm_uuid(sysfs_path: &Path) -> Option<String> {
+    read_trimmed(&sysfs_path.join("dm/uuid")).ok()
+}
+
+fn slaves(sysfs_path: &Path) -> Vec<String> {
+    let Ok(entries) = fs::read_dir(sysfs_path.join("slaves")) else {
+        return Vec::new();
+    };
+    let mut slaves: Vec<String> = entries.flatten().map(|entry| file_name(&entry.path())).collect();
+    slaves.sort();
+    slaves
+}
+
+// The disk a partition belongs to. A partition directory sits below the one of its disk, a kpartx partition
+// of a multipath disk has the disk as its only slave. Any other device is returned as it is.
+fn parent_disk_in(sys_class_block: &Path, name: &str) -> Result<String> {
+    let sysfs_path = sys_class_block.join(name);
+    if !sysfs_path.exists() {
+        return Err(anyhow!("{name} is not a block device known to the kernel"));
+    }
+    if sysfs_path.join("partition").exists() {
+        let device = fs::canonicalize(&sysfs_path)?;
+        return device
+            .parent()
+            .map(file_name)
+            .ok_or_else(|| anyhow!("The disk of the partition {name} can not be determined"));
+    }
+    if dm_uuid(&sysfs_path).is_some_and(|uuid| uuid.starts_with("part"))
+        && let [disk] = slaves(&sysfs_path).as_slice()
+    {
+        return Ok(disk.clone());
+    }
+    Ok(name.to_string())
+}
+
+// Resolves a disk, a partition or any /dev/disk/by-* link to the device node of the disk
+pub(crate) fn parent_disk(path: &str) -> Result<String> {
+    let device = fs::canonicalize(path).with_context(|| format!("{path} can not be resolved"))?;
+    let disk = parent_disk_in(Path::new(SYS_CLASS_BLOCK), &file_name(&device))?;
+    Ok(format!("/dev/{disk}"))
+}
+
+// The disks a device is stored on, following partitions and device-mapper devices like LVM and dm-crypt down to the disks.
+// A multipath device counts as a disk itself.
+fn backing_disks_in(sys_class_block: &Path, name: &str) -> Result<Vec<String>> {
+    let disk = parent_disk_in(sys_class_block, name)?;
+    let sysfs_path = sys_class_block.join(&disk);
+    let slaves = slaves(&sysfs_path);
+    if slaves.is_empty() || dm_uuid(&sysfs_path).is_some_and(|uuid| uuid.starts_with("mpath-")) {
+        return Ok(vec![disk]);
+    }
+    let mut disks = Vec::new();
+    for slave in slaves {
+        for disk in backing_disks_in(sys_class_block, &slave)? {
+            if !disks.contains(&disk) {
+                disks.push(disk);
+            }
+        }
+    }
+    Ok(disks)
+}
+
+pub(crate) fn backing_disks(path: &str) -> Result<Vec<String>> {
+    let device = fs::canonicalize(path).with_context(|| format!("{path} can not be resolved"))?;
+    backing_disks_in(Path::new(SYS_CLASS_BLOCK), &file_name(&device))
+}
+
+// Devices that never hold a disk to be recovered
+const IGNORED_DEVICES: [&str; 6] = ["loop", "ram", "zram", "sr", "fd", "md"];
+
+// The kernel names of all disks. The paths of a multipath disk are left out, the multipath device is listed instead.
+fn disks_in(sys_class_block: &Path) -> Result<Vec<String>> {
+    let mut disks = Vec::new();
+    for entry in fs::read_dir(sys_class_block).with_context(|| format!("{} can not be read", sys_class_block.display()))? {
+        let path = entry?.path();
+        let name = file_name(&path);
+        if path.join("partition").exists() || IGNORED_DEVICES.iter().any(|prefix| name.starts_with(prefix)) {
+            continue;
+        }
+        if path.join("dm").is_dir() && !dm_uuid(&path).is_some_and(|uuid| uuid.starts_with("mpath-")) {
+            continue;
+        }
+        let is_multipath_member = fs::read_dir(path.join("holders")).is_ok_and(|mut holders| {
+            holders.any(|holder| {
+                holder.is_ok_and(|holder| {
+                    dm_uuid(&sys_class_block.join(holder.file_name())).is_some_and(|uuid| uuid.starts_with("mpath-"))
+                })
+            })
+        });
+        if is_multipath_member || size_in(sys_class_block, &name).unwrap_or(0) == 0 {
+            continue;
+        }
+        disks.push(name);
+    }
+    disks.sort();
+    Ok(disks)
+}
+
+pub(crate) fn disks() -> Result<Vec<String>> {
+    disks_in(Path::new(SYS_CLASS_BLOCK))
+}
+
+// The size file counts 512 byte sectors, whatever the logical sector size of the disk is
+fn size_in(sys_class_block: &Path, name: &str) -> Result<u64> {
+    Ok(read_trimmed(&sys_class_block.join(name).join("size"))?.parse::<u64>()? * 512)
+}
+
+pub(crate) fn size(name: &str) -> Result<u64> {
+    size_in(Path::new(SYS_CLASS_BLOCK), name)
+}
+
+// The device directory of a multipath disk is the one of its first path
+fn device_dir(sys_class_block: &Path, name: &str) -> std::path::PathBuf {
+    let sysfs_path = sys_class_block.join(name);
+    match slaves(&sysfs_path).first() {
+        Some(slave) if sysfs_path.join("dm").is_dir() => sys_class_block.join(slave).join("device"),
+        _ => sysfs_path.join("device"),
+    }
+}
+
+pub(crate) fn model(name: &str) -> Option<String> {
+    read_trimmed(&device_dir(Path::new(SYS_CLASS_BLOCK), name).join("model"))
+        .ok()
+        .filter(|model| !model.is_empty())
+}
+
+// NVMe disks have a serial file. SCSI disks report it in the VPD page 0x80, after a header of four bytes.
+fn serial_in(sys_class_block: &Path, name: &str) -> Option<String> {
+    let device = device_dir(sys_class_block, name);
+    if let Ok(serial) = read_trimmed(&device.join("serial")) {
+        return Some(serial).filter(|serial| !serial.is_empty());
+    }
+    let page = fs::read(device.join("vpd_pg80")).ok()?;
+    let serial = String::from_utf8_lossy(page.get(4..)?).trim_matches(|c: char| c.is_whitespace() || c == '\0').to_string();
+    Some(serial).filter(|serial| !serial.is_empty())
+}
+
+pub(crate) fn serial(name: &str) -> Option<String> {
+    serial_in(Path::new(SYS_CLASS_BLOCK), name)
+}
+
+// The LUN of a SCSI disk is the last part of its address host:channel:target:lun
+pub(crate) fn scsi_lun(name: &str) -> Option<u32> {
+    let device = fs::canonicalize(device_dir(Path::new(SYS_CLASS_BLOCK), name)).ok()?;
+    let address = file_name(&device);
+    let parts: Vec<&str> = address.split(':').collect();
+    match parts.as_slice() {
+        [_, _, _, lun] => lun.parse().ok(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(path: &Path, content: &str) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+
+    #[test]
+    fn partitions_from_sysfs() {
+        let root = std::env::temp_dir().join(format!("alar-blockdev-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+
+        // An NVMe disk, its partition names can't be derived by appending the number
+        write(&root.join("nvme1n1/dev"), "259:0\n");
+        for (name, number, dev) in [("nvme1n1p15", "15", "259:3"), ("nvme1n1p1", "1", "259:1")] {
+            write(&root.join(format!("nvme1n1/{name}/partition")), &format!("{number}\n"));
+            write(&root.join(format!("nvme1n1/{name}/dev")), &format!("{dev}\n"));
+            write(&root.join(format!("nvme1n1/{name}/uevent")), &format!("MAJOR=259\nDEVNAME={name}\n"));
+            // The same directory is reachable from the class directory
+            write(&root.join(format!("{name}/dev")), &format!("{dev}\n"));
+        }
+        let partitions = partitions_in(&root, "nvme1n1").unwrap();
+        assert_eq!(
+            partitions,
+            vec![
+                KernelPartition {
+                    number: 1,
+                    device: "/dev/nvme1n1p1".to_string(),
+                    dev: "259:1".to_string(),
+                    sysfs_path: root.join("nvme1n1p1").display().to_string(),
+                },
+                KernelPartition {
+                    number: 15,
+                    device: "/dev/nvme1n1p15".to_string(),
+                    dev: "259:3".to_string(),
+                    sysfs_path: root.join("nvme1n1p15").display().to_string(),
+                },
+            ]
+        );
+
+        // A multipath disk with a kpartx partition
+        write(&root.join("dm-0/dm/name"), "mpatha\n");
+        write(&root.join("dm-0/holders/dm-1"), "");
+        write(&root.join("dm-1/dm/uuid"), "part2-mpath-3600224800000000000000000000000\n");
+        write(&root.join("dm-1/dm/name"), "mpatha2\n");
+        write(&root.join("dm-1/dev"), "253:1\n");
+        let partitions = partitions_in(&root, "dm-0").unwrap();
+        assert_eq!(partitions.len(), 1);
+        assert_eq!(partitions[0].number, 2);
+        assert_eq!(partitions[0].device, "/dev/mapper/mpatha2");
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn disks_and_their_parents() {
+        let root = std::env::temp_dir().join(format!("alar-blockdev-disks-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let devices = root.join("devices");
+        let class = root.join("class");
+        fs::create_dir_all(&class).unwrap();
+        let link = |name: &str, target: &Path| std::os::unix::fs::symlink(target, class.join(name)).unwrap();
+
+        // The OS disk sda with the root VG on sda2, the recovery disk sdc and a DVD drive
+        write(&devices.join("sda/size"), "62914560\n");
+        write(&devices.join("sda/sda2/partition"), "2\n");
+        write(&devices.join("sda/sda2/holders/dm-0"), "");
+        write(&devices.join("sdc/size"), "67108864\n");
+        write(&devices.join("sdc/device/vpd_pg80"), "\0\u{1}\0\u{c}60022480abcd");
+        write(&devices.join("sdc/device/model"), "Virtual Disk    \n");
+        write(&devices.join("sr0/size"), "2048\n");
+        write(&devices.join("dm-0/size"), "20971520\n");
+        write(&devices.join("dm-0/dm/uuid"), "LVM-abcdef\n");
+        write(&devices.join("dm-0/slaves/sda2"), "");
+        link("sda", &devices.join("sda"));
+        link("sda2", &devices.join("sda/sda2"));
+        link("sdc", &devices.join("sdc"));
+        link("sr0", &devices.join("sr0"));
+        link("dm-0", &devices.join("dm-0"));
+
+        assert_eq!(disks_in(&class).unwrap(), vec!["sda".to_string(), "sdc".to_string()]);
+        assert_eq!(parent_disk_in(&class, "sda2").unwrap(), "sda");
+        assert_eq!(parent_disk_in(&class, "sdc").unwrap(), "sdc");
+        assert_eq!(backing_disks_in(&class, "dm-0").unwrap(), vec!["sda".to_string()]);
+        assert_eq!(size_in(&class, "sdc").unwrap(), 32 * 1024 * 1024 * 1024);
+        assert_eq!(serial_in(&class, "sdc").as_deref(), Some("60022480abcd"));
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
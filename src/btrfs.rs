@@ -0,0 +1,196 @@
+use crate::helper;
+use crate::mount;
+use anyhow::{Context, Result, anyhow};
+use log::{debug, info};
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+
+// A btrfs root filesystem is not necessarily the top-level subvolume. SLES and openSUSE boot the default subvolume,
+// i.e. @/.snapshots/1/snapshot, and mount further subvolumes like @/var or @/home as listed in their fstab.
+
+// The top-level subvolume always has the ID 5
+const TOP_LEVEL_ID: u64 = 5;
+// Snapper keeps its snapshots below this subvolume, each with an info.xml describing it
+const SNAPPER_DIRECTORY: &str = ".snapshots";
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub(crate) struct Subvolume {
+    pub(crate) id: u64,
+    pub(crate) path: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub(crate) struct Snapshot {
+    pub(crate) number: u32,
+    // single, pre or post
+    pub(crate) kind: String,
+    pub(crate) date: String,
+    pub(crate) description: String,
+    pub(crate) is_default: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub(crate) struct BtrfsLayout {
+    pub(crate) default_subvolume: Subvolume,
+    pub(crate) subvolumes: Vec<Subvolume>,
+    pub(crate) snapshots: Vec<Snapshot>,
+}
+
+impl BtrfsLayout {
+    // The mount option selecting the root of the system
+    pub(crate) fn root_option(&self) -> String {
+        format!("subvolid={}", self.default_subvolume.id)
+    }
+
+    // The newest snapshot older than the one booted by default. If the system doesn't boot a snapshot, the newest one.
+    pub(crate) fn rollback_candidate(&self) -> Option<&Snapshot> {
+        let current = self.snapshots.iter().find(|snapshot| snapshot.is_default).map(|snapshot| snapshot.number);
+        self.snapshots
+            .iter()
+            .filter(|snapshot| current.is_none_or(|current| snapshot.number < current))
+            .max_by_key(|snapshot| snapshot.number)
+    }
+}
+
+// Parses a line of 'btrfs subvolume list' or 'btrfs subvolume get-default': ID 258 gen 9 top level 5 path @/var
+fn parse_subvolume(line: &str) -> Option<Subvolume> {
+    let id = line.strip_prefix("ID ")?.split_whitespace().next()?.parse().ok()?;
+    let path = line.split_once(" path ").map(|(_, path)| path.trim().to_string())?;
+    Some(Subvolume { id, path })
+}
+
+// The top-level subvolume is reported without a path
+fn parse_default(output: &str) -> Option<Subvolume> {
+    let line = output.trim();
+    parse_subvolume(line).or_else(|| {
+        line.starts_with(&format!("ID {TOP_LEVEL_ID} "))
+            .then(|| Subvolume { id: TOP_LEVEL_ID, path: String::new() })
+    })
+}
+
+fn xml_value(xml: &str, tag: &str) -> String {
+    xml.split_once(&format!("<{tag}>"))
+        .and_then(|(_, rest)| rest.split_once(&format!("</{tag}>")))
+        .map(|(value, _)| value.trim().to_string())
+        .unwrap_or_default()
+}
+
+fn parse_snapshot_info(xml: &str) -> Option<Snapshot> {
+    Some(Snapshot {
+        number: xml_value(xml, "num").parse().ok()?,
+        kind: xml_value(xml, "type"),
+        date: xml_value(xml, "date"),
+        description: xml_value(xml, "description"),
+        is_default: false,
+    })
+}
+
+// The snapshot number of a subvolume path like @/.snapshots/12/snapshot
+fn snapshot_number(path: &str) -> Option<u32> {
+    let rest = path.split_once(&format!("{SNAPPER_DIRECTORY}/"))?.1;
+    let (number, name) = rest.split_once('/')?;
+    (name == "snapshot").then(|| number.parse().ok()).flatten()
+}
+
+// Reads the snapshots snapper recorded below the top-level subvolume mounted at top_level
+fn read_snapshots(top_level: &Path, subvolumes: &[Subvolume], default: &Subvolume) -> Vec<Snapshot> {
+    let mut snapshots: Vec<Snapshot> = subvolumes
+        .iter()
+        .filter(|subvolume| snapshot_number(&subvolume.path).is_some())
+        .filter_map(|subvolume| {
+            let info = top_level.join(&subvolume.path).parent()?.join("info.xml");
+            let mut snapshot = parse_snapshot_info(&fs::read_to_string(info).ok()?)?;
+            snapshot.is_default = subvolume.id == default.id;
+            Some(snapshot)
+        })
+        .collect();
+    snapshots.sort_by_key(|snapshot| snapshot.number);
+    snapshots
+}
+
+// Mounts the top-level subvolume of the device at mount_path and collects the subvolumes and snapshots.
+// In dry-run mode the filesystem is mounted read-only and without replaying its log tree.
+pub(crate) fn inspect(device: &str, mount_path: &str, is_read_only: bool) -> Result<BtrfsLayout> {
+    let options = format!("subvolid={TOP_LEVEL_ID}");
+    if is_read_only {
+        mount::mount_readonly(device, mount_path, &format!("{options},nologreplay"), false)?;
+    } else {
+        mount::mount(device, mount_path, &options, false)?;
+    }
+    let layout = read_layout(mount_path);
+    mount::umount(mount_path, false)?;
+    let layout = layout?;
+
+    info!(
+        "btrfs on {device}: the default subvolume is {} (ID {})",
+        if layout.default_subvolume.path.is_empty() { "the top-level one" } else { &layout.default_subvolume.path },
+        layout.default_subvolume.id
+    );
+    for snapshot in &layout.snapshots {
+        info!(
+            "  snapshot {} {} {} {}{}",
+            snapshot.number,
+            snapshot.kind,
+            snapshot.date,
+            snapshot.description,
+            if snapshot.is_default { " (default)" } else { "" }
+        );
+    }
+    Ok(layout)
+}
+
+fn read_layout(mount_path: &str) -> Result<BtrfsLayout> {
+    let default_output = helper::run_fun(&format!("btrfs subvolume get-default {mount_path}"))?;
+    let default_subvolume = parse_default(&default_output)
+        .ok_or_else(|| anyhow!("The default subvolume can not be determined from '{}'", default_output.trim()))?;
+    let subvolumes: Vec<Subvolume> = helper::run_fun(&format!("btrfs subvolume list {mount_path}"))
+        .context("The btrfs subvolumes can not be listed")?
+        .lines()
+        .filter_map(parse_subvolume)
+        .collect();
+    debug!("btrfs subvolumes: {subvolumes:#?}");
+
+    Ok(BtrfsLayout {
+        snapshots: read_snapshots(Path::new(mount_path), &subvolumes, &default_subvolume),
+        default_subvolume,
+        subvolumes,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn subvolumes_and_snapshots() {
+        assert_eq!(
+            parse_subvolume("ID 268 gen 1402 top level 267 path @/.snapshots/1/snapshot"),
+            Some(Subvolume {
+                id: 268,
+                path: "@/.snapshots/1/snapshot".to_string()
+            })
+        );
+        assert_eq!(parse_default("ID 5 (FS_TREE)\n").map(|subvolume| subvolume.id), Some(5));
+        assert_eq!(snapshot_number("@/.snapshots/12/snapshot"), Some(12));
+        assert_eq!(snapshot_number("@/.snapshots"), None);
+
+        let info = "<?xml version=\"1.0\"?>\n<snapshot>\n  <type>post</type>\n  <num>4</num>\n  <date>2026-05-02 10:11:12</date>\n  <description>zypp(zypper)</description>\n</snapshot>\n";
+        let snapshot = parse_snapshot_info(info).unwrap();
+        assert_eq!((snapshot.number, snapshot.kind.as_str()), (4, "post"));
+        assert_eq!(snapshot.description, "zypp(zypper)");
+
+        let snapshot = |number: u32, is_default: bool| Snapshot {
+            number,
+            is_default,
+            ..Snapshot::default()
+        };
+        let mut layout = BtrfsLayout {
+            snapshots: vec![snapshot(1, false), snapshot(3, false), snapshot(4, true), snapshot(5, false)],
+            ..BtrfsLayout::default()
+        };
+        assert_eq!(layout.rollback_candidate().map(|snapshot| snapshot.number), Some(3));
+        layout.snapshots[2].is_default = false;
+        assert_eq!(layout.rollback_candidate().map(|snapshot| snapshot.number), Some(5));
+    }
+}
@@ -1,7 +1,13 @@
+use crate::config::{ActionSourceConfig, Config};
+use crate::constants;
 use crate::helper;
+use crate::telemetry::{self, TelemetryMode};
 use anyhow::Result;
-use clap::{ArgAction, Parser};
+use clap::{ArgAction, Parser, Subcommand, ValueEnum};
 use log::debug;
+use serde::Deserialize;
+use std::env;
+use std::time::{Duration, Instant};
 
 // The Initiator type is used to determine the context in which ALAR is running
 // This information is required to be used later in a telemetry module TODO
@@ -12,14 +18,99 @@ pub(crate) enum Initiator {
     #[default]
     Cli,
 }
+// The Mode defines what ALAR is asked to do. By default the actions are executed against the recovery disk
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub(crate) enum Mode {
+    #[default]
+    Recover,
+    Inspect(OutputFormat),
+    ListActions,
+    // Restores the metadata of a damaged VG, see lvm_metadata.rs
+    LvmMetadata { candidate: Option<usize>, is_confirmed: bool },
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, ValueEnum)]
+pub(crate) enum OutputFormat {
+    #[default]
+    Json,
+}
+
+// What to do with the remaining actions if one of them fails
+#[derive(Debug, Default, Clone, Copy, PartialEq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum ActionFailurePolicy {
+    Stop,
+    #[default]
+    Continue,
+}
+
+// How the filesystems of the recovery disk are checked before they get mounted
+#[derive(Debug, Default, Clone, Copy, PartialEq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum FsckPolicy {
+    // Repair the filesystem and stop ALAR if it can't be repaired
+    #[default]
+    Auto,
+    // Only report the state of the filesystem, nothing gets repaired
+    Check,
+    // Don't check the filesystem at all
+    Skip,
+}
+
+// Narrows down the disks that qualify as the recovery disk
+#[derive(Debug, Default, Clone, PartialEq)]
+pub(crate) struct DiskFilter {
+    pub(crate) lun: Option<u32>,
+    pub(crate) serial: Option<String>,
+    pub(crate) size: Option<DiskSize>,
+}
+
+impl DiskFilter {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.lun.is_none() && self.serial.is_none() && self.size.is_none()
+    }
+}
+
+// A disk size as passed over on the command line. The size of a disk is rounded to the given unit before it is compared.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct DiskSize {
+    pub(crate) value: u64,
+    pub(crate) unit: u64,
+}
+
+impl DiskSize {
+    pub(crate) fn matches(&self, bytes: u64) -> bool {
+        (bytes + self.unit / 2) / self.unit == self.value
+    }
+}
+
 #[derive(Default, Debug, Clone)]
 pub(crate) struct CliInfo {
     pub(crate) local_action_directory: String,
     pub(crate) actions: String,
     pub(crate) initiator: Initiator,
     pub(crate) custom_recover_disk: String,
+    pub(crate) disk_filter: DiskFilter,
+    pub(crate) additional_recover_disks: Vec<String>,
+    // The disks selected at the start of the run, see disk_select::select()
+    pub(crate) recovery_disk: String,
+    pub(crate) additional_recovery_disks: Vec<String>,
+    // Allows a VG to be used even if some of its PVs are missing
+    pub(crate) allow_partial_vg: bool,
+    // The snapper snapshot the action snapshot-rollback returns to
+    pub(crate) rollback_snapshot: Option<u32>,
+    // Allows the recovery disk to be one the running system depends on
+    pub(crate) i_know_what_i_am_doing: bool,
     pub(crate) ade_password: String,
     pub(crate) download_action_scripts: bool,
+    pub(crate) dry_run: bool,
+    pub(crate) mode: Mode,
+    pub(crate) on_action_failure: ActionFailurePolicy,
+    pub(crate) action_timeout: Option<Duration>,
+    pub(crate) run_timeout: Option<Duration>,
+    pub(crate) fsck_policy: FsckPolicy,
+    pub(crate) log_directory: String,
+    pub(crate) telemetry: TelemetryMode,
 }
 impl CliInfo {
     pub(crate) fn new() -> CliInfo {
@@ -42,60 +133,255 @@ impl CliInfo {
     about = r#"
 ALAR assists in recovering virtual machines from non-bootable states by executing one or more predefined actions.
 Once the VM is restored to a bootable and accessible state, administrators can continue with further recovery or maintenance operations.
-"#
+"#,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
 )]
 struct Cli {
+    #[command(subcommand)]
+    command: Option<Commands>,
+
     /// A required parameter that defines the action to be executed. Multiple actions can be separated by a comma
-    #[arg(index = 1, value_name = "ACTION")]
-    action: String,
+    #[arg(index = 1, value_name = "ACTION", required = true)]
+    action: Option<String>,
+
+    /// The configuration file to be used instead of /etc/alar/alar.toml. Options on the command line take precedence over it
+    #[arg(long = "config", value_name = "PATH", global = true)]
+    config: Option<String>,
 
     /// The directory in which custom actions are defined
-    #[arg(short = 'd', long = "directory", value_name = "DIR")]
+    #[arg(short = 'd', long = "directory", value_name = "DIR", global = true)]
     directory: Option<String>,
 
     /// Use this flag to download the action scripts from GIT instead of the builtin ones
-    #[arg(long = "download-action-scripts", action = ArgAction::SetTrue)]
+    #[arg(long = "download-action-scripts", action = ArgAction::SetTrue, global = true)]
     download_action_scripts: bool,
 
 
+    /// Don't send any telemetry data. The same is achieved with ALAR_TELEMETRY=off or the configuration file
+    #[arg(long = "no-telemetry", action = ArgAction::SetTrue, global = true)]
+    no_telemetry: bool,
+
     /// Selfhelp initiator flag
     #[arg(long = "selfhelp-initiator", alias = "SELFHELP", action = ArgAction::SetTrue)]
     selfhelp_initiator: bool,
 
 
     /// The path to the custom recovery disk
-    #[arg(long = "custom-recover-disk", value_name = "PATH")]
+    #[arg(long = "custom-recover-disk", value_name = "PATH", global = true)]
     custom_recover_disk: Option<String>,
 
+    /// A further disk holding PVs of the VG on the recovery disk. Can be given more than once
+    #[arg(long = "additional-recover-disk", value_name = "PATH", action = ArgAction::Append, global = true)]
+    additional_recover_disks: Vec<String>,
+
+    /// Use a VG of the recovery disk even if some of its PVs are missing. The LVs on the missing PVs are not available
+    #[arg(long = "allow-partial-vg", action = ArgAction::SetTrue, global = true)]
+    allow_partial_vg: bool,
+
+    /// Select the recovery disk attached at this LUN
+    #[arg(long = "lun", value_name = "N", global = true, conflicts_with = "custom_recover_disk")]
+    lun: Option<u32>,
+
+    /// Select the recovery disk with this serial number
+    #[arg(long = "disk-serial", value_name = "SERIAL", global = true, conflicts_with = "custom_recover_disk")]
+    disk_serial: Option<String>,
+
+    /// Select the recovery disk of this size, i.e. 64G or 1T. A number without a unit is taken as bytes
+    #[arg(long = "disk-size", value_name = "SIZE", value_parser = parse_disk_size, global = true, conflicts_with = "custom_recover_disk")]
+    disk_size: Option<DiskSize>,
+
+    /// Use the recovery disk even if the running system depends on it, i.e. if it holds / or /boot. This may destroy the repair VM
+    #[arg(long = "i-know-what-i-am-doing", action = ArgAction::SetTrue, global = true)]
+    i_know_what_i_am_doing: bool,
+
     /// The password to decrypt the ADE encrypted disk (base64-encoded)
-    #[arg(long = "ade-password", value_name = "PASSWORD")]
+    #[arg(long = "ade-password", value_name = "PASSWORD", global = true)]
     ade_password: Option<String>,
+
+    /// Run the disk detection read-only and print the recovery plan without changing anything
+    #[arg(long = "dry-run", action = ArgAction::SetTrue)]
+    dry_run: bool,
+
+    /// Whether the remaining actions are executed if an action fails. The exit code reflects the worst action result in any case
+    #[arg(long = "on-action-failure", value_enum)]
+    on_action_failure: Option<ActionFailurePolicy>,
+
+    /// The snapper snapshot the action 'snapshot-rollback' makes the default one. Without it the newest snapshot older than the current one is used
+    #[arg(long = "snapshot", value_name = "NUMBER")]
+    snapshot: Option<u32>,
+
+    /// The time limit for each action, i.e. 900, 30m or 1h. The action gets terminated if it takes longer
+    #[arg(long = "action-timeout", value_name = "DURATION", value_parser = parse_duration)]
+    action_timeout: Option<Duration>,
+
+    /// The time limit for the whole run. ALAR gets stopped and cleans up the repair VM if it takes longer
+    #[arg(long = "run-timeout", value_name = "DURATION", value_parser = parse_duration)]
+    run_timeout: Option<Duration>,
+
+    /// How the filesystems are checked before they get mounted. 'auto' repairs them, 'check' only reports their state
+    #[arg(long = "fsck-policy", value_enum)]
+    fsck_policy: Option<FsckPolicy>,
+
+    /// The directory the log of this run is written to
+    #[arg(long = "log-directory", value_name = "DIR")]
+    log_directory: Option<String>,
+}
+
+// Accepts a number of seconds, optionally followed by one of the units s, m or h. A deadline has to be computable from it.
+pub(crate) fn parse_duration(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    let (number, multiplier) = match value.chars().last() {
+        Some('s') => (&value[..value.len() - 1], 1),
+        Some('m') => (&value[..value.len() - 1], 60),
+        Some('h') => (&value[..value.len() - 1], 3600),
+        _ => (value, 1),
+    };
+    match number.parse::<u64>() {
+        Ok(0) => Err("the duration must be greater than zero".to_string()),
+        Ok(number) => number
+            .checked_mul(multiplier)
+            .map(Duration::from_secs)
+            .filter(|duration| Instant::now().checked_add(*duration).is_some())
+            .ok_or_else(|| format!("'{value}' is too long for a duration")),
+        Err(_) => Err(format!("'{value}' is not a valid duration, use i.e. 900, 30m or 1h")),
+    }
+}
+
+// Accepts a number of bytes, optionally followed by one of the binary units K, M, G or T. A trailing 'iB' or 'B' is allowed.
+pub(crate) fn parse_disk_size(value: &str) -> Result<DiskSize, String> {
+    let trimmed = value.trim();
+    let without_suffix = trimmed
+        .strip_suffix("iB")
+        .or_else(|| trimmed.strip_suffix('B'))
+        .unwrap_or(trimmed);
+    let (number, unit) = match without_suffix.chars().last().map(|unit| unit.to_ascii_uppercase()) {
+        Some('K') => (&without_suffix[..without_suffix.len() - 1], 1 << 10),
+        Some('M') => (&without_suffix[..without_suffix.len() - 1], 1 << 20),
+        Some('G') => (&without_suffix[..without_suffix.len() - 1], 1 << 30),
+        Some('T') => (&without_suffix[..without_suffix.len() - 1], 1 << 40),
+        _ => (without_suffix, 1),
+    };
+    match number.trim().parse::<u64>() {
+        Ok(0) => Err("the disk size must be greater than zero".to_string()),
+        Ok(value) => Ok(DiskSize { value, unit }),
+        Err(_) => Err(format!("'{trimmed}' is not a valid disk size, use i.e. 64G or 1T")),
+    }
+}
+
+#[derive(Debug, Subcommand)]
+enum Commands {
+    /// Detect the layout of the recovery disk read-only and print it without running any action
+    Inspect {
+        /// The output format
+        #[arg(long = "format", value_enum, default_value_t = OutputFormat::Json)]
+        format: OutputFormat,
+    },
+    /// List the available actions with their version and purpose. Builtin actions are always listed,
+    /// '--directory' and '--download-action-scripts' add the actions of those sources
+    ListActions,
+    /// Scan the metadata areas of the PVs on the recovery disks for earlier copies of the VG metadata and restore
+    /// the chosen one like vgcfgrestore does. Nothing is changed without a confirmation
+    LvmMetadata {
+        /// The number of the listed copy to be restored
+        #[arg(long = "candidate", value_name = "NUMBER")]
+        candidate: Option<usize>,
+        /// Restore the copy without asking for a confirmation
+        #[arg(long = "yes", action = ArgAction::SetTrue)]
+        yes: bool,
+    },
 }
 
 pub(crate) fn cli() -> Result<CliInfo> {
     let args = Cli::parse();
+    let config = Config::load(args.config.as_deref())?;
+    debug!("Configuration: {config:#?}");
 
     let mut cli_info = CliInfo::new();
 
-    // we should be safe here to rely on clap and its verification, though let us fail back to a default value to avoid panics
-    cli_info.actions = if args.action.trim().is_empty() {
-        "fstab".to_string()
-    } else {
-        args.action
-    };
+    match args.command {
+        Some(Commands::Inspect { format }) => {
+            // An inspection must never alter the disk. It uses the same read-only detection as the dry-run mode
+            cli_info.mode = Mode::Inspect(format);
+            cli_info.dry_run = true;
+        }
+        Some(Commands::ListActions) => {
+            cli_info.mode = Mode::ListActions;
+        }
+        Some(Commands::LvmMetadata { candidate, yes }) => {
+            cli_info.mode = Mode::LvmMetadata {
+                candidate,
+                is_confirmed: yes,
+            };
+        }
+        None => {
+            // we should be safe here to rely on clap and its verification, though let us fail back to a default value to avoid panics
+            cli_info.actions = match args.action {
+                Some(action) if !action.trim().is_empty() => action,
+                _ => "fstab".to_string(),
+            };
+        }
+    }
 
-    // Here the default is intentionally set to an empty string as a default value.
-    cli_info.local_action_directory = args.directory.unwrap_or_default();
+    // The action source of the configuration file is only considered if no source is selected on the command line
+    if args.directory.is_some() || args.download_action_scripts {
+        // Here the default is intentionally set to an empty string as a default value.
+        cli_info.local_action_directory = args.directory.unwrap_or_default();
+        cli_info.download_action_scripts = args.download_action_scripts;
+    } else {
+        match config.actions.source {
+            Some(ActionSourceConfig::Directory) => {
+                cli_info.local_action_directory = config.actions.directory.unwrap_or_default();
+            }
+            Some(ActionSourceConfig::Download) => cli_info.download_action_scripts = true,
+            Some(ActionSourceConfig::Builtin) | None => {}
+        }
+    }
 
+    cli_info.disk_filter = DiskFilter {
+        lun: args.lun,
+        serial: args.disk_serial,
+        size: args.disk_size,
+    };
     // We also set a default value for an empty string.
-    cli_info.custom_recover_disk = args.custom_recover_disk.unwrap_or_default();
+    // A disk filter on the command line replaces the disk of the configuration file
+    let config_disk = config.disk.custom_recover_disk.filter(|_| cli_info.disk_filter.is_empty());
+    cli_info.custom_recover_disk = args.custom_recover_disk.or(config_disk).unwrap_or_default();
+    cli_info.i_know_what_i_am_doing = args.i_know_what_i_am_doing;
+    cli_info.additional_recover_disks = args.additional_recover_disks;
+    cli_info.allow_partial_vg = args.allow_partial_vg;
+    cli_info.rollback_snapshot = args.snapshot;
 
     // If the encryption key is passed over manually we can be sure it is copied out of the key-vault
     // /the key-vault value is base64 encoded as well. Thus we need to decode it first to be able to use it to decrypt the disk.
     let decoded_bytes = simple_base64::decode(args.ade_password.as_deref().unwrap_or(""))?;
     cli_info.ade_password = String::from_utf8(decoded_bytes)?;
 
-    cli_info.download_action_scripts = args.download_action_scripts;
+    cli_info.dry_run |= args.dry_run;
+    cli_info.on_action_failure = args
+        .on_action_failure
+        .or(config.actions.on_failure)
+        .unwrap_or_default();
+    cli_info.action_timeout = args.action_timeout.or(config.actions.action_timeout);
+    cli_info.run_timeout = args.run_timeout.or(config.actions.run_timeout);
+    cli_info.fsck_policy = args.fsck_policy.or(config.fsck.policy).unwrap_or_default();
+    cli_info.log_directory = args
+        .log_directory
+        .or(config.log.directory)
+        .unwrap_or_else(|| constants::RUN_LOG_DIR.to_string());
+    // The flag wins over the environment variable, which wins over the configuration file
+    let env_telemetry = match env::var(constants::TELEMETRY_ENV) {
+        Ok(value) => Some(
+            telemetry::parse_mode(&value)
+                .map_err(|e| anyhow::anyhow!("{}: {e}", constants::TELEMETRY_ENV))?,
+        ),
+        Err(_) => None,
+    };
+    cli_info.telemetry = if args.no_telemetry {
+        TelemetryMode::Off
+    } else {
+        env_telemetry.or(config.telemetry.mode).unwrap_or_default()
+    };
 
     // selfhelp-initiator and initiator serve the same purpose, initiator is the parameter passed over from the Portal SelfHelp framework
     cli_info.initiator = if args.selfhelp_initiator {
@@ -120,3 +406,34 @@ pub(crate) fn cli() -> Result<CliInfo> {
     debug!("cli_info is {cli_info:#?}");
     Ok(cli_info)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn duration_values() {
+        assert_eq!(parse_duration("90"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("45s"), Ok(Duration::from_secs(45)));
+        assert_eq!(parse_duration("30m"), Ok(Duration::from_secs(1800)));
+        assert_eq!(parse_duration("2h"), Ok(Duration::from_secs(7200)));
+        assert!(parse_duration("0").is_err());
+        assert!(parse_duration("10d").is_err());
+        assert!(parse_duration(&format!("{}h", u64::MAX)).is_err());
+        assert!(parse_duration(&u64::MAX.to_string()).is_err());
+        assert!(parse_duration("").is_err());
+    }
+
+    #[test]
+    fn disk_size_values() {
+        let size = parse_disk_size("64G").unwrap();
+        assert_eq!(size, DiskSize { value: 64, unit: 1 << 30 });
+        assert!(size.matches(64 << 30));
+        assert!(!size.matches(128 << 30));
+        assert_eq!(parse_disk_size("1TiB"), Ok(DiskSize { value: 1, unit: 1 << 40 }));
+        assert_eq!(parse_disk_size("512m"), Ok(DiskSize { value: 512, unit: 1 << 20 }));
+        assert_eq!(parse_disk_size("4096"), Ok(DiskSize { value: 4096, unit: 1 }));
+        assert!(parse_disk_size("0G").is_err());
+        assert!(parse_disk_size("big").is_err());
+    }
+}
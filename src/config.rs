@@ -0,0 +1,165 @@
+use crate::cli::{self, ActionFailurePolicy, FsckPolicy};
+use crate::constants;
+use crate::telemetry::TelemetryMode;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Deserializer};
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+// The optional configuration file sets the defaults of a repair VM image. Any option passed over on
+// the command line takes precedence over the value in the file.
+//
+// [actions]
+// source = "builtin"            # builtin, directory or download
+// directory = "/opt/alar/actions"
+// action_timeout = "30m"
+// run_timeout = "2h"
+// on_failure = "stop"
+//
+// [disk]
+// custom_recover_disk = "/dev/disk/azure/scsi1/lun1"
+//
+// [telemetry]
+// mode = "off"
+//
+// [fsck]
+// policy = "auto"               # auto, check or skip
+//
+// [log]
+// directory = "/var/log/alar"
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct Config {
+    pub(crate) actions: ActionsConfig,
+    pub(crate) disk: DiskConfig,
+    pub(crate) telemetry: TelemetryConfig,
+    pub(crate) fsck: FsckConfig,
+    pub(crate) log: LogConfig,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum ActionSourceConfig {
+    Builtin,
+    Directory,
+    Download,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct ActionsConfig {
+    pub(crate) source: Option<ActionSourceConfig>,
+    pub(crate) directory: Option<String>,
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub(crate) action_timeout: Option<Duration>,
+    #[serde(deserialize_with = "deserialize_duration")]
+    pub(crate) run_timeout: Option<Duration>,
+    pub(crate) on_failure: Option<ActionFailurePolicy>,
+}
+
+// The durations are written the same way as on the command line, i.e. 900, 30m or 1h
+fn deserialize_duration<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Duration>, D::Error> {
+    Option::<String>::deserialize(deserializer)?
+        .map(|value| cli::parse_duration(&value).map_err(serde::de::Error::custom))
+        .transpose()
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct DiskConfig {
+    pub(crate) custom_recover_disk: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct TelemetryConfig {
+    pub(crate) mode: Option<TelemetryMode>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct FsckConfig {
+    pub(crate) policy: Option<FsckPolicy>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub(crate) struct LogConfig {
+    pub(crate) directory: Option<String>,
+}
+
+impl Config {
+    fn parse(content: &str) -> Result<Config> {
+        let config: Config = toml::from_str(content)?;
+        if config.actions.source == Some(ActionSourceConfig::Directory)
+            && config.actions.directory.is_none()
+        {
+            anyhow::bail!("actions.source is 'directory' but actions.directory is not set");
+        }
+        Ok(config)
+    }
+
+    // A file passed over with --config has to exist. The default file is optional.
+    pub(crate) fn load(path: Option<&str>) -> Result<Config> {
+        let (path, is_required) = match path {
+            Some(path) => (path, true),
+            None => (constants::CONFIG_FILE, false),
+        };
+
+        if !is_required && !Path::new(path).exists() {
+            return Ok(Config::default());
+        }
+
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Configuration file {path} can not be read"))?;
+        Config::parse(&content).with_context(|| format!("Configuration file {path} is not valid"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_full_config() {
+        let config = Config::parse(
+            r#"
+            [actions]
+            source = "directory"
+            directory = "/opt/alar/actions"
+            action_timeout = "30m"
+            on_failure = "stop"
+
+            [disk]
+            custom_recover_disk = "/dev/sdc"
+
+            [telemetry]
+            mode = "off"
+
+            [fsck]
+            policy = "check"
+
+            [log]
+            directory = "/srv/alar-logs"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.actions.source, Some(ActionSourceConfig::Directory));
+        assert_eq!(config.actions.action_timeout, Some(Duration::from_secs(1800)));
+        assert_eq!(config.actions.run_timeout, None);
+        assert_eq!(config.actions.on_failure, Some(ActionFailurePolicy::Stop));
+        assert_eq!(config.disk.custom_recover_disk.as_deref(), Some("/dev/sdc"));
+        assert_eq!(config.telemetry.mode, Some(TelemetryMode::Off));
+        assert_eq!(config.fsck.policy, Some(FsckPolicy::Check));
+        assert_eq!(config.log.directory.as_deref(), Some("/srv/alar-logs"));
+    }
+
+    #[test]
+    fn parse_invalid_config() {
+        assert!(Config::parse("[actions]\nunknown = 1").is_err());
+        assert!(Config::parse("[actions]\nsource = \"directory\"").is_err());
+        assert!(Config::parse("[actions]\naction_timeout = \"10d\"").is_err());
+        assert!(Config::parse("").is_ok());
+    }
+}
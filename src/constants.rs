@@ -1,4 +1,14 @@
 pub(crate) static RESCUE_DISK: &str = "/dev/disk/azure/scsi1/lun0";
+// The udev rules of the Azure images link the data disks by their LUN
+pub(crate) static AZURE_LUN_LINKS: &[(&str, &str)] = &[("/dev/disk/azure/scsi1", "lun"), ("/dev/disk/azure/data/by-lun", "")];
+// The OS disk and the temporary disk of the repair VM are never a recovery disk
+pub(crate) static AZURE_OS_DISK_LINKS: &[&str] = &["/dev/disk/azure/root", "/dev/disk/azure/os"];
+pub(crate) static AZURE_RESOURCE_DISK: &str = "/dev/disk/azure/resource";
+pub(crate) static NVME_DIRECT_DISK_MODEL: &str = "Microsoft NVMe Direct Disk";
+// Each candidate disk gets mounted here read-only to tell the OS on it
+pub(crate) static CANDIDATE_MOUNT_PATH: &str = "/tmp/alar-candidate";
+// The LVM configuration used instead of the one of the repair VM if the repair VM uses a LVM devices file
+pub(crate) static PRIVATE_LVM_DIR: &str = "/tmp/alar-lvm";
 pub(crate) static RESCUE_BEK: &str = "/srv/rescue-bek/";
 pub(crate) static RESCUE_BEK_BOOT: &str = "/srv/rescue-bek-boot";
 pub(crate) static RESCUE_BEK_LINUX_PASS_PHRASE_FILE_NAME: &str = "/srv/rescue-bek/LinuxPassPhraseFileName";
@@ -6,20 +16,37 @@ pub(crate) static RESCUE_TMP_LINUX_PASS_PHRASE_FILE_NAME: &str = "/tmp/LinuxPass
 pub(crate) static ASSERT_PATH: &str = "/tmp/assert";
 pub(crate) static ASSERT_PATH_USR: &str = "/tmp/assert/usr";
 pub(crate) static OS_RELEASE: &str = "/tmp/assert/etc/os-release";
+pub(crate) static ASSERT_FSTAB: &str = "/tmp/assert/etc/fstab";
 pub(crate) static ADE_OSENCRYPT_PATH: &str = "/dev/mapper/rescueencrypt";
+// The encrypted disk as opened by the repair extension
+pub(crate) static REPAIR_OSENCRYPT_PATH: &str = "/dev/mapper/osencrypt";
 pub(crate) static INVESTIGATEROOT_DIR: &str = "/investigateroot";
-pub(crate) static RESCUE_ROOTVG: &str = "rootvg";
-pub(crate) static ROOTVG_ROOTLV: &str = "/dev/rootvg/rootlv";
-pub(crate) static ROOTVG_USRLV: &str = "/dev/rootvg/usrlv";
-pub(crate) static RESCUE_ADE_USRLV: &str = "/dev/rootvg/usrlv";
-pub(crate) static RESCUE_ADE_ROOTLV: &str = "/dev/rootvg/rootlv";
 pub(crate) static RESCUE_ROOT_BOOT: &str = "/srv/rescue-root/boot";
 pub(crate) static RESCUE_ROOT_BOOT_EFI: &str = "/srv/rescue-root/boot/efi";
 pub(crate) static SUPPORT_FILESYSTEMS: &str = "dev proc sys tmp dev/pts run";
 pub(crate) static ACTION_IMPL_DIR: &str = "/tmp/action_implementation";
+// The run log is written to this directory on the repair VM and copied to the same directory on the recovered disk
+pub(crate) static RUN_LOG_DIR: &str = "/var/log/alar";
+// The optional configuration file of ALAR. Another file can be passed over with --config
+pub(crate) static CONFIG_FILE: &str = "/etc/alar/alar.toml";
+// Setting this environment variable to 'off' turns off the telemetry
+pub(crate) static TELEMETRY_ENV: &str = "ALAR_TELEMETRY";
+pub(crate) static LIST_ACTIONS_DIR: &str = "/tmp/alar-list-actions";
 pub(crate) static CHROOT_CLI: &str = "chroot-cli";
+// The builtin action implemented by ALAR itself, see lvmdevices.rs
+pub(crate) static LVMDEVICES: &str = "lvmdevices";
 pub(crate) static TARBALL: &str = "https://github.com/Azure/ALAR/tarball/master";
 pub(crate) static RESCUE_ROOT: &str = "/srv/rescue-root/";
+// Exit codes of ALAR if an action doesn't succeed. Any other error ends ALAR with exit code 1
+pub(crate) const EXIT_ACTION_FAILED: i32 = 2;
+pub(crate) const EXIT_ACTION_SIGNALED: i32 = 3;
+pub(crate) const EXIT_ACTION_TIMEOUT: i32 = 4;
+// ALAR itself got stopped by SIGINT, SIGTERM or SIGHUP
+pub(crate) const EXIT_INTERRUPTED: i32 = 5;
+// The limit of '--run-timeout' got exceeded before ALAR finished
+pub(crate) const EXIT_RUN_TIMEOUT: i32 = 6;
+// How long the output of a killed action is still collected
+pub(crate) const KILL_GRACE_PERIOD: std::time::Duration = std::time::Duration::from_secs(5);
 // Our builtin action scripts
 pub(crate) static AUDITD_IMPL_FILE: &str =  include_str!("action_implementation/auditd-impl.sh");
 pub(crate) static EFIFIX_IMPL_FILE: &str =  include_str!("action_implementation/efifix-impl.sh");
@@ -32,6 +59,25 @@ pub(crate) static INITRD_IMPL_FILE: &str =  include_str!("action_implementation/
 pub(crate) static KERNEL_IMPL_FILE: &str =  include_str!("action_implementation/kernel-impl.sh");
 pub(crate) static SAFE_EXIT_FILE: &str =  include_str!("action_implementation/safe-exit.sh");
 pub(crate) static SERIALCONSOLE_IMPL_FILE: &str =  include_str!("action_implementation/serialconsole-impl.sh");
+pub(crate) static SNAPSHOT_ROLLBACK_IMPL_FILE: &str =  include_str!("action_implementation/snapshot-rollback-impl.sh");
 pub(crate) static TEST_IMPL_FILE: &str =  include_str!("action_implementation/test-impl.sh");
 pub(crate) static SUDO_IMPL_FILE: &str =  include_str!("action_implementation/sudo-impl.sh");
-pub(crate) static CORRUPT_IMPL_FILE: &str =  include_str!("action_implementation/corrupt-impl.sh");
\ No newline at end of file
+pub(crate) static CORRUPT_IMPL_FILE: &str =  include_str!("action_implementation/corrupt-impl.sh");
+// File names and content of the builtin action scripts as they get written to ACTION_IMPL_DIR
+pub(crate) static BUILTIN_ACTION_FILES: &[(&str, &str)] = &[
+    ("auditd-impl.sh", AUDITD_IMPL_FILE),
+    ("efifix-impl.sh", EFIFIX_IMPL_FILE),
+    ("fstab-impl.py", FSTAB_IMPL_FILE),
+    ("grub.awk", GRUB_AKW_FILE),
+    ("grubfix-impl.sh", GRUBFIX_IMPL_FILE),
+    ("helpers.sh", HELPERS_SH_FILE),
+    ("helpers.py", HELPERS_PY_FILE),
+    ("initrd-impl.sh", INITRD_IMPL_FILE),
+    ("kernel-impl.sh", KERNEL_IMPL_FILE),
+    ("safe-exit.sh", SAFE_EXIT_FILE),
+    ("serialconsole-impl.sh", SERIALCONSOLE_IMPL_FILE),
+    ("snapshot-rollback-impl.sh", SNAPSHOT_ROLLBACK_IMPL_FILE),
+    ("test-impl.sh", TEST_IMPL_FILE),
+    ("sudo-impl.sh", SUDO_IMPL_FILE),
+    ("corrupt-impl.sh", CORRUPT_IMPL_FILE),
+];
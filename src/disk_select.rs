@@ -0,0 +1,515 @@
+use crate::blockdev;
+use crate::cli::{CliInfo, DiskFilter, Initiator};
+use crate::constants;
+use crate::gpt;
+use crate::helper;
+use crate::mbr;
+use crate::mount;
+use crate::probe::{self, FsType};
+use crate::teardown::{self, Step};
+use anyhow::{Context, Result, anyhow};
+use log::{debug, info, warn};
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::fs;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::Path;
+
+// Finds the recovery disk. It is either passed over with '--custom-recover-disk', narrowed down with '--lun',
+// '--disk-serial' and '--disk-size', or the disk at LUN 0. If several disks qualify the user picks one of them.
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Candidate {
+    pub(crate) name: String,
+    pub(crate) device: String,
+    pub(crate) size: u64,
+    pub(crate) lun: Option<u32>,
+    pub(crate) model: Option<String>,
+    pub(crate) serial: Option<String>,
+    // gpt, msdos or the content of a disk without a partition table
+    pub(crate) partition_table: String,
+    pub(crate) os: Option<String>,
+}
+
+impl Candidate {
+    fn matches(&self, filter: &DiskFilter) -> bool {
+        filter.lun.is_none_or(|lun| self.lun == Some(lun))
+            && filter
+                .serial
+                .as_ref()
+                .is_none_or(|serial| self.serial.as_ref().is_some_and(|own| own.eq_ignore_ascii_case(serial)))
+            && filter.size.is_none_or(|size| size.matches(self.size))
+    }
+}
+
+pub(crate) fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{size:.1} {}", UNITS[unit])
+    }
+}
+
+// The candidates as a numbered table, the number is the one to be entered for the interactive pick
+pub(crate) struct CandidateTable<'a>(pub(crate) &'a [Candidate]);
+
+impl Display for CandidateTable<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let optional = |value: &Option<String>| value.clone().unwrap_or_else(|| "-".to_string());
+        writeln!(
+            f,
+            "{:<3} {:<14} {:>10} {:>4} {:<20} {:<24} {:<12} OS",
+            "#", "DEVICE", "SIZE", "LUN", "MODEL", "SERIAL", "TABLE"
+        )?;
+        for (index, candidate) in self.0.iter().enumerate() {
+            writeln!(
+                f,
+                "{:<3} {:<14} {:>10} {:>4} {:<20} {:<24} {:<12} {}",
+                index + 1,
+                candidate.device,
+                format_size(candidate.size),
+                candidate.lun.map(|lun| lun.to_string()).unwrap_or_else(|| "-".to_string()),
+                optional(&candidate.model),
+                optional(&candidate.serial),
+                candidate.partition_table,
+                optional(&candidate.os),
+            )?;
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn select(cli_info: &CliInfo) -> Result<String> {
+    let disk = select_disk(cli_info)?;
+    refuse_system_disk(cli_info, &disk)?;
+    Ok(disk)
+}
+
+// The additional disks are always passed over explicitly. They are checked the same way as the recovery disk.
+pub(crate) fn select_additional(cli_info: &CliInfo) -> Result<Vec<String>> {
+    let mut disks: Vec<String> = Vec::new();
+    for path in &cli_info.additional_recover_disks {
+        let disk = blockdev::parent_disk(path).with_context(|| format!("The additional recovery disk {path} can not be used"))?;
+        if disk == cli_info.recovery_disk || disks.contains(&disk) {
+            debug!("{path} is passed over already as {disk}");
+            continue;
+        }
+        refuse_system_disk(cli_info, &disk)?;
+        info!("Additional recovery disk: {disk}");
+        disks.push(disk);
+    }
+    Ok(disks)
+}
+
+fn select_disk(cli_info: &CliInfo) -> Result<String> {
+    if !cli_info.custom_recover_disk.is_empty() {
+        // For a NBD device this part is used as well. A partition or a /dev/disk/by-* link is taken for its disk.
+        let disk = blockdev::parent_disk(&cli_info.custom_recover_disk)
+            .with_context(|| format!("The recovery disk {} can not be used", cli_info.custom_recover_disk))?;
+        if disk != helper::realpath(&cli_info.custom_recover_disk).unwrap_or_default() {
+            info!("{} is resolved to the disk {disk}", cli_info.custom_recover_disk);
+        }
+        return Ok(disk);
+    }
+
+    // The disk at LUN 0 is the one attached by 'az vm repair'. It is taken without further questions.
+    if cli_info.disk_filter.is_empty() && Path::new(constants::RESCUE_DISK).exists() {
+        return blockdev::parent_disk(constants::RESCUE_DISK);
+    }
+
+    let all_candidates = candidates()?;
+    let mut matching: Vec<Candidate> = all_candidates
+        .iter()
+        .filter(|candidate| candidate.matches(&cli_info.disk_filter))
+        .cloned()
+        .collect();
+    debug!("Recovery disk candidates: {matching:#?}");
+
+    match matching.len() {
+        0 => {
+            let mut all_candidates = all_candidates;
+            add_os_details(&mut all_candidates);
+            Err(anyhow!(
+                "No disk matches the selection {:?}. These disks are attached:\n{}",
+                cli_info.disk_filter,
+                CandidateTable(&all_candidates)
+            ))
+        }
+        1 => {
+            info!("Using {} as the recovery disk", matching[0].device);
+            Ok(matching.remove(0).device)
+        }
+        _ => {
+            add_os_details(&mut matching);
+            pick(cli_info, &matching)
+        }
+    }
+}
+
+// The choice is only offered on a terminal. ALAR started by Run Command or SelfHelp has no one to answer.
+fn pick(cli_info: &CliInfo, candidates: &[Candidate]) -> Result<String> {
+    let table = CandidateTable(candidates);
+    if !matches!(cli_info.initiator, Initiator::Cli) || !io::stdin().is_terminal() {
+        return Err(anyhow!(
+            "More than one disk qualifies as the recovery disk:\n{table}Please select one with '--lun', '--disk-serial', '--disk-size' or '--custom-recover-disk'"
+        ));
+    }
+
+    println!("More than one disk qualifies as the recovery disk:\n{table}");
+    let mut stdin = io::stdin().lock();
+    loop {
+        print!("Select the recovery disk [1-{}] or 'q' to quit: ", candidates.len());
+        io::stdout().flush()?;
+        let mut answer = String::new();
+        if stdin.read_line(&mut answer)? == 0 {
+            return Err(anyhow!("No recovery disk got selected"));
+        }
+        match answer.trim() {
+            "q" | "Q" => return Err(anyhow!("No recovery disk got selected")),
+            answer => match answer.parse::<usize>() {
+                Ok(number) if (1..=candidates.len()).contains(&number) => {
+                    let device = candidates[number - 1].device.clone();
+                    info!("Using {device} as the recovery disk");
+                    return Ok(device);
+                }
+                _ => println!("'{answer}' is not one of the listed disks"),
+            },
+        }
+    }
+}
+
+// All disks except those of the repair VM itself: the ones it is running from, its temporary disk and the local NVMe disks
+fn candidates() -> Result<Vec<Candidate>> {
+    let mut repair_vm_disks: Vec<String> = system_disks().into_iter().map(|(disk, _)| disk).collect();
+    if let Ok(resource_disk) = blockdev::backing_disks(constants::AZURE_RESOURCE_DISK) {
+        repair_vm_disks.extend(resource_disk);
+    }
+    debug!("Disks in use by the repair VM: {repair_vm_disks:?}");
+    let luns = azure_luns();
+
+    let mut candidates = Vec::new();
+    for name in blockdev::disks()? {
+        let model = blockdev::model(&name);
+        if repair_vm_disks.contains(&name) || model.as_deref() == Some(constants::NVME_DIRECT_DISK_MODEL) {
+            continue;
+        }
+        let device = format!("/dev/{name}");
+        // Without the links of the Azure udev rules the SCSI address is the next best source
+        let lun = if luns.is_empty() {
+            blockdev::scsi_lun(&name)
+        } else {
+            luns.get(&name).copied()
+        };
+        candidates.push(Candidate {
+            size: blockdev::size(&name)?,
+            lun,
+            model,
+            serial: blockdev::serial(&name),
+            partition_table: partition_table(&device),
+            os: None,
+            device,
+            name,
+        });
+    }
+    Ok(candidates)
+}
+
+// The disks the running system depends on, each with what it is used for: the disks backing a mount or a swap device,
+// all PVs of the active VGs, and the OS disk as linked by the Azure udev rules
+fn system_disks() -> Vec<(String, String)> {
+    let mut devices: Vec<(String, String)> = Vec::new();
+    if let Ok(mounts) = fs::read_to_string("/proc/mounts") {
+        for line in mounts.lines() {
+            let mut fields = line.split_whitespace();
+            if let (Some(source), Some(target)) = (fields.next(), fields.next())
+                && source.starts_with("/dev/")
+            {
+                devices.push((source.to_string(), format!("{source} is mounted on {target}")));
+            }
+        }
+    }
+    if let Ok(swaps) = fs::read_to_string("/proc/swaps") {
+        devices.extend(
+            swaps
+                .lines()
+                .filter_map(|line| line.split_whitespace().next())
+                .filter(|source| source.starts_with("/dev/"))
+                .map(|source| (source.to_string(), format!("{source} is used as swap"))),
+        );
+    }
+    let vgs = system_vgs(&devices);
+    if !vgs.is_empty()
+        && let Ok(pvs) = helper::run_fun("pvs --noheadings --separator ';' -o pv_name,vg_uuid")
+    {
+        devices.extend(system_pvs(&pvs, &vgs));
+    }
+    devices.extend(
+        constants::AZURE_OS_DISK_LINKS
+            .iter()
+            .filter(|link| Path::new(link).exists())
+            .map(|link| (link.to_string(), format!("{link} is the OS disk of the repair VM"))),
+    );
+
+    let mut disks = Vec::new();
+    for (device, usage) in devices {
+        match blockdev::backing_disks(&device) {
+            Ok(backing) => disks.extend(backing.into_iter().map(|disk| (disk, usage.clone()))),
+            Err(e) => debug!("The disk of {device} is not known: {e:#}"),
+        }
+    }
+    disks.sort();
+    disks.dedup();
+    disks
+}
+
+// The VGs of the mounted LVs and all other active VGs, by UUID and name. The other PVs of those VGs are in use as well,
+// even if none of their extents is mounted. The UUID tells them apart from a VG of the recovery disk with the same name.
+fn system_vgs(devices: &[(String, String)]) -> Vec<(String, String)> {
+    let mut lvs: Vec<String> = devices
+        .iter()
+        .filter(|(device, _)| device.starts_with("/dev/mapper/") || device.starts_with("/dev/dm-"))
+        .filter_map(|(device, _)| helper::run_fun(&format!("lvs --noheadings --separator ';' -o vg_uuid,vg_name {device}")).ok())
+        .collect();
+    if let Ok(active) = helper::run_fun("lvs --noheadings --separator ';' -o vg_uuid,vg_name -S lv_active=active") {
+        lvs.push(active);
+    }
+    let mut vgs = parse_vgs(&lvs.join("\n"));
+    vgs.sort();
+    vgs.dedup();
+    vgs
+}
+
+fn parse_vgs(output: &str) -> Vec<(String, String)> {
+    output
+        .lines()
+        .filter_map(|line| line.trim().split_once(';'))
+        .filter(|(uuid, _)| !uuid.is_empty())
+        .map(|(uuid, name)| (uuid.to_string(), name.to_string()))
+        .collect()
+}
+
+// The PVs of the VGs of the running system, selected from the output of 'pvs -o pv_name,vg_uuid'
+fn system_pvs(pvs: &str, vgs: &[(String, String)]) -> Vec<(String, String)> {
+    pvs.lines()
+        .filter_map(|line| line.trim().split_once(';'))
+        .filter_map(|(pv, vg_uuid)| {
+            vgs.iter()
+                .find(|(uuid, _)| uuid == vg_uuid)
+                .map(|(uuid, name)| (pv.to_string(), format!("{pv} is a PV of the VG {name} ({uuid}) of the running system")))
+        })
+        .collect()
+}
+
+// The last line of defense before anything gets changed. A typo in '--custom-recover-disk' or a shifted LUN must not
+// lead to a fsck, a VG rename or the removal of the disk the repair VM is running from.
+fn refuse_system_disk(cli_info: &CliInfo, disk: &str) -> Result<()> {
+    let name = disk.trim_start_matches("/dev/");
+    let usages: Vec<String> = system_disks()
+        .into_iter()
+        .filter(|(system_disk, _)| system_disk == name)
+        .map(|(_, usage)| usage)
+        .collect();
+    if usages.is_empty() {
+        return Ok(());
+    }
+
+    let details = usages.join("\n  ");
+    if cli_info.i_know_what_i_am_doing {
+        warn!("{disk} is in use by the running system. It is used anyway as '--i-know-what-i-am-doing' is set:\n  {details}");
+        return Ok(());
+    }
+    Err(anyhow!(
+        "{disk} is in use by the running system and can't be the recovery disk:\n  {details}\nSelect another disk or pass over '--i-know-what-i-am-doing' if this is really intended"
+    ))
+}
+
+// Maps the kernel name of a data disk to its LUN
+fn azure_luns() -> HashMap<String, u32> {
+    let mut luns = HashMap::new();
+    for (directory, prefix) in constants::AZURE_LUN_LINKS {
+        let Ok(entries) = fs::read_dir(directory) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let link = entry.file_name().to_string_lossy().to_string();
+            let Some(lun) = link.strip_prefix(prefix).and_then(|lun| lun.parse().ok()) else {
+                continue;
+            };
+            if let Ok(device) = fs::canonicalize(entry.path())
+                && let Some(name) = device.file_name()
+            {
+                luns.insert(name.to_string_lossy().to_string(), lun);
+            }
+        }
+    }
+    luns
+}
+
+fn partition_table(device: &str) -> String {
+    if gpt::read_from_path(device).is_ok() {
+        return "gpt".to_string();
+    }
+    if mbr::read_from_path(device).is_ok() {
+        return "msdos".to_string();
+    }
+    match probe::probe_path(device) {
+        Ok(Some(probe)) => probe.fstype.to_string(),
+        _ => "none".to_string(),
+    }
+}
+
+fn add_os_details(candidates: &mut [Candidate]) {
+    if let Err(e) = fs::create_dir_all(constants::CANDIDATE_MOUNT_PATH) {
+        warn!("The OS of the candidate disks can not be detected: {e}");
+        return;
+    }
+    teardown::register(Step::RemoveDir(constants::CANDIDATE_MOUNT_PATH.to_string()));
+    for candidate in candidates.iter_mut() {
+        candidate.os = detect_os(&candidate.device);
+    }
+    if fs::remove_dir(constants::CANDIDATE_MOUNT_PATH).is_ok() {
+        teardown::forget(&Step::RemoveDir(constants::CANDIDATE_MOUNT_PATH.to_string()));
+    }
+}
+
+// Reads the os-release file of the first filesystem holding one. Nothing gets written to the disk: XFS and ext4
+// are mounted without replaying their journal. LVM and encrypted disks are only reported as such.
+fn detect_os(device: &str) -> Option<String> {
+    let mut devices: Vec<String> = blockdev::partitions(device)
+        .map(|partitions| partitions.into_iter().map(|partition| partition.device).collect())
+        .unwrap_or_default();
+    if devices.is_empty() {
+        devices.push(device.to_string());
+    }
+
+    let mut container = None;
+    for device in devices {
+        let Ok(Some(content)) = probe::probe_path(&device) else {
+            continue;
+        };
+        let options = match content.fstype {
+            FsType::Xfs => "nouuid,norecovery",
+            FsType::Ext3 | FsType::Ext4 => "noload",
+            FsType::Ext2 => "",
+            FsType::Lvm2Member => {
+                container = Some("LVM, not inspected");
+                continue;
+            }
+            FsType::Luks1 | FsType::Luks2 | FsType::BitLocker => {
+                container.get_or_insert("encrypted");
+                continue;
+            }
+            _ => continue,
+        };
+        if mount::mount_readonly(&device, constants::CANDIDATE_MOUNT_PATH, options, true).is_err() {
+            continue;
+        }
+        let os_release = fs::read_to_string(Path::new(constants::CANDIDATE_MOUNT_PATH).join("etc/os-release"))
+            .or_else(|_| fs::read_to_string(Path::new(constants::CANDIDATE_MOUNT_PATH).join("usr/lib/os-release")));
+        let _ = mount::umount(constants::CANDIDATE_MOUNT_PATH, false);
+        if let Some(name) = os_release.ok().as_deref().and_then(pretty_name) {
+            return Some(name);
+        }
+    }
+    container.map(|container| format!("({container})"))
+}
+
+fn pretty_name(os_release: &str) -> Option<String> {
+    os_release
+        .lines()
+        .find_map(|line| line.strip_prefix("PRETTY_NAME="))
+        .map(|name| name.trim().trim_matches('"').to_string())
+        .filter(|name| !name.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cli::DiskSize;
+
+    fn candidate(device: &str, lun: Option<u32>, serial: &str, size: u64) -> Candidate {
+        Candidate {
+            name: device.trim_start_matches("/dev/").to_string(),
+            device: device.to_string(),
+            size,
+            lun,
+            model: Some("Virtual Disk".to_string()),
+            serial: Some(serial.to_string()),
+            partition_table: "gpt".to_string(),
+            os: None,
+        }
+    }
+
+    #[test]
+    fn candidates_matching_the_filter() {
+        let candidates = [
+            candidate("/dev/sdc", Some(0), "60022480A1", 64 << 30),
+            candidate("/dev/sdd", Some(1), "60022480B2", 128 << 30),
+        ];
+        let pick = |filter: DiskFilter| -> Vec<&str> {
+            candidates
+                .iter()
+                .filter(|candidate| candidate.matches(&filter))
+                .map(|candidate| candidate.device.as_str())
+                .collect()
+        };
+
+        assert_eq!(pick(DiskFilter::default()), vec!["/dev/sdc", "/dev/sdd"]);
+        assert_eq!(pick(DiskFilter { lun: Some(1), ..DiskFilter::default() }), vec!["/dev/sdd"]);
+        assert_eq!(
+            pick(DiskFilter {
+                serial: Some("60022480a1".to_string()),
+                ..DiskFilter::default()
+            }),
+            vec!["/dev/sdc"]
+        );
+        assert_eq!(
+            pick(DiskFilter {
+                lun: Some(0),
+                size: Some(DiskSize { value: 128, unit: 1 << 30 }),
+                ..DiskFilter::default()
+            }),
+            Vec::<&str>::new()
+        );
+    }
+
+    #[test]
+    fn system_pvs_by_vg_uuid() {
+        // The repair VM and the recovery disk both have a VG 'rootvg'
+        let vgs = parse_vgs("  Aa1-system;rootvg\n  Cc3-data;datavg\n");
+        assert_eq!(
+            vgs,
+            vec![
+                ("Aa1-system".to_string(), "rootvg".to_string()),
+                ("Cc3-data".to_string(), "datavg".to_string()),
+            ]
+        );
+        let pvs = "  /dev/sda4;Aa1-system\n  /dev/sdc4;Bb2-recovery\n  /dev/sdd;Cc3-data\n  /dev/sde;\n";
+        let system: Vec<String> = system_pvs(pvs, &vgs).into_iter().map(|(pv, _)| pv).collect();
+        assert_eq!(system, vec!["/dev/sda4", "/dev/sdd"]);
+        assert_eq!(
+            system_pvs(pvs, &vgs[..1])[0].1,
+            "/dev/sda4 is a PV of the VG rootvg (Aa1-system) of the running system"
+        );
+    }
+
+    #[test]
+    fn candidate_table_and_os_name() {
+        let mut disk = candidate("/dev/nvme0n2", Some(0), "SN01", 30 << 30);
+        disk.os = pretty_name("NAME=\"Ubuntu\"\nPRETTY_NAME=\"Ubuntu 24.04.1 LTS\"\n");
+        let table = CandidateTable(&[disk]).to_string();
+        let lines: Vec<&str> = table.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[1].starts_with("1   /dev/nvme0n2"));
+        assert!(lines[1].contains("30.0 GiB"));
+        assert!(lines[1].ends_with("Ubuntu 24.04.1 LTS"));
+        assert_eq!(pretty_name("ID=rhel\n"), None);
+    }
+}
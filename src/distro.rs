@@ -1,57 +1,86 @@
 use crate::ade;
+use crate::blockdev;
+use crate::btrfs;
 use crate::cli;
 use crate::cli::CliInfo;
 use crate::constants;
+use crate::fstab;
+use crate::gpt;
 use crate::helper;
+use crate::lvm;
+use crate::mbr;
 use crate::mount;
+use crate::probe;
 use crate::telemetry;
-use anyhow::Result;
+use crate::teardown::{self, Step};
+use anyhow::{Context, Result, anyhow};
+use serde::Serialize;
 use log::debug;
 use log::error;
 use log::info;
-use std::collections::HashMap;
+use log::warn;
 use std::fmt::Display;
-use std::{
-    fs,
-    path::Path,
-    process::{self},
-};
+use std::{fs, path::Path};
 
-#[derive(Debug, Default)]
+// Damaged LVM metadata lets the VG detection fail
+const METADATA_HINT: &str = "If the LVM metadata is damaged, 'alar lvm-metadata' restores an earlier copy of it";
+
+#[derive(Debug, Default, Serialize)]
 pub(crate) struct PartInfo {
     pub(crate) number: i32,
+    // Taken from sysfs, never built from the disk path and the number
+    pub(crate) device: String,
+    // major:minor
+    pub(crate) dev: String,
+    pub(crate) sysfs_path: String,
     pub(crate) part_type: String,
     pub(crate) fstype: String,
+    // Taken from the superblock. Not known for an encrypted partition.
+    pub(crate) fs_uuid: Option<String>,
+    pub(crate) fs_label: Option<String>,
+    pub(crate) fs_features: Vec<String>,
+    // The PARTUUID the kernel reports, taken from the partition table
+    pub(crate) part_uuid: Option<String>,
     pub(crate) contains_os: bool,
     pub(crate) logical_volumes: LogicalVolumesType,
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Serialize)]
 pub(crate) struct LogicalVolume {
     pub(crate) name: String,
     pub(crate) fstype: String,
 }
 
-#[derive(Debug, Clone, PartialEq, Default)]
+// Serialized as a list of LVs or as null if the partition doesn't carry any
+#[derive(Debug, Clone, PartialEq, Default, Serialize)]
+#[serde(untagged)]
 pub(crate) enum LogicalVolumesType {
     Some(Vec<LogicalVolume>),
     #[default]
     None,
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Serialize)]
 pub(crate) struct DistroNameVersion {
     pub(crate) name: String,
     pub(crate) version_id: String,
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Serialize)]
 pub(crate) struct Distro {
     pub(crate) partitions: Vec<PartInfo>,
     pub(crate) distro_name_version: DistroNameVersion,
+    // Never serialized, it may contain the ADE password
+    #[serde(skip)]
     pub(crate) cli_info: CliInfo,
     pub(crate) is_ade: bool,
     pub(crate) is_lvm: bool,
+    // The VGs of the recovery disks with all of their PVs
+    pub(crate) volume_groups: Vec<lvm::VolumeGroup>,
+    // The subvolumes and snapshots if the OS is installed on btrfs
+    pub(crate) btrfs: Option<btrfs::BtrfsLayout>,
+    // The fstab of the recovered system, it defines what gets mounted into the chroot environment
+    pub(crate) fstab: Vec<fstab::FstabEntry>,
     pub(crate) architecture: Architecture,
 }
 
@@ -66,10 +95,12 @@ pub enum DistroType {
     Undefined,
 }
 
-#[derive(Debug, Default, Copy, Clone)]
+#[derive(Debug, Default, Copy, Clone, Serialize)]
 pub(crate) enum Architecture {
     #[default]
+    #[serde(rename = "x86_64")]
     X86_64,
+    #[serde(rename = "aarch64")]
     Aarch64,
 }
 
@@ -127,276 +158,320 @@ impl PartInfo {
     fn activate_is_os(&mut self) {
         self.contains_os = true;
     }
-}
 
-impl Distro {
-    fn split_fields(line: &str) -> Vec<String> {
-        line.split_whitespace().map(|s| s.to_string()).collect()
+    pub(crate) fn is_whole_disk(&self) -> bool {
+        self.number == 0
     }
+}
 
-    fn get_partitions_and_types(disk_path: &str) -> Vec<(String, String)> {
-        let mut partition_numbers_types: Vec<(String, String)> = Vec::with_capacity(6);
-        let uuid_type_map = HashMap::from([
-            (
-                "4f68bce3-e8cd-4db1-96e7-fbcaf984b709",
-                "Linux Root Partition 0x8304",
-            ),
-            (
-                "c12a7328-f81f-11d2-ba4b-00a0c93ec93b",
-                "EFI System Partition 0xEF00",
-            ),
-            ("0657fd6d-a4ab-43c4-84e5-0933c84b4f4f", "Linux Swap 0x8200"),
-            (
-                "21686148-6449-6e6f-744e-656564454649",
-                "Basic Boot Partition 0xEF02",
-            ),
-            (
-                "0fc63daf-8483-4772-8e79-3d69d8477de4",
-                "Linux Filesystem Data 0x8300",
-            ),
-            (
-                "e6d6d379-f507-44c2-a23c-238f2a3df928",
-                "Logical Volume Manager 0x8E00",
-            ),
-        ]);
-        
-        // Need to remove the partition suffix for NVMe or NBD disks as the partition type detection commands expect the disk path without the suffix
-         let disk_path = if let Some(suffix_stripped) = disk_path.strip_suffix('p') {
-            debug!("Detected NVMe or NBD disk. Removing 'p' suffix for partition type detection commands.");
-            suffix_stripped
-         } else {
-            disk_path
-        };
-
-        match Path::new("/usr/sbin/sgdisk").try_exists() {
-            Ok(_is_present @ true) => {
-                debug!("sgdisk is present on the system.");
-                let command = format!("sgdisk {disk_path} -p | tail -n6 | grep -E \"^ *[1,2,3,4,5,6]\" | grep -v EF02 | sed 's/[ ]\\+/ /g;s/^[ \t]*//' ");
-                match helper::run_fun(&command) {
-                    Ok(partitions) => {
-                        for line in partitions.lines() {
-                            let fields = Self::split_fields(line);
-                            let partition_number = fields[0].to_string();
-                            let partition_type = fields[5].to_string();
-                            partition_numbers_types.push((partition_number, partition_type));
-                        }
-                        debug!(
-                            "Partition numbers and types collected via sgdisk: {:#?}",
-                            &partition_numbers_types
-                        );
-                        partition_numbers_types
-                    }
-                    Err(e) => {
-                        error!("Error getting disk info for disk {} with the help of sgdisk : {}. ALAR is not able to proceed. Exiting.", disk_path, e);
-                        process::exit(1);
-                    }
+impl Distro {
+    // GPT and msdos partition tables are both supported. The partitions of a msdos table get the GPT type code
+    // of the same type, thus they are classified the same way.
+    // The BIOS boot partition (EF02) and swap (8200) carry no filesystem to look into, thus they are left out.
+    // The PARTUUID of a msdos partition is built from the disk signature and the partition number.
+    fn get_partitions_and_types(disk_path: &str) -> Result<Vec<(String, String, String)>> {
+        let partitions: Vec<(String, String, String)> = match gpt::read_from_path(disk_path) {
+            Ok(table) => table
+                .partitions
+                .iter()
+                .map(|partition| {
+                    (
+                        partition.number.to_string(),
+                        partition.type_code(),
+                        partition.unique_guid.to_string(),
+                    )
+                })
+                .collect(),
+            Err(gpt_error) => match mbr::read_from_path(disk_path) {
+                Ok(table) => {
+                    debug!("No GPT found on {disk_path}, using the msdos partition table: {gpt_error:#}");
+                    table
+                        .partitions
+                        .iter()
+                        .map(|partition| {
+                            (
+                                partition.number.to_string(),
+                                partition.type_code(),
+                                format!("{:08x}-{:02x}", table.disk_signature, partition.number),
+                            )
+                        })
+                        .collect()
                 }
-            }
-            Ok(_is_present @ false) => {
-                debug!("sgdisk is not present on the system. Falling back to partx.");
-                // The command constucted below will list partition numbers and types excluding the BOOT partition 
-                let command = format!("partx {disk_path} -gs -o NR,TYPE | grep -v 21686148-6449-6e6f-744e-656564454649");
-                match helper::run_fun(&command) {
-                    Ok(partitions) => {
-                        for  line in  partitions.lines() {
-                            let fields: Vec<String> = Self::split_fields(line); 
-                            let partition_number = fields[0].to_string();
-                            let partition_type = fields[1].to_string();
-                            // partition_numbers_types.push((partition_number, uuid_type_map.get(partition_type.as_str()).to_owned().unwrap_or(&"Unknown").to_string()));
-                             partition_numbers_types.push((partition_number, uuid_type_map.get(partition_type.as_str()).to_owned().unwrap_or(&"Unknown").to_string()));
-                        }
-                        debug!(
-                            "Partition numbers and types collected via partx: {:#?}",
-                            &partition_numbers_types
-                        );
-                        partition_numbers_types
-                    }
-                    Err(e) => {
-                        error!("Error getting disk info for disk {} with the help of parted : {}. ALAR is not able to proceed. Exiting.", disk_path, e);
-                        process::exit(1);
-                    }
+                Err(mbr_error) => {
+                    return Err(anyhow!(
+                        "Error getting disk info for disk {disk_path}: {gpt_error:#}; {mbr_error:#}"
+                    ));
                 }
-            }
-            Err(e) => {
-                error!("Error checking for sgdisk presence: {} A general error occured. ALAR is not able to proceed. Exiting.", e);
-                process::exit(1);
-            }
-        }
+            },
+        };
+        let partition_numbers_types: Vec<(String, String, String)> = partitions
+            .into_iter()
+            .filter(|(_, part_type, _)| part_type != "EF02" && part_type != "8200")
+            .collect();
+        debug!(
+            "Partition numbers and types collected from the partition table: {:#?}",
+            &partition_numbers_types
+        );
+        Ok(partition_numbers_types)
     }
 
-    fn get_relevant_recover_partition_information(cli_info: &CliInfo) -> Vec<(String, String)> {
-        let recover_disk = helper::get_recovery_disk_path(cli_info);
+    fn get_relevant_recover_partition_information(cli_info: &CliInfo) -> Result<Vec<(String, String, String)>> {
+        let recover_disk = helper::get_recovery_disk_path(cli_info)?;
         Self::get_partitions_and_types(&recover_disk)
     }
 
-    // Check whether LVM is in use on the repair VM
-    fn is_lvm_on_repair_disk() -> bool {
-        match helper::run_fun("findmnt | grep mapper | grep usr") {
-            Ok(output) => !output.trim().is_empty(),
-            Err(_) => false,
-        }
+    // Returns the type the superblock reveals, or an empty string if no known signature is found
+    pub(crate) fn get_partition_filesystem(partition_path: &str) -> Result<String> {
+        Ok(probe::probe_path(partition_path)?
+            .map(|probe| probe.fstype.to_string())
+            .unwrap_or_default())
     }
 
-    // The logic in this function is intended to verify whther the repair VM is a RHEL 7.x or 8.x version
-    // Only they can be used. With newer versions the functionality is broken to repair a LVM on a LVM based repair VM
-    fn is_repairvm_with_lvm_allowed() -> bool {
-        if Self::is_lvm_on_repair_disk() {
-            match helper::get_repair_os_version() {
-                Ok(version) => {
-                    let local_os_version = version.trim();
-                    debug!("Repair VM OS version detected as: {}", local_os_version);
-                    if local_os_version.starts_with("7.") || local_os_version.starts_with("8.") {
-                        // allowed
-                        info!("LVM detected on the recovery VM disk. ALAR is able to handle LVM based recovery disks.");
-                        true
-                    } else {
-                        error!("LVM detected on the recovery VM disk. However, the repair VM OS version is {}. Only RHEL 7.x and 8.x are supported for LVM based recovery disks. Exiting.", local_os_version);
-                        false
-                    }
-                }
-                Err(e) => {
-                    error!("Error getting repair VM OS version: {}. Exiting.", e);
-                    // We are cautious here and don't allow LVM based recovery disks if we can't determine the OS version
-                    false
-                }
-            }
+    // In dry-run mode the partitions are mounted read-only and the journal replay is suppressed.
+    // Otherwise the mount alone would already modify the disk to be recovered.
+    fn mount_assert(source: &str, destination: &str, fstype: &str, cli_info: &CliInfo, is_relaxed: bool) -> Result<()> {
+        if cli_info.dry_run {
+            let options = match fstype {
+                "xfs" => "nouuid,norecovery",
+                "ext3" | "ext4" => "noload",
+                "btrfs" => "nologreplay",
+                _ => "",
+            };
+            mount::mount_readonly(source, destination, options, false)
         } else {
-            // no LVM in use on repair disk
-            true
+            let options = if fstype == "xfs" { "nouuid" } else { "" };
+            mount::mount(source, destination, options, is_relaxed)
         }
     }
 
-    pub(crate) fn get_partition_filesystem(partition_path: &str) -> Result<String> {
-        // Need to remove the partition suffix for NVMe or NBD disks as the partition type detection commands expect the disk path without the suffix
-         let partition_path = if let Some(suffix_stripped) = partition_path.strip_suffix('p') {
-            debug!("Detected NVMe or NBD disk. Removing 'p' suffix for partition type detection commands.");
-            suffix_stripped
-         } else {
-            partition_path
-        };
+    // Mounts with the given options, read-only in dry-run mode. A read-only btrfs mount still replays the log tree.
+    fn mount_assert_btrfs(source: &str, destination: &str, options: &str, cli_info: &CliInfo) -> Result<()> {
+        if cli_info.dry_run {
+            mount::mount_readonly(source, destination, &format!("{options},nologreplay"), false)
+        } else {
+            mount::mount(source, destination, options, false)
+        }
+    }
 
-        let command = format!("file -sL {}", partition_path);
-        let command_output_string = helper::run_fun(&command)?.to_lowercase();
-
-        match command_output_string.as_str() {
-            s if s.contains("xfs") => Ok("xfs".to_string()),
-            s if s.contains("ext4") => Ok("ext4".to_string()),
-            s if s.contains("ext2") => Ok("ext2".to_string()),
-            s if s.contains("ext3") => Ok("ext3".to_string()),
-            s if s.contains("lvm2") => Ok("LVM2_member".to_string()),
-            s if s.contains("btrfs") => Ok("btrfs".to_string()),
-            s if s.contains("zfs") => Ok("zfs".to_string()),
-            s if s.contains("crypt") => Ok("crypt".to_string()),
-            s if s.contains("fat") => Ok("vfat".to_string()),
-            _ => Ok("".to_string()),
+    fn fsck_unless_dry_run(partition_path: &str, cli_info: &CliInfo) -> Result<()> {
+        if cli_info.dry_run {
+            debug!("Dry-run: skipping fsck for {partition_path}");
+            return Ok(());
         }
+        mount::fsck_partition(partition_path, cli_info.fsck_policy)
+    }
+
+    // A disk without partition table may carry a filesystem, a LVM PV or a LUKS container directly.
+    // It is handled like a single partition with the number 0, its device is the disk itself.
+    fn get_whole_disk_details(disk_path: &str) -> Result<Option<PartInfo>> {
+        Self::get_whole_disk_details_in(Path::new(blockdev::SYS_CLASS_BLOCK), disk_path)
     }
 
-    fn get_partition_details(cli_info: &CliInfo) -> Vec<PartInfo> {
+    fn get_whole_disk_details_in(sys_class_block: &Path, disk_path: &str) -> Result<Option<PartInfo>> {
+        let Some(probe) = probe::probe_path(disk_path)? else {
+            return Ok(None);
+        };
+        let part_type = match probe.fstype {
+            probe::FsType::Lvm2Member => "8E00",
+            probe::FsType::Luks1 | probe::FsType::Luks2 => "8309",
+            fstype if fstype.is_filesystem() => "8300",
+            _ => return Ok(None),
+        };
+        let disk_name = blockdev::disk_name_in(sys_class_block, disk_path)?;
+        let sysfs_path = sys_class_block.join(disk_name).display().to_string();
+        info!("{disk_path} has no partition table. It carries {} on the whole disk.", probe.fstype);
+
+        Ok(Some(PartInfo {
+            number: 0,
+            device: disk_path.to_string(),
+            dev: fs::read_to_string(format!("{sysfs_path}/dev"))?.trim().to_string(),
+            sysfs_path,
+            part_type: part_type.to_string(),
+            fstype: probe.fstype.to_string(),
+            fs_uuid: probe.uuid,
+            fs_label: probe.label,
+            fs_features: probe.features,
+            ..PartInfo::default()
+        }))
+    }
+
+    fn get_partition_details(cli_info: &CliInfo) -> Result<Vec<PartInfo>> {
         let mut recover_partitions: Vec<PartInfo> = Vec::new();
-        let partition_numbers_types = Self::get_relevant_recover_partition_information(cli_info);
+        let recovery_disk_path = helper::get_recovery_disk_path(cli_info)?;
+        let partition_numbers_types = match Self::get_relevant_recover_partition_information(cli_info) {
+            Ok(partition_numbers_types) => partition_numbers_types,
+            Err(e) => match Self::get_whole_disk_details(&recovery_disk_path)? {
+                Some(whole_disk) => return Ok(vec![whole_disk]),
+                None => return Err(e),
+            },
+        };
+        let kernel_partitions = blockdev::partitions(&recovery_disk_path)?;
 
-        for (partition_number, partition_type) in partition_numbers_types.iter() {
+        for (partition_number, partition_type, part_uuid) in partition_numbers_types.iter() {
             let number = partition_number.to_string().parse::<i32>().unwrap();
             let part_type = partition_type.to_string();
 
-            let partition_path = format!("{}{}", helper::get_recovery_disk_path(cli_info), number);
-            let mut partition_fstype = if let Ok(pfs) =
-                Self::get_partition_filesystem(&partition_path)
-            {
-                pfs
-            } else {
-                error!("Not able to determine the partition filesystem. ALAR is not able to proceed. Exiting.");
+            let kernel_partition = kernel_partitions
+                .iter()
+                .find(|partition| partition.number as i32 == number)
+                .ok_or_else(|| {
+                    anyhow!("Partition {number} of {recovery_disk_path} is not known to the kernel. Please run 'partprobe {recovery_disk_path}' and try again")
+                })?;
+            let partition_path = kernel_partition.device.clone();
+            let Ok(probe) = probe::probe_path(&partition_path) else {
                 let _ = telemetry::send_envelope(&telemetry::create_exception_envelope(
                     telemetry::SeverityLevel::Error,
                     "ALAR EXCEPTION",
                     "Not able to determine the partition filesystem.",
-                    "Distro::get_partition_details() -> get_partition_filesystem() returned error",
+                    "Distro::get_partition_details() -> probe::probe_path() returned error",
                     cli_info,
                     &Distro::default(),
                 ));
-                process::exit(1);
+                return Err(anyhow!("Not able to determine the filesystem of {partition_path}"));
             };
 
-            // An empty filesystem info is a hint that the partition is usual encrypted if ADE is in use
-            if partition_fstype.is_empty() {
-                partition_fstype = "crypt?".to_string();
-            } else {
-                partition_fstype = partition_fstype.trim().to_string();
-            }
-
-            recover_partitions.push(PartInfo {
+            // No known signature is a hint that the partition is usual encrypted if ADE is in use.
+            // ADE keeps the LUKS header of the root partition on the boot partition.
+            let partition = PartInfo {
                 number,
+                device: partition_path,
+                dev: kernel_partition.dev.clone(),
+                sysfs_path: kernel_partition.sysfs_path.clone(),
                 part_type,
-                fstype: partition_fstype,
-                contains_os: false,
-                logical_volumes: LogicalVolumesType::None,
-            });
+                part_uuid: Some(part_uuid.clone()),
+                ..PartInfo::default()
+            };
+            let partition = match probe {
+                Some(probe) => PartInfo {
+                    fstype: probe.fstype.to_string(),
+                    fs_uuid: probe.uuid,
+                    fs_label: probe.label,
+                    fs_features: probe.features,
+                    ..partition
+                },
+                None => PartInfo {
+                    fstype: "crypt?".to_string(),
+                    ..partition
+                },
+            };
+            recover_partitions.push(partition);
+        }
+        Ok(recover_partitions)
+    }
+
+    // The LV types decide what gets activated, checked and mounted. In dry-run mode the LVs are only listed.
+    fn prepare_logical_volumes(volume_group: &mut lvm::VolumeGroup, cli_info: &CliInfo) -> Result<()> {
+        let vg_name = volume_group.active_name().to_string();
+        volume_group.logical_volumes = lvm::logical_volumes(&vg_name).unwrap_or_else(|e| {
+            warn!("The LVs of {vg_name} can't be listed, their types are unknown: {e:#}");
+            Vec::new()
+        });
+        debug!("LVs of {vg_name}: {:#?}", volume_group.logical_volumes);
+        if !cli_info.dry_run {
+            lvm::activate_stacks(&vg_name, &volume_group.logical_volumes, cli_info.fsck_policy)
+                .with_context(|| format!("The thin or cache pools of {vg_name} can't be used"))?;
         }
-        recover_partitions
+        Ok(())
     }
 
     fn build_logical_volume_details(
         part: &mut [PartInfo],
         cli_info: &CliInfo,
         distro: &mut Distro,
-    ) {
+    ) -> Result<()> {
         let mut lv: Vec<LogicalVolume> = Vec::new();
         // The command 'vgchange -ay' is required to make the logical volumes available which are residing on the partition. i
         // Otherwise we won't be able to get details on those logical volumes.
-        let _ = helper::run_cmd("vgchange -ay");
-
-        part.iter_mut()
-            .filter(|lvm| lvm.part_type.contains("8E00"))
-            .for_each(|part| {
-                let lvm_partition = format!(
-                    "{}{}",
-                    helper::get_recovery_disk_path(cli_info),
-                    part.number
-                );
-
-                match mount::importvg(cli_info, part.number) {
-                    Ok(_) => {}
-                    Err(e) => {
-                        error!("Error importing VG: {e}");
-                        process::exit(1);
+        // In dry-run mode nothing gets activated, only the LVs which are active already are looked into.
+        if !cli_info.dry_run {
+            let _ = helper::run_cmd("vgchange -ay");
+        }
+        let recovery_disk_path = helper::get_recovery_disk_path(cli_info)?;
+        let recovery_disks = helper::get_recovery_disk_paths(cli_info)?;
+
+        for part in part.iter_mut().filter(|lvm| lvm.part_type.contains("8E00")) {
+            let lvm_partition = part.device.clone();
+
+            // A VG spanning several PVs is imported once, as a whole
+            let is_known_vg = distro
+                .volume_groups
+                .iter()
+                .any(|volume_group| volume_group.pvs.contains(&lvm_partition));
+            if !is_known_vg {
+                let volume_group = lvm::volume_group_of(&lvm_partition, &recovery_disks, cli_info.dry_run)
+                    .with_context(|| format!("The VG of {lvm_partition} can't be determined. {METADATA_HINT}"));
+                // Without the private LVM configuration a repair VM using a devices file doesn't see the PVs
+                let mut volume_group = match volume_group {
+                    Err(e) if cli_info.dry_run => {
+                        info!("Dry-run: {e:#}");
+                        continue;
                     }
+                    volume_group => volume_group?,
+                };
+                lvm::verify_complete(&volume_group, cli_info.allow_partial_vg)?;
+                if volume_group.is_partial() && !cli_info.dry_run {
+                    let _ = helper::run_cmd(&format!(
+                        "vgchange -ay --activationmode partial --select vg_uuid={}",
+                        volume_group.uuid
+                    ));
                 }
 
-                if log::log_enabled!(log::Level::Debug) {
-                    let lvscan = helper::run_fun("lvscan").unwrap();
-                    debug!("lvscan after running importvg ");
-                    let _ = &lvscan.lines().for_each(|line| debug!("{:#?}", line));
+                // In dry-run mode the VG is neither imported nor renamed. The plan lists those steps instead.
+                if !cli_info.dry_run {
+                    mount::importvg(cli_info, &volume_group)
+                        .with_context(|| format!("Error importing VG. {METADATA_HINT}"))?;
+                    // The VG of the recovery disk gets deactivated again at the end, whatever name it carries now
+                    teardown::register(Step::DeactivateVg(mount::vg_name_of(&lvm_partition)?));
                 }
+                Self::prepare_logical_volumes(&mut volume_group, cli_info)?;
+                distro.volume_groups.push(volume_group);
+            }
 
-                let lv_detail = helper::run_fun(&format!(
-                    "lsblk -ln {} -o NAME,FSTYPE | sed '1d'",
-                    lvm_partition
-                ));
+            if log::log_enabled!(log::Level::Debug) {
+                let lvscan = helper::run_fun("lvscan").context("lvscan failed to list the LVs after the VG import")?;
+                debug!("lvscan after running importvg ");
+                let _ = &lvscan.lines().for_each(|line| debug!("{:#?}", line));
+            }
 
-                let lv_detail_string =
-                    lv_detail.expect("lsblk shouldn't raise an error when getting fs information");
-                debug!(
-                    "build_logical_volume_details: lv_detail_string: {:#?}",
-                    &lv_detail_string
-                );
+            let lv_detail = helper::run_fun(&format!(
+                "lsblk -ln {} -o NAME,FSTYPE | sed '1d'",
+                lvm_partition
+            ));
 
-                let recovery_disk_name = helper::get_recovery_disk_path(cli_info).split_off(5);
-                for line in lv_detail_string.lines() {
-                    if line.contains(&recovery_disk_name) {
-                        continue;
-                    }
-                    let mut v: Vec<&str> = line.trim().split(' ').collect();
-                    v.retain(|&x| !x.is_empty());
+            let lv_detail_string =
+                lv_detail.context("lsblk shouldn't raise an error when getting fs information")?;
+            debug!(
+                "build_logical_volume_details: lv_detail_string: {:#?}",
+                &lv_detail_string
+            );
 
-                    lv.push(LogicalVolume {
-                        name: v[0].to_string(),
-                        fstype: v[1].to_string(),
-                    });
+            let recovery_disk_name = blockdev::disk_name(&recovery_disk_path)?;
+            for line in lv_detail_string.lines() {
+                if line.contains(&recovery_disk_name) {
+                    continue;
                 }
-                part.logical_volumes = LogicalVolumesType::Some(lv.clone());
-                distro.is_lvm = true;
-            });
+                let mut fields = line.split_whitespace();
+                let Some(name) = fields.next() else {
+                    continue;
+                };
+
+                // An LV spanning several PVs is listed below each of them
+                if lv.iter().any(|known| known.name == name) {
+                    continue;
+                }
+                if !lvm::is_mountable_dm_name(name, &distro.volume_groups) {
+                    debug!("{name} is a pool or a layer of a pool or cache, it doesn't get mounted");
+                    continue;
+                }
+                lv.push(LogicalVolume {
+                    name: name.to_string(),
+                    fstype: fields.next().unwrap_or_default().to_string(),
+                });
+            }
+            part.logical_volumes = LogicalVolumesType::Some(lv.clone());
+            distro.is_lvm = true;
+        }
+        Ok(())
     }
 
     /**
@@ -407,16 +482,13 @@ impl Distro {
         partitions: &mut Vec<PartInfo>,
         cli_info: &CliInfo,
         distro: &mut Distro,
-    ) -> Option<DistroNameVersion> {
-        let recovery_disk_path = helper::get_recovery_disk_path(cli_info);
+    ) -> Result<Option<DistroNameVersion>> {
+        let recovery_disk_path = helper::get_recovery_disk_path(cli_info)?;
 
         debug!("recovery_disk_path: {}", recovery_disk_path);
         debug!("what_distro: Partitions to be processed: {:#?}", partitions);
 
-        if mount::mkdir_assert().is_err() {
-            error!("Error creating assert dir. ALAR is not able to proceed. Exiting.");
-            process::exit(1);
-        }
+        mount::mkdir_assert().context("Error creating assert dir")?;
 
         // cycling through each of the partitions to figure out what sort of partition we do have
         for partition in partitions {
@@ -426,70 +498,42 @@ impl Distro {
             }
 
             if partition.part_type.contains("8E00") && partition.fstype == "LVM2_member" {
-                // Due to issues with RHEL above version 9.x we need to check whether the repair VM is allowed to use LVM based recovery disks
-                if !Self::is_repairvm_with_lvm_allowed() {
-                    let _ = telemetry::send_envelope(&telemetry::create_exception_envelope(
-                    telemetry::SeverityLevel::Error,
-                    "ALAR EXCEPTION",
-                      "LVM based recovery disks are not supported on repair VMs with OS version >= 9.x.",
-                        "Distro::is_repairvm_allowed_to_use_lvm() -> get_repair_os_version() returned >= 9.x",
-                        cli_info,
-                        distro,
-                ));
-                    process::exit(1);
-                }
-
                 debug!("Found LVM partition. Executing read_distro_name_version_from_lv");
-                return Self::read_distro_name_version_from_lv(partition, distro.is_ade, cli_info);
+                return Self::read_distro_name_version_from_lv(partition, distro, cli_info);
             }
 
             // Above we handle any kind of LVM partition including an encrypted one.
             // Below we handle the rest of the non-LVM partitions including one which resides on an encrypted device.
 
-            fn error_condition_mount(e: anyhow::Error) {
-                error!("Error mounting partition: {e}");
-                process::exit(1);
-            }
-
-            fn error_condition_umount(e: anyhow::Error) {
-                error!("Error umounting partition: {e}, this may cause side effects");
-                process::exit(1);
-            }
-
-            fn nouuid_option(fstype: &str) -> &str {
-                if fstype == "xfs" {
-                    "nouuid"
-                } else {
-                    ""
-                }
-            }
-
-            let mount_path = format!("{}{}", recovery_disk_path, partition.number);
+            let mount_path = partition.device.clone();
 
             debug!(
                 "Mounting partition number {} to {}",
                 partition.number, &mount_path
             );
 
+            let mut btrfs_layout = None;
             match partition.fstype.as_str() {
                 fs if fs == "xfs" || fs == "ext4" => {
-                    match mount::fsck_partition(&mount_path) {
-                        Ok(_) => {}
-                        Err(e) => {
-                            error!("Error fscking partition: {e}");
-                            process::exit(1);
-                        }
-                    }
-
-                    match mount::mount(
-                        &mount_path,
-                        constants::ASSERT_PATH,
-                        nouuid_option(fs),
-                        false,
-                    ) {
-                        Ok(_) => {}
-                        Err(e) => error_condition_mount(e),
-                    }
+                    Self::fsck_unless_dry_run(&mount_path, cli_info)
+                        .context("Error fscking partition")?;
+                    Self::mount_assert(&mount_path, constants::ASSERT_PATH, fs, cli_info, false)
+                        .context("Error mounting partition")?;
+                }
+                // The root of the system is the default subvolume, not the top-level one
+                "btrfs" => {
+                    Self::fsck_unless_dry_run(&mount_path, cli_info)
+                        .context("Error fscking partition")?;
+                    let layout = btrfs::inspect(&mount_path, constants::ASSERT_PATH, cli_info.dry_run)
+                        .context("Error reading the btrfs subvolumes")?;
+                    Self::mount_assert_btrfs(&mount_path, constants::ASSERT_PATH, &layout.root_option(), cli_info)
+                        .context("Error mounting partition")?;
+                    btrfs_layout = Some(layout);
+                }
+                // In dry-run mode the encrypted device isn't opened, thus there is nothing to look into
+                "crypt?" if cli_info.dry_run => {
+                    info!("Dry-run: the encrypted partition {} is not unlocked.", partition.number);
+                    continue;
                 }
                 // If the partition is marked as 'crypt?' the partition path needs to be corrected
                 "crypt?" => {
@@ -498,37 +542,22 @@ impl Distro {
                         Self::get_partition_filesystem(partition_path).unwrap_or("xfs".to_string());
                     debug!("Filesystem type for the encrypted partition is: {}", fstype);
 
-                    match mount::fsck_partition(partition_path) {
-                        Ok(_) => {}
-                        Err(e) => {
-                            error!("Error fscking partition: {e}");
-                            process::exit(1);
-                        }
-                    }
-
-                    match mount::mount(
+                    mount::fsck_partition(partition_path, cli_info.fsck_policy)
+                        .context("Error fscking partition")?;
+                    Self::mount_assert(
                         partition_path,
                         constants::ASSERT_PATH,
-                        nouuid_option(&fstype),
+                        &fstype,
+                        cli_info,
                         false,
-                    ) {
-                        Ok(_) => {}
-                        Err(e) => error_condition_mount(e),
-                    }
+                    )
+                    .context("Error mounting partition")?;
                 }
                 _ => {
-                    match mount::fsck_partition(&mount_path) {
-                        Ok(_) => {}
-                        Err(e) => {
-                            error!("Error fscking partition: {e}");
-                            process::exit(1);
-                        }
-                    }
-
-                    match mount::mount(&mount_path, constants::ASSERT_PATH, "", false) {
-                        Ok(_) => {}
-                        Err(e) => error_condition_mount(e),
-                    }
+                    Self::fsck_unless_dry_run(&mount_path, cli_info)
+                        .context("Error fscking partition")?;
+                    Self::mount_assert(&mount_path, constants::ASSERT_PATH, &partition.fstype, cli_info, false)
+                        .context("Error mounting partition")?;
                 }
             }
 
@@ -536,6 +565,9 @@ impl Distro {
                 // If we have found this file we can be sure this is the OS partition
                 partition.activate_is_os();
 
+                distro.btrfs = btrfs_layout;
+                distro.fstab = fstab::read(constants::ASSERT_FSTAB);
+
                 let mut _name = "".to_string();
                 let mut _version_id = "".to_string();
                 //unwrap is safe here because we checked if the file exists
@@ -572,10 +604,7 @@ impl Distro {
                     distro.architecture = Architecture::X86_64;
                 }
 
-                match mount::umount(constants::ASSERT_PATH, false) {
-                    Ok(_) => {}
-                    Err(e) => error_condition_umount(e),
-                }
+                mount::umount(constants::ASSERT_PATH, false).context("Error umounting partition")?;
 
                 if mount::rmdir(constants::ASSERT_PATH).is_ok() {
                     info!("Removed assert path");
@@ -583,16 +612,13 @@ impl Distro {
                     error!("Error removing directory ASSER_PATH");
                 }
 
-                return Some(DistroNameVersion {
+                return Ok(Some(DistroNameVersion {
                     name: _name,
                     //version_id: _version_id.parse::<f32>().unwrap(),
                     version_id: _version_id,
-                });
-            }
-            match mount::umount(constants::ASSERT_PATH, false) {
-                Ok(_) => {}
-                Err(e) => error_condition_umount(e),
+                }));
             }
+            mount::umount(constants::ASSERT_PATH, false).context("Error umounting partition")?;
         }
 
         if mount::rmdir(constants::ASSERT_PATH).is_ok() {
@@ -602,7 +628,7 @@ impl Distro {
         }
         // If we reach this point we haven't found the OS partition
         // which could point out to operate on a data disk.
-        None
+        Ok(None)
     }
 
     fn ade_set_no_lvm_partiton_fs(partitions: &mut [PartInfo]) {
@@ -618,9 +644,9 @@ impl Distro {
 
     fn read_distro_name_version_from_lv(
         partinfo: &mut PartInfo,
-        is_ade: bool,
+        distro: &mut Distro,
         cli_info: &CliInfo,
-    ) -> Option<DistroNameVersion> {
+    ) -> Result<Option<DistroNameVersion>> {
         let volumes = &partinfo.logical_volumes;
         let mut _name = "".to_string();
         let mut _version_id = "".to_string();
@@ -631,8 +657,12 @@ impl Distro {
         );
 
         if let LogicalVolumesType::Some(lv) = volumes {
+            // Without importing the VG the LVs may not be visible if the names collide with the ones of the repair VM
+            if lv.is_empty() && cli_info.dry_run {
+                info!("Dry-run: no LV details available without importing the VG.");
+                return Ok(None);
+            }
             if lv.is_empty() {
-                error!("No rootlv found in LVM. This is a not supported LVM setup. ALAR is not able to proceed. Exiting.");
                 let _ = telemetry::send_envelope(&telemetry::create_exception_envelope(telemetry::SeverityLevel::Error,
                     "ALAR EXCEPTION",
                      "No rootlv found in LVM.",
@@ -640,70 +670,38 @@ impl Distro {
                      cli_info,
                      &Distro::default(),
                 ));
-                process::exit(1);
+                return Err(anyhow!("No rootlv found in LVM. This is a not supported LVM setup."));
+            }
+            // Find the rootlv and mount it. The VG carries its original name, also after a clone-import.
+            for volume in lv.iter().filter(|volume| lvm::is_root_lv(&volume.name)) {
+                let partition_path = &format!("/dev/mapper/{}", volume.name);
+
+                Self::fsck_unless_dry_run(partition_path, cli_info)
+                    .context("Error fscking the root LV")?;
+                Self::mount_assert(
+                    partition_path,
+                    constants::ASSERT_PATH,
+                    &volume.fstype,
+                    cli_info,
+                    false,
+                )
+                .context("Error mounting the root LV")?;
             }
-            // Find the rootlv and mount it
-            lv.iter()
-                .filter(|volume| volume.name.contains("rootlv"))
-                .for_each(|volume| {
-                    let mount_option = if volume.fstype == "xfs" { "nouuid" } else { "" };
-
-                    let partition_path = if is_ade {
-                        constants::RESCUE_ADE_ROOTLV
-                    } else {
-                        constants::ROOTVG_ROOTLV
-                    };
-
-                    match mount::fsck_partition(partition_path) {
-                        Ok(_) => {}
-                        Err(e) => {
-                            error!("Error fscking rescuevg-rootlv: {e}");
-                            process::exit(1);
-                        }
-                    }
-                    if mount::mount(partition_path, constants::ASSERT_PATH, mount_option, false)
-                        .is_err()
-                    {
-                        error!(
-                            "Error mounting rescue-rootlv. ALAR is not able to proceed. Exiting."
-                        );
-                        process::exit(1);
-                    }
-                });
             // Find the usrlv and mount it
-            lv.iter()
-                .filter(|volume| volume.name.contains("usrlv"))
-                .for_each(|volume| {
-                    let mount_option = if volume.fstype == "xfs" { "nouuid" } else { "" };
-
-                    let partition_path = if is_ade {
-                        constants::RESCUE_ADE_USRLV
-                    } else {
-                        constants::ROOTVG_USRLV
-                    };
-
-                    match mount::fsck_partition(partition_path) {
-                        Ok(_) => {}
-                        Err(e) => {
-                            error!("Error fscking rescuevg-usrlv: {e}");
-                            process::exit(1);
-                        }
-                    }
-
-                    if mount::mount(
-                        partition_path,
-                        constants::ASSERT_PATH_USR,
-                        mount_option,
-                        true,
-                    )
-                    .is_err()
-                    {
-                        error!(
-                            "Error mounting rescue-usrlv. ALAR is not able to proceed. Exiting."
-                        );
-                        process::exit(1);
-                    }
-                });
+            for volume in lv.iter().filter(|volume| lvm::is_usr_lv(&volume.name)) {
+                let partition_path = &format!("/dev/mapper/{}", volume.name);
+
+                Self::fsck_unless_dry_run(partition_path, cli_info)
+                    .context("Error fscking the usr LV")?;
+                Self::mount_assert(
+                    partition_path,
+                    constants::ASSERT_PATH_USR,
+                    &volume.fstype,
+                    cli_info,
+                    true,
+                )
+                .context("Error mounting the usr LV")?;
+            }
 
             if let Ok(file_content) = fs::read_to_string(constants::OS_RELEASE) {
                 for line in file_content.lines() {
@@ -724,68 +722,80 @@ impl Distro {
                     }
                 }
                 partinfo.activate_is_os();
+                distro.fstab = fstab::read(constants::ASSERT_FSTAB);
             } else {
-                error!("Error reading os-release file. ALAR is not able to proceed. Exiting.");
-                process::exit(1);
+                return Err(anyhow!("Error reading the os-release file"));
             }
 
-            if mount::umount(constants::ASSERT_PATH, true).is_err() {
-                error!("Error umounting rescue-rootlv. This may cause side effects. ALAR is not able to proceed. Exiting.");
-                process::exit(1);
-            }
-            return Some(DistroNameVersion {
+            mount::umount(constants::ASSERT_PATH, true).context("Error umounting rescue-rootlv")?;
+            return Ok(Some(DistroNameVersion {
                 name: _name,
                 version_id: _version_id,
-            });
+            }));
         }
-        None
+        Ok(None)
     }
 
     fn is_fs_crypt_detected(partitions: &[PartInfo]) -> bool {
         partitions.iter().any(|part| part.fstype == "crypt?")
     }
 
-    fn enable_ade(cli_info: &mut CliInfo, partition_details: &mut [PartInfo], distro: &mut Distro) {
-        match ade::prepare_ade_environment(cli_info, partition_details).is_err() {
-            true => {
-                error!("Error preparing ADE environment. ALAR is not able to proceed. Exiting.");
-                let _ = telemetry::send_envelope(&telemetry::create_exception_envelope(
-                    telemetry::SeverityLevel::Error,
-                    "ALAR EXCEPTION",
-                    "Error preparing ADE environment. Probably the ADE key secret isn't correct.",
-                    "Distro::enable_ade() -> ade::prepare_ade_environment() returned error",
-                    cli_info,
-                    distro,
-                ));
-                process::exit(1);
-            }
-            false => {
-                // No error arm
-
-                distro.set_is_ade(true);
-                // if the crypt partition contains a LVM signature we need to import the volumegroup
-                partition_details
-                    .iter()
-                    .filter(|x| x.fstype == "crypt?")
-                    .for_each(|part| match part.part_type.as_str() {
-                        "8E00" => match ade::ade_importvg() {
-                            Ok(_) => {}
-                            Err(e) => {
-                                error!("Error importing ADE VG: {e}");
-                                process::exit(1);
-                            }
-                        },
-                        "8300" => {}
-                        _ => {
-                            error!("Unknown partition type. ALAR is not able to proceed. Exiting.");
-                            process::exit(1);
-                        }
-                    });
+    fn enable_ade(cli_info: &mut CliInfo, partition_details: &mut [PartInfo], distro: &mut Distro) -> Result<()> {
+        if let Err(e) = ade::prepare_ade_environment(cli_info, partition_details) {
+            let _ = telemetry::send_envelope(&telemetry::create_exception_envelope(
+                telemetry::SeverityLevel::Error,
+                "ALAR EXCEPTION",
+                "Error preparing ADE environment. Probably the ADE key secret isn't correct.",
+                "Distro::enable_ade() -> ade::prepare_ade_environment() returned error",
+                cli_info,
+                distro,
+            ));
+            return Err(e.context("Error preparing ADE environment"));
+        }
+
+        distro.set_is_ade(true);
+        // if the crypt partition contains a LVM signature we need to import the volumegroup
+        for part in partition_details.iter().filter(|x| x.fstype == "crypt?") {
+            match part.part_type.as_str() {
+                "8E00" => {
+                    let mut volume_group = ade::ade_importvg(cli_info).context("Error importing ADE VG")?;
+                    Self::prepare_logical_volumes(&mut volume_group, cli_info)?;
+                    distro.volume_groups.push(volume_group);
+                }
+                "8300" => {}
+                part_type => return Err(anyhow!("Unknown partition type {part_type}")),
             }
         }
+        Ok(())
+    }
+
+    // After the unlock the container is handled like an ADE partition, which is marked as 'crypt?'
+    fn enable_whole_disk_luks(cli_info: &mut CliInfo, partition_details: &mut [PartInfo], distro: &mut Distro) -> Result<()> {
+        let Some(disk) = partition_details.iter_mut().find(|part| Self::is_whole_disk_luks(part)) else {
+            return Ok(());
+        };
+        ade::open_whole_disk_luks(cli_info, &disk.device).context("Error opening the LUKS container")?;
+        distro.set_is_ade(true);
+        disk.fstype = "crypt?".to_string();
+
+        let content = probe::probe_path(constants::ADE_OSENCRYPT_PATH)?;
+        if content.is_some_and(|probe| probe.fstype == probe::FsType::Lvm2Member) {
+            disk.part_type = "8E00".to_string();
+            let mut volume_group =
+                ade::ade_importvg(cli_info).context("Error importing the VG of the LUKS container")?;
+            Self::prepare_logical_volumes(&mut volume_group, cli_info)?;
+            distro.volume_groups.push(volume_group);
+        } else {
+            disk.part_type = "8300".to_string();
+        }
+        Ok(())
     }
 
-    fn ade_prepare_lv(partition_details: &mut [PartInfo], distro: &mut Distro) {
+    fn is_whole_disk_luks(part: &PartInfo) -> bool {
+        part.is_whole_disk() && part.fstype == probe::FsType::Luks1.to_string()
+    }
+
+    fn ade_prepare_lv(partition_details: &mut [PartInfo], distro: &mut Distro) -> Result<()> {
         info!(
             "ADE is enabled. Collecting LV details from the ADE disk if an LVM signature is found."
         );
@@ -797,7 +807,7 @@ impl Distro {
         // if the partition is not a LVM partition we don't need to proceed
         if !crypt_partition.part_type.contains("8E00") {
             info!("No LVM partition found on the ADE disk.");
-            return;
+            return Ok(());
         } else {
             crypt_partition.fstype = "LVM2_member".to_string();
             distro.is_lvm = true;
@@ -805,20 +815,11 @@ impl Distro {
 
         let mut lv: Vec<LogicalVolume> = Vec::new();
 
-        #[allow(unused_assignments)]
-        let mut lv_detail_string: String = String::with_capacity(64);
-        match helper::run_fun(&format!(
+        let lv_detail_string = helper::run_fun(&format!(
             "lsblk -ln {} -o NAME,FSTYPE | sed '1d'",
             constants::ADE_OSENCRYPT_PATH
-        )) {
-            Ok(value) => {
-                lv_detail_string = value;
-            }
-            Err(e) => {
-                error!("Error getting LV details from ADE disk: {e}");
-                process::exit(1);
-            }
-        };
+        ))
+        .context("Error getting LV details from ADE disk")?;
 
         debug!(
             " ade_prepare_lv :: lv_detail_string: {:?}",
@@ -826,12 +827,18 @@ impl Distro {
         );
 
         for line in lv_detail_string.lines() {
-            let mut v: Vec<&str> = line.trim().split(' ').collect();
-            v.retain(|&x| !x.is_empty());
+            let mut fields = line.split_whitespace();
+            let Some(name) = fields.next() else {
+                continue;
+            };
 
+            if !lvm::is_mountable_dm_name(name, &distro.volume_groups) {
+                debug!("{name} is a pool or a layer of a pool or cache, it doesn't get mounted");
+                continue;
+            }
             lv.push(LogicalVolume {
-                name: v[0].to_string(),
-                fstype: v[1].to_string(),
+                name: name.to_string(),
+                fstype: fields.next().unwrap_or_default().to_string(),
             });
         }
         crypt_partition.logical_volumes = LogicalVolumesType::Some(lv);
@@ -840,47 +847,68 @@ impl Distro {
             "LV partition collected on the ADE eneabled disk/partition: {:#?}",
             &partition_details
         );
+        Ok(())
     }
 
     fn set_is_ade(&mut self, is_ade: bool) {
         self.is_ade = is_ade;
     }
 
-    pub fn new(cli_info: &mut cli::CliInfo) -> Distro {
+    pub fn new(cli_info: &mut cli::CliInfo) -> Result<Distro> {
         let mut distro = Distro::default();
-        let mut partition_details = Self::get_partition_details(cli_info);
+        // A repair VM using a LVM devices file doesn't see the PVs of the recovery disks otherwise.
+        // In dry-run mode nothing gets written, the plan lists the private configuration instead.
+        if !cli_info.dry_run {
+            lvm::use_private_config(&helper::get_recovery_disk_paths(cli_info)?)
+                .context("Error preparing the private LVM configuration")?;
+        }
+        let mut partition_details = Self::get_partition_details(cli_info)?;
         debug!(
             "Partition details of the disk to be recovered: {:?}",
             &partition_details
         );
 
+        let is_whole_disk_luks = partition_details.iter().any(Self::is_whole_disk_luks);
+
         // at this point is is still not determined whether, if the fs_type is crypt, the disk needs to manually decrypted
-        if Self::is_fs_crypt_detected(&partition_details) {
+        if is_whole_disk_luks && cli_info.dry_run {
+            info!("Dry-run: the LUKS container on the whole disk is not unlocked.");
+            distro.set_is_ade(true);
+        } else if is_whole_disk_luks {
+            Self::enable_whole_disk_luks(cli_info, &mut partition_details, &mut distro)?;
+            Self::ade_prepare_lv(&mut partition_details, &mut distro)?;
+        } else if Self::is_fs_crypt_detected(&partition_details) && cli_info.dry_run {
+            // The plan describes how the disk would be unlocked. Nothing gets opened here.
+            distro.set_is_ade(true);
+        } else if Self::is_fs_crypt_detected(&partition_details) {
             /*
                The ADE disk gets decrypted and if we find an LVM signature we need to import the VG.
                Also, the LV on it get determined.
             */
-            Self::enable_ade(cli_info, &mut partition_details, &mut distro);
-            Self::ade_prepare_lv(&mut partition_details, &mut distro);
+            Self::enable_ade(cli_info, &mut partition_details, &mut distro)?;
+            Self::ade_prepare_lv(&mut partition_details, &mut distro)?;
         } else {
             /*
                No encrypted disk got detected.
                It is also required to determine the LVs on the disk.
             */
-            Self::build_logical_volume_details(&mut partition_details, cli_info, &mut distro);
+            Self::build_logical_volume_details(&mut partition_details, cli_info, &mut distro)?;
         }
 
         let distro_name = match Self::what_distro_name_version(
             &mut partition_details,
             cli_info,
             &mut distro,
-        ) {
+        )? {
             Some(distro_name) => distro_name,
+            None if cli_info.dry_run => {
+                info!("Dry-run: the distro could not be determined without changing the disk.");
+                DistroNameVersion::default()
+            }
             None => {
                 error!("No OS partition found.");
                 error!("Please make sure the disk isn't a Data-disk.");
                 error!("If you are sure the attached disk is an OS-Disk please report this at: https://github.com/Azure/ALAR/issues.");
-                error!("ALAR isn't able to proceed. Exiting.");
                 let message_details = format!(
                     "No OS partition found during distro detection. Partition details: {:#?}",
                     &partition_details
@@ -893,16 +921,80 @@ impl Distro {
                     cli_info,
                     &distro,
                 ));
-                process::exit(1);
+                return Err(anyhow!("No OS partition found"));
             }
         };
         // Correct the filesystem for a non LVM ADE disk
-        Self::ade_set_no_lvm_partiton_fs(&mut partition_details);
+        if !cli_info.dry_run {
+            Self::ade_set_no_lvm_partiton_fs(&mut partition_details);
+        }
 
         distro.partitions = partition_details;
         distro.distro_name_version = distro_name;
         distro.cli_info = cli_info.clone();
 
-        distro
+        Ok(distro)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(path: &Path, content: &[u8]) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+
+    #[test]
+    fn whole_disk_details() {
+        let root = std::env::temp_dir().join(format!("alar-distro-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let sys_class_block = root.join("sys/class/block");
+
+        // A PV on the whole disk, without partition table
+        let mut pv = vec![0u8; 1024 * 1024];
+        pv[512..520].copy_from_slice(b"LABELONE");
+        pv[512 + 20..512 + 24].copy_from_slice(&32u32.to_le_bytes());
+        pv[512 + 24..512 + 32].copy_from_slice(b"LVM2 001");
+        pv[544..576].copy_from_slice(b"abcdef0123456789abcdef0123456789");
+        let disk = root.join("dev/sdd");
+        write(&disk, &pv);
+        write(&sys_class_block.join("sdd/dev"), b"8:48\n");
+        let disk_path = disk.display().to_string();
+        let partition = Distro::get_whole_disk_details_in(&sys_class_block, &disk_path).unwrap().unwrap();
+        assert_eq!(partition.number, 0);
+        assert_eq!(partition.device, disk_path);
+        assert_eq!(partition.dev, "8:48");
+        assert_eq!(partition.sysfs_path, sys_class_block.join("sdd").display().to_string());
+        assert_eq!(partition.part_type, "8E00");
+        assert_eq!(partition.fstype, "LVM2_member");
+        assert_eq!(partition.fs_uuid.as_deref(), Some("abcdef-0123-4567-89ab-cdef-0123-456789"));
+
+        // A filesystem on the whole disk
+        let mut xfs = vec![0u8; 1024 * 1024];
+        xfs[..4].copy_from_slice(b"XFSB");
+        xfs[108..112].copy_from_slice(b"data");
+        let disk = root.join("dev/sde");
+        write(&disk, &xfs);
+        write(&sys_class_block.join("sde/dev"), b"8:64\n");
+        let partition = Distro::get_whole_disk_details_in(&sys_class_block, &disk.display().to_string()).unwrap().unwrap();
+        assert_eq!(partition.part_type, "8300");
+        assert_eq!(partition.fstype, "xfs");
+        assert_eq!(partition.fs_label.as_deref(), Some("data"));
+
+        // Neither a filesystem nor a container, the disk isn't usable
+        let disk = root.join("dev/sdf");
+        write(&disk, &vec![0u8; 1024 * 1024]);
+        write(&sys_class_block.join("sdf/dev"), b"8:80\n");
+        assert!(Distro::get_whole_disk_details_in(&sys_class_block, &disk.display().to_string()).unwrap().is_none());
+
+        // A partition is refused as the recovery disk
+        let disk = root.join("dev/sdd1");
+        write(&disk, &pv);
+        write(&sys_class_block.join("sdd1/partition"), b"1\n");
+        assert!(Distro::get_whole_disk_details_in(&sys_class_block, &disk.display().to_string()).is_err());
+
+        let _ = fs::remove_dir_all(&root);
     }
 }
@@ -0,0 +1,373 @@
+use log::{debug, warn};
+use serde::Serialize;
+use std::fs;
+use std::path::{Component, Path};
+
+// Reads the fstab of the recovered system and resolves its entries against the partitions and LVs of the recovery disks.
+// Nothing is ever resolved against a device of the repair VM, thus a device path like /dev/sda2 means the partition 2
+// of the recovery disk. This holds only if the root or boot entries name the same disk sda.
+
+// Filesystems which are not stored on a local block device
+const NON_LOCAL_FSTYPES: [&str; 12] = [
+    "swap", "nfs", "nfs4", "cifs", "smb3", "tmpfs", "proc", "sysfs", "devpts", "devtmpfs", "none", "9p",
+];
+// Options which select what gets mounted. Any other option of the fstab is left out, the mount has to succeed in any case.
+const SELECTING_OPTIONS: [&str; 2] = ["subvol=", "subvolid="];
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub(crate) struct FstabEntry {
+    pub(crate) spec: String,
+    pub(crate) mountpoint: String,
+    pub(crate) fstype: String,
+    pub(crate) options: Vec<String>,
+}
+
+impl FstabEntry {
+    fn has_option(&self, option: &str) -> bool {
+        self.options.iter().any(|own| own == option)
+    }
+
+    // Entries of other filesystems than local ones, swap and those which are not mounted at boot are not of interest
+    fn is_local_mount(&self) -> bool {
+        self.mountpoint.starts_with('/')
+            && self.mountpoint != "/"
+            && !NON_LOCAL_FSTYPES.contains(&self.fstype.as_str())
+            && !self.fstype.starts_with("fuse")
+            && !self.has_option("bind")
+            && !self.has_option("noauto")
+            && !self.has_option("_netdev")
+    }
+}
+
+// A partition or LV of the recovery disks as it can be referred to from the fstab
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct Volume {
+    pub(crate) device: String,
+    pub(crate) fstype: String,
+    pub(crate) uuid: Option<String>,
+    pub(crate) label: Option<String>,
+    pub(crate) part_uuid: Option<String>,
+    pub(crate) partition_number: Option<i32>,
+    // The device-mapper name of a LV, i.e. rootvg-homelv
+    pub(crate) lv_name: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct ResolvedMount {
+    pub(crate) device: String,
+    pub(crate) mountpoint: String,
+    pub(crate) options: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct UnresolvedEntry {
+    pub(crate) entry: FstabEntry,
+    pub(crate) reason: String,
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct Resolution {
+    // Sorted by mountpoint, a parent directory comes before its children
+    pub(crate) mounts: Vec<ResolvedMount>,
+    pub(crate) unresolved: Vec<UnresolvedEntry>,
+}
+
+pub(crate) fn parse(content: &str) -> Vec<FstabEntry> {
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let [spec, mountpoint, fstype, rest @ ..] = fields.as_slice() else {
+                return None;
+            };
+            Some(FstabEntry {
+                spec: unescape(spec),
+                mountpoint: unescape(mountpoint),
+                fstype: fstype.to_string(),
+                options: rest
+                    .first()
+                    .map(|options| options.split(',').map(str::to_string).collect())
+                    .unwrap_or_default(),
+            })
+        })
+        .collect()
+}
+
+// Spaces and tabs are written as octal escapes, i.e. \040
+fn unescape(field: &str) -> String {
+    field.replace("\\040", " ").replace("\\011", "\t")
+}
+
+pub(crate) fn read(path: &str) -> Vec<FstabEntry> {
+    match fs::read_to_string(path) {
+        Ok(content) => {
+            let entries = parse(&content);
+            debug!("fstab entries of the recovered system: {entries:#?}");
+            entries
+        }
+        Err(e) => {
+            warn!("The fstab of the recovered system can not be read from {path}: {e}");
+            Vec::new()
+        }
+    }
+}
+
+// The disk and the partition number of a device path like /dev/sda2, /dev/vda2, /dev/xvda2 or /dev/nvme0n1p2
+fn partition_of(device: &str) -> Option<(&str, i32)> {
+    let name = device.strip_prefix("/dev/")?;
+    if name.contains('/') {
+        return None;
+    }
+    let number_start = name.trim_end_matches(|c: char| c.is_ascii_digit()).len();
+    let (disk, number) = name.split_at(number_start);
+    let disk = if disk.starts_with("nvme") || disk.starts_with("mmcblk") {
+        disk.strip_suffix('p')?
+    } else if disk.starts_with("sd") || disk.starts_with("vd") || disk.starts_with("xvd") || disk.starts_with("hd") {
+        disk
+    } else {
+        return None;
+    };
+    Some((disk, number.parse().ok()?))
+}
+
+// The disks the recovered system names in the device paths of its root and boot entries. A device path is only
+// resolved if it names one of them, the recovery disk has been that disk when the recovered system was running.
+fn os_disks(entries: &[FstabEntry]) -> Vec<&str> {
+    entries
+        .iter()
+        .filter(|entry| ["/", "/boot", "/boot/efi"].contains(&entry.mountpoint.as_str()))
+        .filter_map(|entry| partition_of(&entry.spec))
+        .map(|(disk, _)| disk)
+        .collect()
+}
+
+// The device-mapper name of /dev/mapper/<name> or /dev/<vg>/<lv>. A hyphen within the VG or LV name is doubled.
+fn lv_name_of(device: &str) -> Option<String> {
+    if let Some(name) = device.strip_prefix("/dev/mapper/") {
+        return Some(name.to_string());
+    }
+    let [vg, lv] = device.strip_prefix("/dev/")?.split('/').collect::<Vec<_>>()[..] else {
+        return None;
+    };
+    (!vg.starts_with("disk")).then(|| format!("{}-{}", vg.replace('-', "--"), lv.replace('-', "--")))
+}
+
+fn find_volume<'a>(spec: &str, volumes: &'a [Volume]) -> Option<&'a Volume> {
+    let same = |own: &Option<String>, wanted: &str| own.as_ref().is_some_and(|own| own.eq_ignore_ascii_case(wanted));
+    let tagged = |tag: &str, link: &str| {
+        spec.strip_prefix(&format!("{tag}="))
+            .or_else(|| spec.strip_prefix(&format!("/dev/disk/{link}/")))
+            .map(|value| value.trim_matches('"'))
+    };
+
+    if let Some(uuid) = tagged("UUID", "by-uuid") {
+        return volumes.iter().find(|volume| same(&volume.uuid, uuid));
+    }
+    if let Some(label) = tagged("LABEL", "by-label") {
+        return volumes.iter().find(|volume| volume.label.as_deref() == Some(label));
+    }
+    if let Some(part_uuid) = tagged("PARTUUID", "by-partuuid") {
+        return volumes.iter().find(|volume| same(&volume.part_uuid, part_uuid));
+    }
+    if let Some(lv_name) = lv_name_of(spec) {
+        return volumes.iter().find(|volume| volume.lv_name.as_deref() == Some(lv_name.as_str()));
+    }
+    let (_, number) = partition_of(spec)?;
+    volumes.iter().find(|volume| volume.partition_number == Some(number))
+}
+
+fn mount_options(entry: &FstabEntry, volume: &Volume) -> String {
+    let mut options: Vec<&str> = entry
+        .options
+        .iter()
+        .filter(|option| SELECTING_OPTIONS.iter().any(|prefix| option.starts_with(prefix)))
+        .map(String::as_str)
+        .collect();
+    // The repair VM may use the same XFS UUID
+    if volume.fstype == "xfs" {
+        options.push("nouuid");
+    }
+    options.join(",")
+}
+
+// Sorting by the path components places a parent directory before its children
+pub(crate) fn mount_order(mountpoint: &str) -> Vec<String> {
+    Path::new(mountpoint)
+        .components()
+        .filter_map(|component| match component {
+            Component::Normal(name) => Some(name.to_string_lossy().to_string()),
+            _ => None,
+        })
+        .collect()
+}
+
+pub(crate) fn resolve(entries: &[FstabEntry], volumes: &[Volume]) -> Resolution {
+    let mut resolution = Resolution::default();
+    let os_disks = os_disks(entries);
+    for entry in entries.iter().filter(|entry| entry.is_local_mount()) {
+        if resolution.mounts.iter().any(|known| known.mountpoint == entry.mountpoint) {
+            resolution.unresolved.push(UnresolvedEntry {
+                entry: entry.clone(),
+                reason: "the mountpoint is used by an earlier entry".to_string(),
+            });
+            continue;
+        }
+        if Path::new(&entry.mountpoint).components().any(|component| component == Component::ParentDir) {
+            resolution.unresolved.push(UnresolvedEntry {
+                entry: entry.clone(),
+                reason: "the mountpoint contains '..'".to_string(),
+            });
+            continue;
+        }
+        if partition_of(&entry.spec).is_some_and(|(disk, _)| !os_disks.contains(&disk)) {
+            resolution.unresolved.push(UnresolvedEntry {
+                entry: entry.clone(),
+                reason: "the device path doesn't name the disk of the root or boot filesystem".to_string(),
+            });
+            continue;
+        }
+        match find_volume(&entry.spec, volumes) {
+            Some(volume) => resolution.mounts.push(ResolvedMount {
+                device: volume.device.clone(),
+                mountpoint: entry.mountpoint.clone(),
+                options: mount_options(entry, volume),
+            }),
+            None => resolution.unresolved.push(UnresolvedEntry {
+                entry: entry.clone(),
+                reason: "no partition or LV of the recovery disks matches".to_string(),
+            }),
+        }
+    }
+    resolution.mounts.sort_by_key(|mount| mount_order(&mount.mountpoint));
+    resolution
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FSTAB: &str = "\
+# /etc/fstab
+/dev/mapper/rootvg-rootlv /                       xfs     defaults        0 0
+UUID=5d6a1e6e-0c4a-4f0a-9f5e-1c1c0f0f0f0f /boot   xfs     defaults        0 0
+UUID=7B77-95E7          /boot/efi               vfat    defaults,uid=0,gid=0,umask=077,shortname=winnt 0 2
+/dev/mapper/rootvg-homelv /home                   xfs     defaults        0 0
+/dev/rootvg/varlv       /var                    xfs     defaults        0 0
+/dev/mapper/rootvg-varloglv /var/log            xfs     defaults        0 0
+/dev/mapper/rootvg-auditlv /var/log/audit       xfs     defaults        0 0
+LABEL=data              /data                   ext4    defaults,nofail 0 2
+PARTUUID=5f2c0c6e-06    /opt                    ext4    defaults,x-systemd.device-timeout=10 0 2
+/dev/sda5               /srv                    ext4    defaults        0 2
+UUID=1234 /.snapshots btrfs subvol=/@/.snapshots 0 0
+/dev/mapper/rootvg-swaplv swap                  swap    defaults        0 0
+server:/export          /mnt/nfs                nfs     defaults        0 0
+/dev/disk/cloud/azure_resource-part1 /mnt       auto    defaults,nofail,x-systemd.requires=cloud-init.service 0 2
+UUID=0000 /backup xfs noauto 0 0
+/dev/mapper/rootvg-homelv /home/../etc          xfs     defaults        0 0
+";
+
+    fn volume(device: &str, fstype: &str) -> Volume {
+        Volume {
+            device: device.to_string(),
+            fstype: fstype.to_string(),
+            ..Volume::default()
+        }
+    }
+
+    fn volumes() -> Vec<Volume> {
+        let lv = |name: &str| Volume {
+            lv_name: Some(name.to_string()),
+            ..volume(&format!("/dev/mapper/{name}"), "xfs")
+        };
+        vec![
+            Volume {
+                uuid: Some("7B77-95E7".to_string()),
+                partition_number: Some(1),
+                ..volume("/dev/sdc1", "vfat")
+            },
+            Volume {
+                uuid: Some("5d6a1e6e-0c4a-4f0a-9f5e-1c1c0f0f0f0f".to_string()),
+                partition_number: Some(2),
+                ..volume("/dev/sdc2", "xfs")
+            },
+            Volume {
+                label: Some("data".to_string()),
+                partition_number: Some(5),
+                ..volume("/dev/sdc5", "ext4")
+            },
+            Volume {
+                part_uuid: Some("5f2c0c6e-06".to_string()),
+                partition_number: Some(6),
+                ..volume("/dev/sdc6", "ext4")
+            },
+            Volume {
+                uuid: Some("1234".to_string()),
+                ..volume("/dev/sdd1", "btrfs")
+            },
+            lv("rootvg-rootlv"),
+            lv("rootvg-homelv"),
+            lv("rootvg-varlv"),
+            lv("rootvg-varloglv"),
+        ]
+    }
+
+    #[test]
+    fn mounts_in_mountpoint_order() {
+        let entries = parse(FSTAB);
+        assert_eq!(entries.len(), 16);
+        assert_eq!(entries[2].options[0], "defaults");
+
+        let resolution = resolve(&entries, &volumes());
+        let mounts: Vec<(&str, &str, &str)> = resolution
+            .mounts
+            .iter()
+            .map(|mount| (mount.mountpoint.as_str(), mount.device.as_str(), mount.options.as_str()))
+            .collect();
+        assert_eq!(
+            mounts,
+            vec![
+                ("/.snapshots", "/dev/sdd1", "subvol=/@/.snapshots"),
+                ("/boot", "/dev/sdc2", "nouuid"),
+                ("/boot/efi", "/dev/sdc1", ""),
+                ("/data", "/dev/sdc5", ""),
+                ("/home", "/dev/mapper/rootvg-homelv", "nouuid"),
+                ("/opt", "/dev/sdc6", ""),
+                ("/var", "/dev/mapper/rootvg-varlv", "nouuid"),
+                ("/var/log", "/dev/mapper/rootvg-varloglv", "nouuid"),
+            ]
+        );
+
+        let unresolved: Vec<&str> = resolution
+            .unresolved
+            .iter()
+            .map(|unresolved| unresolved.entry.mountpoint.as_str())
+            .collect();
+        assert_eq!(unresolved, vec!["/var/log/audit", "/srv", "/mnt", "/home/../etc"]);
+    }
+
+    #[test]
+    fn device_paths_of_the_os_disk() {
+        let entries = parse(
+            "/dev/sda1 / ext4 defaults 0 0\n/dev/sda5 /srv ext4 defaults 0 2\n/dev/sdb1 /data ext4 defaults 0 2\n",
+        );
+        let resolution = resolve(&entries, &volumes());
+        assert_eq!(resolution.mounts.len(), 1);
+        let srv = &resolution.mounts[0];
+        assert_eq!((srv.mountpoint.as_str(), srv.device.as_str()), ("/srv", "/dev/sdc5"));
+        assert_eq!(resolution.unresolved.len(), 1);
+        assert_eq!(resolution.unresolved[0].entry.mountpoint, "/data");
+    }
+
+    #[test]
+    fn device_paths() {
+        assert_eq!(partition_of("/dev/sda2"), Some(("sda", 2)));
+        assert_eq!(partition_of("/dev/nvme0n1p15"), Some(("nvme0n1", 15)));
+        assert_eq!(partition_of("/dev/nvme0n1"), None);
+        assert_eq!(partition_of("/dev/mapper/rootvg-rootlv"), None);
+        assert_eq!(lv_name_of("/dev/my-vg/root"), Some("my--vg-root".to_string()));
+        assert_eq!(lv_name_of("/dev/disk/by-uuid"), None);
+        assert_eq!(parse("/dev/sdb1 /mnt/my\\040data ext4 defaults 0 0")[0].mountpoint, "/mnt/my data");
+    }
+}
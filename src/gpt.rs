@@ -0,0 +1,369 @@
+use anyhow::{Context, Result, anyhow};
+use log::{debug, warn};
+use std::fmt::Display;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+
+// Reads the GUID partition table directly from the disk or an image file. The primary header is used if it is valid,
+// otherwise the backup header at the end of the disk. Nothing is ever written.
+
+const SIGNATURE: &[u8; 8] = b"EFI PART";
+// GPT is only defined for those logical sector sizes
+const SECTOR_SIZES: [u64; 2] = [512, 4096];
+const PROTECTIVE_MBR_TYPE: u8 = 0xEE;
+const MIN_HEADER_SIZE: usize = 92;
+const MIN_ENTRY_SIZE: usize = 128;
+// The spec requires space for at least 128 entries. Anything way above is a broken header.
+const MAX_ENTRIES: u32 = 4096;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub(crate) struct Guid([u8; 16]);
+
+impl Guid {
+    // Kept as stored on disk, where the first three fields are little endian
+    fn from_disk(bytes: &[u8]) -> Guid {
+        let mut guid = [0u8; 16];
+        guid.copy_from_slice(&bytes[..16]);
+        Guid(guid)
+    }
+
+    fn is_zero(&self) -> bool {
+        self.0.iter().all(|&byte| byte == 0)
+    }
+}
+
+impl Display for Guid {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let b = &self.0;
+        write!(
+            f,
+            "{:02x}{:02x}{:02x}{:02x}-{:02x}{:02x}-{:02x}{:02x}-{:02x}{:02x}-{:02x}{:02x}{:02x}{:02x}{:02x}{:02x}",
+            b[3], b[2], b[1], b[0], b[5], b[4], b[7], b[6], b[8], b[9], b[10], b[11], b[12], b[13], b[14], b[15]
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct GptHeader {
+    pub(crate) current_lba: u64,
+    pub(crate) backup_lba: u64,
+    pub(crate) first_usable_lba: u64,
+    pub(crate) last_usable_lba: u64,
+    pub(crate) disk_guid: Guid,
+    pub(crate) entries_lba: u64,
+    pub(crate) number_of_entries: u32,
+    pub(crate) entry_size: u32,
+    pub(crate) entries_crc32: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct GptPartition {
+    // The index of the entry plus one, the same number the kernel uses for the device node
+    pub(crate) number: u32,
+    pub(crate) type_guid: Guid,
+    pub(crate) unique_guid: Guid,
+    pub(crate) name: String,
+    pub(crate) first_lba: u64,
+    pub(crate) last_lba: u64,
+    pub(crate) attributes: u64,
+}
+
+impl GptPartition {
+    // The short type code sgdisk prints, i.e. 8300 or EF00. Other parts of ALAR classify the partitions by it.
+    // Types without a short code are returned as their GUID.
+    pub(crate) fn type_code(&self) -> String {
+        let code = match self.type_guid.to_string().as_str() {
+            "0fc63daf-8483-4772-8e79-3d69d8477de4" => "8300",
+            "933ac7e1-2eb4-4f13-b844-0e14e2aef915" => "8302",
+            "44479540-f297-41b2-9af7-d131d5f0458a" => "8303",
+            "4f68bce3-e8cd-4db1-96e7-fbcaf984b709" => "8304",
+            "b921b045-1df0-41c3-af44-4c6f280d3fae" => "8305",
+            "ca7d7ccb-63ed-4c53-861c-1742536059cc" => "8309",
+            "0657fd6d-a4ab-43c4-84e5-0933c84b4f4f" => "8200",
+            "e6d6d379-f507-44c2-a23c-238f2a3df928" => "8E00",
+            "c12a7328-f81f-11d2-ba4b-00a0c93ec93b" => "EF00",
+            "21686148-6449-6e6f-744e-656564454649" => "EF02",
+            "bc13c2ff-59e6-4262-a352-b275fd6f7172" => "EA00",
+            "a19d880f-05fc-4d3b-a006-743f0f84911e" => "FD00",
+            "ebd0a0a2-b9e5-4433-87c0-68b6b72699c7" => "0700",
+            guid => return guid.to_string(),
+        };
+        code.to_string()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct GptTable {
+    pub(crate) sector_size: u64,
+    pub(crate) has_protective_mbr: bool,
+    pub(crate) header: GptHeader,
+    // None if the backup header is missing or damaged. Only the valid header of both is required.
+    pub(crate) backup_header: Option<GptHeader>,
+    pub(crate) partitions: Vec<GptPartition>,
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn u64_at(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+fn read_at<R: Read + Seek>(reader: &mut R, offset: u64, length: usize) -> Result<Vec<u8>> {
+    let mut buffer = vec![0u8; length];
+    reader.seek(SeekFrom::Start(offset))?;
+    reader.read_exact(&mut buffer)?;
+    Ok(buffer)
+}
+
+// The type 0xEE entry marks the disk as GPT for tools that only know MBR
+fn has_protective_mbr(sector: &[u8]) -> bool {
+    sector[510..512] == [0x55, 0xAA]
+        && (0..4).any(|index| sector[446 + index * 16 + 4] == PROTECTIVE_MBR_TYPE)
+}
+
+fn parse_header(sector: &[u8], lba: u64) -> Result<GptHeader> {
+    if &sector[..8] != SIGNATURE {
+        return Err(anyhow!("no GPT signature at LBA {lba}"));
+    }
+    let header_size = u32_at(sector, 12) as usize;
+    if header_size < MIN_HEADER_SIZE || header_size > sector.len() {
+        return Err(anyhow!("invalid GPT header size {header_size} at LBA {lba}"));
+    }
+    // The CRC is calculated with its own field set to zero
+    let mut header = sector[..header_size].to_vec();
+    header[16..20].fill(0);
+    if crc32fast::hash(&header) != u32_at(sector, 16) {
+        return Err(anyhow!("GPT header checksum mismatch at LBA {lba}"));
+    }
+
+    let header = GptHeader {
+        current_lba: u64_at(sector, 24),
+        backup_lba: u64_at(sector, 32),
+        first_usable_lba: u64_at(sector, 40),
+        last_usable_lba: u64_at(sector, 48),
+        disk_guid: Guid::from_disk(&sector[56..72]),
+        entries_lba: u64_at(sector, 72),
+        number_of_entries: u32_at(sector, 80),
+        entry_size: u32_at(sector, 84),
+        entries_crc32: u32_at(sector, 88),
+    };
+    if header.current_lba != lba {
+        return Err(anyhow!("GPT header at LBA {lba} claims to be at LBA {}", header.current_lba));
+    }
+    if (header.entry_size as usize) < MIN_ENTRY_SIZE
+        || !header.entry_size.is_multiple_of(8)
+        || header.number_of_entries > MAX_ENTRIES
+    {
+        return Err(anyhow!(
+            "invalid GPT entry layout at LBA {lba}: {} entries of {} bytes",
+            header.number_of_entries,
+            header.entry_size
+        ));
+    }
+    Ok(header)
+}
+
+fn parse_entries(bytes: &[u8], header: &GptHeader) -> Result<Vec<GptPartition>> {
+    if crc32fast::hash(bytes) != header.entries_crc32 {
+        return Err(anyhow!("GPT partition entries checksum mismatch"));
+    }
+    let partitions = bytes
+        .chunks_exact(header.entry_size as usize)
+        .enumerate()
+        .filter_map(|(index, entry)| {
+            let type_guid = Guid::from_disk(&entry[0..16]);
+            if type_guid.is_zero() {
+                return None;
+            }
+            // The name is UTF-16LE, padded with zeros
+            let name: Vec<u16> = entry[56..128]
+                .chunks_exact(2)
+                .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+                .take_while(|&unit| unit != 0)
+                .collect();
+            Some(GptPartition {
+                number: index as u32 + 1,
+                type_guid,
+                unique_guid: Guid::from_disk(&entry[16..32]),
+                name: String::from_utf16_lossy(&name),
+                first_lba: u64_at(entry, 32),
+                last_lba: u64_at(entry, 40),
+                attributes: u64_at(entry, 48),
+            })
+        })
+        .collect();
+    Ok(partitions)
+}
+
+fn read_header_and_entries<R: Read + Seek>(
+    reader: &mut R,
+    sector_size: u64,
+    lba: u64,
+) -> Result<(GptHeader, Vec<GptPartition>)> {
+    let sector = read_at(reader, lba * sector_size, sector_size as usize)?;
+    let header = parse_header(&sector, lba)?;
+    let length = header.number_of_entries as usize * header.entry_size as usize;
+    let entries = read_at(reader, header.entries_lba * sector_size, length)
+        .with_context(|| format!("GPT partition entries of the header at LBA {lba} can not be read"))?;
+    let partitions = parse_entries(&entries, &header)?;
+    Ok((header, partitions))
+}
+
+// The sector size is the one that has the GPT signature at LBA 1
+fn detect_sector_size<R: Read + Seek>(reader: &mut R) -> Option<u64> {
+    SECTOR_SIZES.into_iter().find(|&sector_size| {
+        read_at(reader, sector_size, SIGNATURE.len()).is_ok_and(|bytes| bytes == SIGNATURE)
+    })
+}
+
+pub(crate) fn read<R: Read + Seek>(reader: &mut R) -> Result<GptTable> {
+    let disk_size = reader.seek(SeekFrom::End(0))?;
+    let mbr = read_at(reader, 0, 512).context("The first sector can not be read")?;
+    let has_protective_mbr = has_protective_mbr(&mbr);
+
+    // A damaged primary header has no signature any more, then the sector size is guessed from the backup header
+    let sector_size = detect_sector_size(reader)
+        .or_else(|| {
+            SECTOR_SIZES.into_iter().find(|&sector_size| {
+                disk_size >= sector_size * 2
+                    && read_at(reader, disk_size - sector_size, SIGNATURE.len()).is_ok_and(|bytes| bytes == SIGNATURE)
+            })
+        })
+        .ok_or_else(|| anyhow!("No GPT partition table found"))?;
+    let last_lba = disk_size / sector_size - 1;
+
+    let primary = read_header_and_entries(reader, sector_size, 1);
+    let backup_lba = match &primary {
+        Ok((header, _)) if header.backup_lba <= last_lba => header.backup_lba,
+        _ => last_lba,
+    };
+    let backup = read_header_and_entries(reader, sector_size, backup_lba);
+
+    let (header, partitions, backup_header) = match (primary, backup) {
+        (Ok((header, partitions)), Ok((backup_header, _))) => (header, partitions, Some(backup_header)),
+        (Ok((header, partitions)), Err(e)) => {
+            warn!("The backup GPT header is not usable: {e:#}");
+            (header, partitions, None)
+        }
+        (Err(e), Ok((header, partitions))) => {
+            warn!("The primary GPT header is not usable, the backup header is used: {e:#}");
+            (header, partitions, None)
+        }
+        (Err(primary), Err(backup)) => {
+            return Err(anyhow!(
+                "Neither the primary nor the backup GPT header is usable: {primary:#}; {backup:#}"
+            ));
+        }
+    };
+    if !has_protective_mbr {
+        warn!("The disk has a GPT partition table but no protective MBR");
+    }
+    debug!("GPT partition entries: {partitions:#?}");
+
+    Ok(GptTable {
+        sector_size,
+        has_protective_mbr,
+        header,
+        backup_header,
+        partitions,
+    })
+}
+
+pub(crate) fn read_from_path(path: &str) -> Result<GptTable> {
+    let mut file = File::open(path).with_context(|| format!("{path} can not be opened"))?;
+    read(&mut file).with_context(|| format!("The GPT partition table of {path} can not be read"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    const LINUX_FS: [u8; 16] = [
+        0xaf, 0x3d, 0xc6, 0x0f, 0x83, 0x84, 0x72, 0x47, 0x8e, 0x79, 0x3d, 0x69, 0xd8, 0x47, 0x7d, 0xe4,
+    ];
+    const EFI_SYSTEM: [u8; 16] = [
+        0x28, 0x73, 0x2a, 0xc1, 0x1f, 0xf8, 0xd2, 0x11, 0xba, 0x4b, 0x00, 0xa0, 0xc9, 0x3e, 0xc9, 0x3b,
+    ];
+
+    fn write_header(disk: &mut [u8], lba: u64, backup_lba: u64, entries_lba: u64, entries_crc32: u32) {
+        let mut header = vec![0u8; 92];
+        header[..8].copy_from_slice(SIGNATURE);
+        header[8..12].copy_from_slice(&0x0001_0000u32.to_le_bytes());
+        header[12..16].copy_from_slice(&92u32.to_le_bytes());
+        header[24..32].copy_from_slice(&lba.to_le_bytes());
+        header[32..40].copy_from_slice(&backup_lba.to_le_bytes());
+        header[40..48].copy_from_slice(&34u64.to_le_bytes());
+        header[48..56].copy_from_slice(&(backup_lba.max(lba) - 33).to_le_bytes());
+        header[72..80].copy_from_slice(&entries_lba.to_le_bytes());
+        header[80..84].copy_from_slice(&128u32.to_le_bytes());
+        header[84..88].copy_from_slice(&128u32.to_le_bytes());
+        header[88..92].copy_from_slice(&entries_crc32.to_le_bytes());
+        let crc = crc32fast::hash(&header);
+        header[16..20].copy_from_slice(&crc.to_le_bytes());
+        let offset = lba as usize * 512;
+        disk[offset..offset + 92].copy_from_slice(&header);
+    }
+
+    // An Ubuntu like layout: the root on partition 1 and the EFI partition on 15
+    fn disk_image() -> Vec<u8> {
+        let sectors = 2048u64;
+        let mut disk = vec![0u8; sectors as usize * 512];
+        disk[446 + 4] = PROTECTIVE_MBR_TYPE;
+        disk[510] = 0x55;
+        disk[511] = 0xAA;
+
+        let mut entries = vec![0u8; 128 * 128];
+        for (index, type_guid, first, last, name) in
+            [(0usize, LINUX_FS, 227u64, 2000u64, "root"), (14, EFI_SYSTEM, 34, 226, "UEFI")]
+        {
+            let entry = &mut entries[index * 128..(index + 1) * 128];
+            entry[..16].copy_from_slice(&type_guid);
+            entry[16] = index as u8 + 1;
+            entry[32..40].copy_from_slice(&first.to_le_bytes());
+            entry[40..48].copy_from_slice(&last.to_le_bytes());
+            for (position, unit) in name.encode_utf16().enumerate() {
+                entry[56 + position * 2..58 + position * 2].copy_from_slice(&unit.to_le_bytes());
+            }
+        }
+        entries[14 * 128 + 48..14 * 128 + 56].copy_from_slice(&1u64.to_le_bytes());
+        let entries_crc32 = crc32fast::hash(&entries);
+        disk[2 * 512..34 * 512].copy_from_slice(&entries);
+        disk[(sectors as usize - 33) * 512..(sectors as usize - 1) * 512].copy_from_slice(&entries);
+        write_header(&mut disk, 1, sectors - 1, 2, entries_crc32);
+        write_header(&mut disk, sectors - 1, 1, sectors - 33, entries_crc32);
+        disk
+    }
+
+    #[test]
+    fn read_gpt() {
+        let table = read(&mut Cursor::new(disk_image())).unwrap();
+        assert_eq!(table.sector_size, 512);
+        assert!(table.has_protective_mbr);
+        assert!(table.backup_header.is_some());
+        assert_eq!(table.partitions.len(), 2);
+
+        let efi = &table.partitions[1];
+        assert_eq!(efi.number, 15);
+        assert_eq!(efi.type_code(), "EF00");
+        assert_eq!(efi.type_guid.to_string(), "c12a7328-f81f-11d2-ba4b-00a0c93ec93b");
+        assert_eq!(efi.name, "UEFI");
+        assert_eq!((efi.first_lba, efi.last_lba, efi.attributes), (34, 226, 1));
+        assert_eq!(table.partitions[0].type_code(), "8300");
+        assert_eq!(table.partitions[0].unique_guid.to_string(), "00000001-0000-0000-0000-000000000000");
+    }
+
+    #[test]
+    fn read_gpt_from_backup() {
+        let mut disk = disk_image();
+        // Damage the primary header
+        disk[512 + 30] ^= 0xff;
+        let table = read(&mut Cursor::new(disk)).unwrap();
+        assert_eq!(table.header.current_lba, 2047);
+        assert_eq!(table.partitions.len(), 2);
+
+        assert!(read(&mut Cursor::new(vec![0u8; 512 * 64])).is_err());
+    }
+}
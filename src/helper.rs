@@ -1,39 +1,18 @@
 use crate::{
-    ade,
     cli::{self, CliInfo},
-    constants,
-    distro::{Distro, LogicalVolumesType},
-    mount, nvme, telemetry,
+    constants, lvm, signals,
 };
 use anyhow::{Context, Result, anyhow};
-use log::{debug, error, info};
+use log::{debug, error};
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::{
     fs,
     path::Path,
-    process::{self, Command},
+    process::Command,
     time::Duration,
 };
 
-enum DiskType {
-    Nvme,
-    Scsi,
-    Nbd,
-}
-
-fn what_disk_type(path: &str) -> Result<DiskType> {
-    if path.contains("/dev/nvme") {
-        Ok(DiskType::Nvme)
-    } else if path.contains("/dev/sd") {
-        Ok(DiskType::Scsi)
-    } else if path.contains("/dev/nbd") {
-        Ok(DiskType::Nbd)
-    } else {
-        Err(anyhow!("Unknown disk type for path: {}", path))
-    }
-}
-
 // There are issue with readlink or readpath. Somehow the pathes can't be resolved correctly
 // The following functions are a workaround to get the correct path and to determine the partition numbers
 // based on those details we can get from the partition path.
@@ -49,79 +28,20 @@ pub(crate) fn realpath(path: &str) -> Result<String> {
     Ok(String::from_utf8(device)?.trim().to_string())
 }
 
-pub(crate) fn get_recovery_disk_path(cli_info: &CliInfo) -> String {
-    #[allow(unused_assignments)]
-    let mut path_info = String::new();
-    let error_condition = |e| {
-        error!(
-            "Error getting recover disk info. Something went wrong. ALAR is not able to proceed. Exiting."
-        );
-        error!("Error detail: {}", e);
-
-        let _ = telemetry::send_envelope(&telemetry::create_exception_envelope(
-            telemetry::SeverityLevel::Error,
-            "ALAR EXCEPTION",
-            "Error getting recovery disk partition information.",
-            &format!(
-                "Distro::get_all_recovery_partitions() -> helper::run_fun() returned error: {}",
-                e
-            ),
-            cli_info,
-            &Distro::default(),
-        ))
-        .inspect_err(|e| error!("Failed to send telemetry: {}", e));
-    };
-
-    if !cli_info.custom_recover_disk.is_empty() {
-        // For a NBD device this part is used as well. No special handling required as with NVME devices.
-        match realpath(&cli_info.custom_recover_disk) {
-            Ok(path) => {
-                debug!(
-                    "Using custom recovery disk path: {}",
-                    cli_info.custom_recover_disk
-                );
-                path_info = path;
-            }
-            Err(e) => {
-                error_condition(e);
-                process::exit(1);
-            }
-        }
-    } else {
-        match is_nvme_controller_present() {
-            Ok(is_nvme) => {
-                if is_nvme {
-                    path_info = match nvme::get_recovery_nvme_disk_path() {
-                        Ok(path) => path,
-                        Err(e) => {
-                            error_condition(e);
-                            process::exit(1);
-                        }
-                    };
-                } else {
-                    match realpath(constants::RESCUE_DISK) {
-                        Ok(path) => {
-                            path_info = path;
-                        }
-                        Err(e) => {
-                            error_condition(e);
-                            process::exit(1)
-                        }
-                    }
-                }
-            }
-            Err(e) => {
-                error_condition(e);
-                process::exit(1);
-            }
-        }
-    };
-    // add the suffix 'p' to the path if it's a nvme or NBD disk, otherwise the partition number will be wrong later on
-    match what_disk_type(&path_info) {
-        Ok(DiskType::Nvme) | Ok(DiskType::Nbd) => path_info.push('p'),
-        _ => {}
+// The disk got selected at the start of the run. The partitions are taken from sysfs, thus the path is the one
+// of the disk itself, whatever its naming scheme is.
+pub(crate) fn get_recovery_disk_path(cli_info: &CliInfo) -> Result<String> {
+    if cli_info.recovery_disk.is_empty() {
+        return Err(anyhow!("The recovery disk is not selected yet"));
     }
-    path_info
+    Ok(cli_info.recovery_disk.clone())
+}
+
+// The recovery disk and the additional ones, which hold further PVs of its VG
+pub(crate) fn get_recovery_disk_paths(cli_info: &CliInfo) -> Result<Vec<String>> {
+    let mut disks = vec![get_recovery_disk_path(cli_info)?];
+    disks.extend(cli_info.additional_recovery_disks.iter().cloned());
+    Ok(disks)
 }
 
 pub(crate) fn is_repair_vm_imds() -> Result<bool> {
@@ -156,63 +76,27 @@ pub(crate) fn is_repair_vm_imds() -> Result<bool> {
     Ok(is_repair_vm)
 }
 
-pub(crate) fn cleanup(distro: &Distro, cli_info: &CliInfo) -> Result<()> {
-    if distro.is_ade {
-        debug!("Running ADE cleanup");
-        if distro
-            .partitions
-            .iter()
-            .filter(|lvm| matches!(lvm.logical_volumes, LogicalVolumesType::Some(_)))
-            .count()
-            > 0
-        {
-            info!("LVM clean up at the end of the recovery process.");
-            match ade::ade_lvm_cleanup() {
-                Ok(_) => {}
-                Err(e) => {
-                    error!("Clean up phase :: ade_cleanup raised an error : {e}");
-                }
-            };
-        } else {
-            info!("Clean up at the end of the recovery process.");
-            ade::close_rescueencrypt()?;
-        }
-    } else {
-        distro
-            .partitions
-            .iter()
-            .filter(|lvm| matches!(lvm.logical_volumes, LogicalVolumesType::Some(_)))
-            .for_each(|_| {
-                info!("Cleaning up at the end of the recovery process.");
-
-                match mount::disable_broken_disk(cli_info) {
-                    Ok(_) => {}
-                    Err(e) => {
-                        error!("Clean up phase :: disable_broken_disk raised and error : {e}");
-                    }
-                }
-                mount::rename_oldvg();
-
-                match mount::rescan_host() {
-                    Ok(_) => {}
-                    Err(e) => {
-                        error!("Clean up phase :: rescan_host raised an error : {e}");
-                    }
-                }
-            });
+// The private LVM configuration is passed to each command ALAR runs, instead of changing the environment of ALAR itself
+fn shell(shell: &str, command: &str) -> Command {
+    let mut child = Command::new(shell);
+    child.arg("-c").arg(command);
+    if lvm::is_private() {
+        child.env("LVM_SYSTEM_DIR", constants::PRIVATE_LVM_DIR);
     }
-    Ok(())
+    child
 }
 
 pub(crate) fn run_fun(command: &str) -> Result<String> {
     debug!("Running function: {}", command);
-    let output = Command::new("bash").arg("-c").arg(command).output()?.stdout;
+    signals::verify_not_interrupted()?;
+    let output = shell("bash", command).output()?.stdout;
     Ok(String::from_utf8(output)?)
 }
 
 pub(crate) fn run_cmd(command: &str) -> Result<()> {
     debug!("Running command: {}", command);
-    let output = Command::new("sh").arg("-c").arg(command).output()?;
+    signals::verify_not_interrupted()?;
+    let output = shell("sh", command).output()?;
     if !output.status.success() {
         return Err(anyhow!("Unable to run command {}", command));
     }
@@ -248,14 +132,19 @@ fn download_action_scripts() -> Result<()> {
         );
     }
 
+    fetch_action_scripts_tarball("/tmp")
+}
+
+// Downloads the tarball and expands the directory action_implementation into the given parent directory
+pub(crate) fn fetch_action_scripts_tarball(parent_directory: &str) -> Result<()> {
     debug!("Downloading the action scripts from the remote repository");
     let command = format!("curl -o /tmp/alar2.tar.gz -L {}", constants::TARBALL);
     run_cmd(&command).context("Archive alar2.tar.gz not downloaded")?;
     debug!("Downloaded the action scripts from the remote repository");
     // Expand the action_implementation directory
-    run_cmd(
-        "tar --wildcards --strip-component=2 -xzf /tmp/alar2.tar.gz -C /tmp *action_implementation",
-    )?;
+    run_cmd(&format!(
+        "tar --wildcards --strip-component=2 -xzf /tmp/alar2.tar.gz -C {parent_directory} *action_implementation"
+    ))?;
 
     Ok(())
 }
@@ -288,89 +177,10 @@ fn write_builtin_action_scripts() -> Result<()> {
     fs::create_dir_all(constants::ACTION_IMPL_DIR)
         .context("Directory ACTION_IMPL_DIR can not be created")?;
 
-    fs::write(
-        format!("{}/{}", constants::ACTION_IMPL_DIR, "auditd-impl.sh"),
-        constants::AUDITD_IMPL_FILE,
-    )
-    .context("Writing auditd-impl.sh failed")?;
-
-    fs::write(
-        format!("{}/{}", constants::ACTION_IMPL_DIR, "efifix-impl.sh"),
-        constants::EFIFIX_IMPL_FILE,
-    )
-    .context("Writing efifix-impl.sh failed")?;
-
-    fs::write(
-        format!("{}/{}", constants::ACTION_IMPL_DIR, "fstab-impl.py"),
-        constants::FSTAB_IMPL_FILE,
-    )
-    .context("Writing fstab-impl.py failed")?;
-
-    fs::write(
-        format!("{}/{}", constants::ACTION_IMPL_DIR, "grub.awk"),
-        constants::GRUB_AKW_FILE,
-    )
-    .context("Writing grub.awk failed")?;
-
-    fs::write(
-        format!("{}/{}", constants::ACTION_IMPL_DIR, "grubfix-impl.sh"),
-        constants::GRUBFIX_IMPL_FILE,
-    )
-    .context("Writing grubfix-impl.sh failed")?;
-
-    fs::write(
-        format!("{}/{}", constants::ACTION_IMPL_DIR, "helpers.sh"),
-        constants::HELPERS_SH_FILE,
-    )
-    .context("Writing helpers.sh failed")?;
-
-    fs::write(
-        format!("{}/{}", constants::ACTION_IMPL_DIR, "helpers.py"),
-        constants::HELPERS_PY_FILE,
-    )
-    .context("Writing helpers.py failed")?;
-
-    fs::write(
-        format!("{}/{}", constants::ACTION_IMPL_DIR, "initrd-impl.sh"),
-        constants::INITRD_IMPL_FILE,
-    )
-    .context("Writing initrd-impl.sh failed")?;
-
-    fs::write(
-        format!("{}/{}", constants::ACTION_IMPL_DIR, "kernel-impl.sh"),
-        constants::KERNEL_IMPL_FILE,
-    )
-    .context("Writing kernel-impl.sh failed")?;
-
-    fs::write(
-        format!("{}/{}", constants::ACTION_IMPL_DIR, "safe-exit.sh"),
-        constants::SAFE_EXIT_FILE,
-    )
-    .context("Writing safe-exit.sh failed")?;
-
-    fs::write(
-        format!("{}/{}", constants::ACTION_IMPL_DIR, "serialconsole-impl.sh"),
-        constants::SERIALCONSOLE_IMPL_FILE,
-    )
-    .context("Writing serialconsole-impl.sh failed")?;
-
-    fs::write(
-        format!("{}/{}", constants::ACTION_IMPL_DIR, "test-impl.sh"),
-        constants::TEST_IMPL_FILE,
-    )
-    .context("Writing test-impl.sh failed")?;
-
-    fs::write(
-        format!("{}/{}", constants::ACTION_IMPL_DIR, "sudo-impl.sh"),
-        constants::SUDO_IMPL_FILE,
-    )
-    .context("Writing sudo-impl.sh failed")?;
-
-    fs::write(
-        format!("{}/{}", constants::ACTION_IMPL_DIR, "corrupt-impl.sh"),
-        constants::CORRUPT_IMPL_FILE,
-    )
-    .context("Writing corrupt-impl.sh failed")?;
+    for (file_name, content) in constants::BUILTIN_ACTION_FILES {
+        fs::write(format!("{}/{}", constants::ACTION_IMPL_DIR, file_name), content)
+            .with_context(|| format!("Writing {file_name} failed"))?;
+    }
 
     Ok(())
 }
@@ -0,0 +1,91 @@
+use crate::cli::OutputFormat;
+use crate::distro::Distro;
+use anyhow::Result;
+use serde::Serialize;
+
+// The schema version needs to be increased whenever a field gets renamed, removed or changes its meaning.
+// Adding a new field is a compatible change and doesn't require a new version.
+pub(crate) const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Debug, Serialize)]
+pub(crate) struct InspectReport<'a> {
+    schema_version: u32,
+    alar_version: &'static str,
+    recovery_disk: String,
+    distro: &'a Distro,
+}
+
+impl<'a> InspectReport<'a> {
+    pub(crate) fn new(distro: &'a Distro, recovery_disk: &str) -> Self {
+        InspectReport {
+            schema_version: SCHEMA_VERSION,
+            alar_version: clap::crate_version!(),
+            recovery_disk: recovery_disk.to_string(),
+            distro,
+        }
+    }
+
+    pub(crate) fn render(&self, format: OutputFormat) -> Result<String> {
+        match format {
+            OutputFormat::Json => Ok(serde_json::to_string_pretty(self)?),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::distro::{Architecture, LogicalVolume, LogicalVolumesType, PartInfo};
+    use serde_json::Value;
+
+    #[test]
+    fn inspect_report_json() {
+        let mut distro = Distro {
+            architecture: Architecture::Aarch64,
+            is_lvm: true,
+            partitions: vec![
+                PartInfo {
+                    number: 1,
+                    part_type: "EF00".to_string(),
+                    fstype: "vfat".to_string(),
+                    ..PartInfo::default()
+                },
+                PartInfo {
+                    number: 2,
+                    part_type: "8E00".to_string(),
+                    fstype: "LVM2_member".to_string(),
+                    fs_uuid: Some("abcdef-0123-4567-89ab-cdef-0123-456789".to_string()),
+                    contains_os: true,
+                    logical_volumes: LogicalVolumesType::Some(vec![LogicalVolume {
+                        name: "rootvg-rootlv".to_string(),
+                        fstype: "xfs".to_string(),
+                    }]),
+                    ..PartInfo::default()
+                },
+            ],
+            ..Distro::default()
+        };
+        distro.cli_info.ade_password = "secret".to_string();
+
+        let json = InspectReport::new(&distro, "/dev/sdc")
+            .render(OutputFormat::Json)
+            .unwrap();
+        assert!(!json.contains("secret"));
+
+        let value: Value = serde_json::from_str(&json).unwrap();
+        assert_eq!(value["schema_version"], SCHEMA_VERSION);
+        assert_eq!(value["recovery_disk"], "/dev/sdc");
+        assert_eq!(value["distro"]["architecture"], "aarch64");
+        assert_eq!(value["distro"]["is_lvm"], true);
+        assert!(value["distro"]["partitions"][0]["logical_volumes"].is_null());
+        assert!(value["distro"]["partitions"][0]["fs_uuid"].is_null());
+        assert_eq!(
+            value["distro"]["partitions"][1]["fs_uuid"],
+            "abcdef-0123-4567-89ab-cdef-0123-456789"
+        );
+        assert_eq!(
+            value["distro"]["partitions"][1]["logical_volumes"][0]["name"],
+            "rootvg-rootlv"
+        );
+    }
+}
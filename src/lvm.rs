@@ -0,0 +1,713 @@
+use crate::blockdev;
+use crate::cli::FsckPolicy;
+use crate::constants;
+use crate::helper;
+use crate::probe;
+use crate::teardown::{self, Step};
+use anyhow::{Context, Result, anyhow};
+use log::{debug, info, warn};
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// The PVs of a VG are looked up by the UUID of the VG. The name may be the same as the one of the repair VM.
+// A VG whose name or UUID collides with a VG of the repair VM is clone-imported with a temporary name. The VG of the
+// repair VM gives way to it, so that the recovered system finds its VG under the original name.
+//
+// A repair VM using a LVM devices file, like RHEL 9 and later, doesn't see the PVs of the recovery disks, and it hides
+// the duplicates of its own PVs. In this case all LVM commands of ALAR run with a private configuration which only lets
+// the PVs of the recovery disks through. The VGs of the repair VM stay untouched, a colliding VG keeps its temporary
+// name while ALAR works with it. Right before the chroot it gets its original name back in its metadata, its LVs stay
+// active under the device-mapper names of the temporary name. Only the chroot sees them under their original names.
+
+// The device-mapper layers below thin pools and caches. They are recognized by their name if the LVs couldn't be listed.
+const INTERNAL_LV_SUFFIXES: [&str; 9] =
+    ["_tdata", "_tmeta", "-tpool", "_cdata", "_cmeta", "_corig", "_wcorig", "_cvol", "_pmspare"];
+// The name LVM reports for a PV it knows from the metadata but can't find
+const UNKNOWN_PV: &str = "[unknown]";
+const DEVICES_FILE: &str = "/etc/lvm/devices/system.devices";
+// Temporary VG names are built from this prefix and a number
+const TEMPORARY_VG_PREFIX: &str = "alar_vg";
+// The LV names used for / and /usr by the Azure images and the common installers
+const ROOT_LV_NAMES: [&str; 3] = ["rootlv", "root", "lv_root"];
+const USR_LV_NAMES: [&str; 3] = ["usrlv", "usr", "lv_usr"];
+
+// The LVM commands of ALAR use the private configuration once it is written
+static IS_PRIVATE: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct PhysicalVolume {
+    pub(crate) name: String,
+    pub(crate) uuid: String,
+    pub(crate) vg_name: String,
+    pub(crate) vg_uuid: String,
+    pub(crate) is_missing: bool,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub(crate) struct VolumeGroup {
+    pub(crate) name: String,
+    pub(crate) uuid: String,
+    // The PVs found on the recovery disks
+    pub(crate) pvs: Vec<String>,
+    // PVs on attached disks which are not passed over as recovery disk
+    pub(crate) pvs_elsewhere: Vec<String>,
+    // The UUIDs of the PVs LVM can't find at all
+    pub(crate) missing_pvs: Vec<String>,
+    pub(crate) import: ImportPlan,
+    // All LVs of the VG including the hidden ones, empty if they couldn't be listed
+    pub(crate) logical_volumes: Vec<LvInfo>,
+}
+
+// The LV types as given by the segment type. Thin LVs and cached LVs are mounted like any other LV, the pools are not.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub(crate) enum LvKind {
+    #[default]
+    Plain,
+    ThinPool,
+    Thin,
+    CachePool,
+    // A LV with a dm-cache or a dm-writecache in front of it
+    Cached,
+    Other,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct LvInfo {
+    pub(crate) name: String,
+    pub(crate) kind: LvKind,
+    // The pool of a thin or cached LV
+    pub(crate) pool: Option<String>,
+    // The sub-LVs of pools and caches, i.e. pool_tdata, pool_tmeta, lv_corig or lvol0_pmspare
+    pub(crate) is_hidden: bool,
+}
+
+impl LvInfo {
+    pub(crate) fn is_mountable(&self) -> bool {
+        !self.is_hidden && matches!(self.kind, LvKind::Plain | LvKind::Thin | LvKind::Cached)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub(crate) struct VgRename {
+    pub(crate) uuid: String,
+    pub(crate) from: String,
+    pub(crate) to: String,
+}
+
+// How a VG of the recovery disks gets imported
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub(crate) struct ImportPlan {
+    // The name vgimportclone gives to the VG, None if the VG doesn't collide with any VG of the repair VM
+    pub(crate) clone_name: Option<String>,
+    // The VGs of the repair VM which carry the same name. They are renamed until the recovery disks are gone.
+    pub(crate) repair_vm_renames: Vec<VgRename>,
+    // With the private configuration the VG keeps the clone name until it is deactivated at the cleanup
+    pub(crate) keeps_clone_name: bool,
+}
+
+impl VolumeGroup {
+    pub(crate) fn is_partial(&self) -> bool {
+        !self.missing_pvs.is_empty()
+    }
+
+    // The VG spans more than the recovery disks. All disks have to be passed over to use it as a whole.
+    pub(crate) fn is_incomplete(&self) -> bool {
+        !self.pvs_elsewhere.is_empty()
+    }
+
+    // The name the VG carries while ALAR works with it
+    pub(crate) fn active_name(&self) -> &str {
+        match &self.import.clone_name {
+            Some(clone_name) if self.import.keeps_clone_name => clone_name,
+            _ => &self.name,
+        }
+    }
+}
+
+// Parses the output of 'pvs --noheadings --separator ; -o pv_name,pv_uuid,vg_name,vg_uuid,pv_missing'
+// A device which isn't a PV is listed by 'pvs -a' without a UUID
+fn parse_pvs(output: &str) -> Vec<PhysicalVolume> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.trim().split(';').map(str::trim).collect();
+            match fields.as_slice() {
+                [_, "", ..] => None,
+                [name, uuid, vg_name, vg_uuid, missing] => Some(PhysicalVolume {
+                    name: name.to_string(),
+                    uuid: uuid.to_string(),
+                    vg_name: vg_name.to_string(),
+                    vg_uuid: vg_uuid.to_string(),
+                    is_missing: *name == UNKNOWN_PV || !missing.is_empty(),
+                }),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+// All devices are listed. A disk created from the same image as the repair VM carries duplicates of its PVs,
+// which LVM otherwise hides.
+pub(crate) fn physical_volumes() -> Result<Vec<PhysicalVolume>> {
+    let output =
+        helper::run_fun("pvs -a --noheadings --separator ';' -o pv_name,pv_uuid,vg_name,vg_uuid,pv_missing")?;
+    Ok(parse_pvs(&output))
+}
+
+// The PVs as the repair VM sees them with its own configuration
+fn repair_vm_physical_volumes() -> Result<Vec<PhysicalVolume>> {
+    let output = helper::run_fun(
+        "env -u LVM_SYSTEM_DIR pvs -a --noheadings --separator ';' -o pv_name,pv_uuid,vg_name,vg_uuid,pv_missing",
+    )?;
+    Ok(parse_pvs(&output))
+}
+
+// LVM ignores every device which isn't listed in the devices file, if the file exists
+pub(crate) fn is_devices_file_in_use() -> bool {
+    let is_enabled = helper::run_fun("lvmconfig --typeconfig full devices/use_devicesfile")
+        .is_ok_and(|output| output.trim() == "use_devicesfile=1");
+    is_enabled && Path::new(DEVICES_FILE).is_file()
+}
+
+pub(crate) fn is_private() -> bool {
+    IS_PRIVATE.load(Ordering::SeqCst)
+}
+
+fn private_config(devices: &[String]) -> String {
+    let filter = devices
+        .iter()
+        .map(|device| format!("\"a|^{device}$|\""))
+        .chain(std::iter::once("\"r|.*|\"".to_string()))
+        .collect::<Vec<String>>()
+        .join(", ");
+    format!(
+        "# Written by ALAR. Only the devices of the recovery disks are visible to LVM.\n\
+         devices {{\n\
+         \tuse_devicesfile = 1\n\
+         \tfilter = [ {filter} ]\n\
+         \tglobal_filter = [ {filter} ]\n\
+         }}\n\
+         backup {{\n\
+         \tbackup = 0\n\
+         \tarchive = 0\n\
+         }}\n"
+    )
+}
+
+fn is_pv(device: &str) -> bool {
+    probe::probe_path(device)
+        .ok()
+        .flatten()
+        .is_some_and(|probe| probe.fstype == probe::FsType::Lvm2Member)
+}
+
+// Switches the LVM commands of ALAR to the private configuration if the repair VM uses a devices file. The devices file
+// of the private configuration lists the PVs of the recovery disks only. The environment of ALAR stays as it is, the
+// configuration is passed to each command by helper::run_fun() and helper::run_cmd(). The actions never get it.
+pub(crate) fn use_private_config(recovery_disks: &[String]) -> Result<()> {
+    if !is_devices_file_in_use() {
+        return Ok(());
+    }
+    info!("The repair VM uses a LVM devices file. LVM is used with a private configuration in {}", constants::PRIVATE_LVM_DIR);
+
+    let mut devices: Vec<String> = Vec::new();
+    for disk in recovery_disks {
+        devices.push(disk.clone());
+        devices.extend(blockdev::partitions(disk)?.into_iter().map(|partition| partition.device));
+    }
+    // The LUKS container gets opened later on
+    devices.push(constants::ADE_OSENCRYPT_PATH.to_string());
+
+    fs::create_dir_all(format!("{}/devices", constants::PRIVATE_LVM_DIR))?;
+    teardown::register(Step::RemoveTree(constants::PRIVATE_LVM_DIR.to_string()));
+    fs::write(format!("{}/lvm.conf", constants::PRIVATE_LVM_DIR), private_config(&devices))?;
+    IS_PRIVATE.store(true, Ordering::SeqCst);
+
+    for device in devices.iter().filter(|device| is_pv(device)) {
+        allow_device(device)?;
+    }
+    Ok(())
+}
+
+// Replaces the name of the VG in a metadata backup written by vgcfgbackup. The name opens the only unindented section.
+fn rename_in_metadata(metadata: &str, from: &str, to: &str) -> String {
+    metadata
+        .lines()
+        .map(|line| if line == format!("{from} {{") { format!("{to} {{") } else { line.to_string() })
+        .collect::<Vec<String>>()
+        .join("\n")
+        + "\n"
+}
+
+// A VG which keeps its clone name gets its original name back in its metadata before the actions run in the chroot.
+// A vgrename isn't possible, it would rename the device-mapper devices of the LVs to the names the repair VM uses
+// for its own VG. The metadata is written back with vgcfgrestore instead, while the LVs stay active.
+pub(crate) fn restore_name_for_chroot(volume_group: &VolumeGroup) -> Result<()> {
+    let (Some(clone_name), true) = (&volume_group.import.clone_name, volume_group.import.keeps_clone_name) else {
+        return Ok(());
+    };
+    let file = format!("{}/{clone_name}.vg", constants::PRIVATE_LVM_DIR);
+    helper::run_cmd(&format!("vgcfgbackup -f {file} {clone_name}"))
+        .with_context(|| format!("The metadata of the VG {clone_name} can't be saved"))?;
+    fs::write(&file, rename_in_metadata(&fs::read_to_string(&file)?, clone_name, &volume_group.name))?;
+    helper::run_cmd(&format!("vgcfgrestore --force --yes -f {file} {}", volume_group.name))
+        .with_context(|| format!("The VG {clone_name} can't get its original name {} back", volume_group.name))?;
+    info!("The VG {clone_name} carries its original name {} again", volume_group.name);
+
+    teardown::forget(&Step::RestoreVgName {
+        from: clone_name.clone(),
+        to: volume_group.name.clone(),
+    });
+    teardown::forget(&Step::DeactivateVg(clone_name.clone()));
+    teardown::register(Step::DeactivateVg(volume_group.name.clone()));
+    Ok(())
+}
+
+// Adds a PV to the private devices file. Nothing is done with the configuration of the repair VM.
+pub(crate) fn allow_device(device: &str) -> Result<()> {
+    if !is_private() {
+        return Ok(());
+    }
+    helper::run_cmd(&format!("lvmdevices --adddev {device}"))
+        .with_context(|| format!("{device} can't be added to the private LVM devices file"))
+}
+
+// Sorts the PVs of the VG by where they are found. The disk of a PV is given by is_on_recovery_disk.
+fn group_pvs(
+    pv: &str,
+    pvs: &[PhysicalVolume],
+    is_on_recovery_disk: impl Fn(&str) -> bool,
+) -> Result<VolumeGroup> {
+    let own = pvs
+        .iter()
+        .find(|candidate| candidate.name == pv)
+        .ok_or_else(|| anyhow!("{pv} is not known to LVM as a PV"))?;
+    if own.vg_uuid.is_empty() {
+        return Err(anyhow!("{pv} doesn't belong to any VG"));
+    }
+
+    let mut volume_group = VolumeGroup {
+        name: own.vg_name.clone(),
+        uuid: own.vg_uuid.clone(),
+        ..VolumeGroup::default()
+    };
+    let members: Vec<&PhysicalVolume> = pvs.iter().filter(|member| member.vg_uuid == own.vg_uuid).collect();
+    let (missing, found): (Vec<&PhysicalVolume>, Vec<&PhysicalVolume>) =
+        members.into_iter().partition(|member| member.is_missing);
+    let (on_recovery_disks, elsewhere): (Vec<&PhysicalVolume>, Vec<&PhysicalVolume>) =
+        found.into_iter().partition(|member| is_on_recovery_disk(&member.name));
+
+    volume_group.pvs = on_recovery_disks.iter().map(|member| member.name.clone()).collect();
+    // A duplicate of a PV of the recovery disks belongs to the VG of the repair VM, which got created from the same image
+    volume_group.pvs_elsewhere = elsewhere
+        .iter()
+        .filter(|member| !on_recovery_disks.iter().any(|own| own.uuid == member.uuid))
+        .map(|member| member.name.clone())
+        .collect();
+    volume_group.missing_pvs = missing.iter().map(|member| member.uuid.clone()).collect();
+    Ok(volume_group)
+}
+
+// Finds all PVs of the VG the given PV belongs to and how the VG gets imported. In dry-run mode the PVs are only listed.
+pub(crate) fn volume_group_of(pv: &str, recovery_disks: &[String], is_dry_run: bool) -> Result<VolumeGroup> {
+    if !is_dry_run {
+        helper::run_cmd("pvscan --cache")?;
+    }
+    let mut pvs = physical_volumes()?;
+    // The VGs of the repair VM are invisible with the private configuration, but their names are still taken
+    if is_private() {
+        pvs.extend(repair_vm_physical_volumes()?);
+    }
+    debug!("PVs known to LVM: {pvs:#?}");
+    let mut volume_group = group_pvs(pv, &pvs, |name| {
+        blockdev::backing_disks(name).is_ok_and(|disks| {
+            disks
+                .iter()
+                .any(|disk| recovery_disks.iter().any(|recovery| recovery.trim_start_matches("/dev/") == disk))
+        })
+    })?;
+    volume_group.import = plan_import(&volume_group, &pvs, is_private());
+    debug!("VG of {pv}: {volume_group:#?}");
+    Ok(volume_group)
+}
+
+fn temporary_vg_name(taken: &[String]) -> String {
+    (1..)
+        .map(|number| format!("{TEMPORARY_VG_PREFIX}{number}"))
+        .find(|name| !taken.contains(name))
+        .unwrap_or_default()
+}
+
+// A VG of the repair VM collides if it carries the same name or, if both got created from the same image, the same UUID.
+// The PVs of the recovery disks are given by volume_group. With the private configuration the VGs of the repair VM
+// are left alone.
+pub(crate) fn plan_import(volume_group: &VolumeGroup, pvs: &[PhysicalVolume], is_private: bool) -> ImportPlan {
+    let repair_vm_pvs: Vec<&PhysicalVolume> = pvs
+        .iter()
+        .filter(|pv| !pv.is_missing && !pv.vg_uuid.is_empty() && !volume_group.pvs.contains(&pv.name))
+        .collect();
+    let is_colliding = repair_vm_pvs
+        .iter()
+        .any(|pv| pv.vg_name == volume_group.name || pv.vg_uuid == volume_group.uuid);
+    if !is_colliding {
+        return ImportPlan::default();
+    }
+
+    let mut taken: Vec<String> = pvs.iter().map(|pv| pv.vg_name.clone()).collect();
+    let clone_name = temporary_vg_name(&taken);
+    taken.push(clone_name.clone());
+    if is_private {
+        return ImportPlan {
+            clone_name: Some(clone_name),
+            keeps_clone_name: true,
+            ..ImportPlan::default()
+        };
+    }
+
+    let mut repair_vm_renames: Vec<VgRename> = Vec::new();
+    for pv in repair_vm_pvs.iter().filter(|pv| pv.vg_name == volume_group.name) {
+        if repair_vm_renames.iter().any(|rename| rename.uuid == pv.vg_uuid) {
+            continue;
+        }
+        let temporary = temporary_vg_name(&taken);
+        taken.push(temporary.clone());
+        repair_vm_renames.push(VgRename {
+            uuid: pv.vg_uuid.clone(),
+            from: pv.vg_name.clone(),
+            to: temporary,
+        });
+    }
+    ImportPlan {
+        clone_name: Some(clone_name),
+        repair_vm_renames,
+        keeps_clone_name: false,
+    }
+}
+
+// Splits a device-mapper name like my--vg-rootlv into the VG and the LV name. A hyphen within a name is doubled.
+pub(crate) fn split_dm_name(dm_name: &str) -> Option<(String, String)> {
+    let bytes = dm_name.as_bytes();
+    let mut position = 0;
+    while position < bytes.len() {
+        if bytes[position] == b'-' {
+            if bytes.get(position + 1) == Some(&b'-') {
+                position += 2;
+                continue;
+            }
+            let (vg, lv) = (&dm_name[..position], &dm_name[position + 1..]);
+            return Some((vg.replace("--", "-"), lv.replace("--", "-")));
+        }
+        position += 1;
+    }
+    None
+}
+
+fn is_lv_named(dm_name: &str, names: &[&str]) -> bool {
+    split_dm_name(dm_name).is_some_and(|(_, lv)| names.contains(&lv.as_str()))
+}
+
+pub(crate) fn is_root_lv(dm_name: &str) -> bool {
+    is_lv_named(dm_name, &ROOT_LV_NAMES)
+}
+
+pub(crate) fn is_usr_lv(dm_name: &str) -> bool {
+    is_lv_named(dm_name, &USR_LV_NAMES)
+}
+
+// The device-mapper name of a LV, the reverse of split_dm_name()
+pub(crate) fn dm_name(vg: &str, lv: &str) -> String {
+    format!("{}-{}", vg.replace('-', "--"), lv.replace('-', "--"))
+}
+
+fn lv_kind_of(segtype: &str) -> LvKind {
+    match segtype {
+        "linear" | "striped" | "mirror" | "vdo" | "raid0" | "raid0_meta" | "raid1" | "raid10" | "raid4" | "raid5" | "raid5_la"
+        | "raid5_ls" | "raid5_ra" | "raid5_rs" | "raid5_n" | "raid6" | "raid6_zr" | "raid6_nr" | "raid6_nc" => LvKind::Plain,
+        "thin-pool" => LvKind::ThinPool,
+        "thin" => LvKind::Thin,
+        "cache-pool" => LvKind::CachePool,
+        "cache" | "writecache" => LvKind::Cached,
+        _ => LvKind::Other,
+    }
+}
+
+// Parses the output of 'lvs -a --noheadings --separator ; -o lv_name,segtype,pool_lv'. A hidden LV is put in brackets.
+// An attached cache pool is the pool of its cached LV, an unattached cache volume shows up as a linear LV.
+fn parse_lvs(output: &str) -> Vec<LvInfo> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.trim().split(';').map(str::trim).collect();
+            let [name, segtype, pool] = fields.as_slice() else {
+                return None;
+            };
+            let is_hidden = name.starts_with('[');
+            let pool = pool.trim_matches(['[', ']']);
+            Some(LvInfo {
+                name: name.trim_matches(['[', ']']).to_string(),
+                kind: lv_kind_of(segtype),
+                pool: (!pool.is_empty()).then(|| pool.to_string()),
+                is_hidden,
+            })
+        })
+        .filter(|lv| !lv.name.is_empty())
+        .collect()
+}
+
+pub(crate) fn logical_volumes(vg_name: &str) -> Result<Vec<LvInfo>> {
+    let output = helper::run_fun(&format!(
+        "lvs -a --noheadings --separator ';' -o lv_name,segtype,pool_lv {vg_name}"
+    ))?;
+    Ok(parse_lvs(&output))
+}
+
+// Only the LVs which carry a filesystem are mounted, never a pool or a layer below a pool or a cache
+pub(crate) fn is_mountable_dm_name(dm_name: &str, volume_groups: &[VolumeGroup]) -> bool {
+    let Some((vg, lv)) = split_dm_name(dm_name) else {
+        return true;
+    };
+    match volume_groups
+        .iter()
+        .find(|volume_group| volume_group.active_name() == vg && !volume_group.logical_volumes.is_empty())
+    {
+        Some(volume_group) => volume_group
+            .logical_volumes
+            .iter()
+            .any(|info| info.name == lv && info.is_mountable()),
+        None => !INTERNAL_LV_SUFFIXES.iter().any(|suffix| lv.ends_with(suffix)),
+    }
+}
+
+// The metadata sub-LV of a pool and the tool checking it
+pub(crate) fn pool_metadata_check(pool: &LvInfo) -> Option<(String, &'static str)> {
+    match pool.kind {
+        LvKind::ThinPool => Some((format!("{}_tmeta", pool.name), "thin_check")),
+        LvKind::CachePool => Some((format!("{}_cmeta", pool.name), "cache_check")),
+        _ => None,
+    }
+}
+
+// The metadata of a pool can only be checked while the pool is inactive. Its metadata sub-LV is activated on its own
+// for the check. A damaged thin pool is repaired by 'lvconvert --repair', which runs thin_repair into the spare metadata LV.
+// A damaged cache pool has to be repaired manually, dropping the cache may lose data which is not written back yet.
+fn check_pool(vg_name: &str, pool: &LvInfo, lvs: &[LvInfo], policy: FsckPolicy) -> Result<()> {
+    let Some((metadata_lv, tool)) = pool_metadata_check(pool) else {
+        return Ok(());
+    };
+    if !lvs.iter().any(|lv| lv.name == metadata_lv) {
+        debug!("{vg_name}/{} has no metadata LV {metadata_lv}, nothing to check", pool.name);
+        return Ok(());
+    }
+    if helper::run_fun(&format!("command -v {tool}")).unwrap_or_default().trim().is_empty() {
+        return Err(anyhow!(
+            "{tool} is required to check the pool {vg_name}/{}. Install device-mapper-persistent-data or thin-provisioning-tools",
+            pool.name
+        ));
+    }
+
+    for user in lvs.iter().filter(|lv| lv.pool.as_ref() == Some(&pool.name)) {
+        let _ = helper::run_cmd(&format!("lvchange -an {vg_name}/{}", user.name));
+    }
+    let _ = helper::run_cmd(&format!("lvchange -an {vg_name}/{}", pool.name));
+    helper::run_cmd(&format!("lvchange -ay -y {vg_name}/{metadata_lv}"))
+        .with_context(|| format!("The metadata of the pool {vg_name}/{} can't be activated for the check", pool.name))?;
+    let is_healthy = helper::run_cmd(&format!("{tool} /dev/mapper/{}", dm_name(vg_name, &metadata_lv))).is_ok();
+    helper::run_cmd(&format!("lvchange -an {vg_name}/{metadata_lv}"))?;
+
+    if is_healthy {
+        info!("{tool}: the metadata of the pool {vg_name}/{} is fine", pool.name);
+        return Ok(());
+    }
+    match (policy, pool.kind) {
+        (FsckPolicy::Auto, LvKind::ThinPool) => {
+            info!("{tool}: the metadata of the pool {vg_name}/{} is damaged, it gets repaired", pool.name);
+            helper::run_cmd(&format!("lvconvert --repair -y {vg_name}/{}", pool.name))
+                .with_context(|| format!("The thin pool {vg_name}/{} can't be repaired", pool.name))
+        }
+        (FsckPolicy::Auto, _) => Err(anyhow!(
+            "{tool}: the metadata of the cache pool {vg_name}/{} is damaged and needs to be repaired manually",
+            pool.name
+        )),
+        _ => {
+            warn!("{tool}: the metadata of the pool {vg_name}/{} is damaged. It is not repaired with the fsck policy 'check'", pool.name);
+            Ok(())
+        }
+    }
+}
+
+// Checks the metadata of the thin and cache pools of the VG and activates the whole stack: the pools first, then the
+// thin LVs and the cached LVs on top of them
+pub(crate) fn activate_stacks(vg_name: &str, lvs: &[LvInfo], policy: FsckPolicy) -> Result<()> {
+    let pools: Vec<&LvInfo> = lvs.iter().filter(|lv| pool_metadata_check(lv).is_some()).collect();
+    if pools.is_empty() {
+        return Ok(());
+    }
+    if policy != FsckPolicy::Skip {
+        for pool in &pools {
+            check_pool(vg_name, pool, lvs, policy)?;
+        }
+    }
+    for pool in pools.iter().filter(|pool| pool.kind == LvKind::ThinPool) {
+        helper::run_cmd(&format!("lvchange -ay {vg_name}/{}", pool.name))
+            .with_context(|| format!("The thin pool {vg_name}/{} can't be activated", pool.name))?;
+    }
+    helper::run_cmd(&format!("vgchange -ay {vg_name}")).with_context(|| format!("The LVs of {vg_name} can't be activated"))
+}
+
+// A VG is only used as a whole, unless a partial VG is explicitly allowed
+pub(crate) fn verify_complete(volume_group: &VolumeGroup, allow_partial: bool) -> Result<()> {
+    if volume_group.is_incomplete() {
+        return Err(anyhow!(
+            "The VG {} has PVs on further disks: {}. Pass over their disks with '--additional-recover-disk'",
+            volume_group.name,
+            volume_group.pvs_elsewhere.join(", ")
+        ));
+    }
+    if volume_group.is_partial() {
+        if !allow_partial {
+            return Err(anyhow!(
+                "The VG {} is partial, the PVs {} are missing. Attach their disks or pass over '--allow-partial-vg' to use the VG nevertheless",
+                volume_group.name,
+                volume_group.missing_pvs.join(", ")
+            ));
+        }
+        warn!(
+            "The VG {} is partial, the PVs {} are missing. The LVs stored on them are not available.",
+            volume_group.name,
+            volume_group.missing_pvs.join(", ")
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PVS: &str = "  /dev/sda2;Aa1;rootvg;VgRepair;\n  /dev/sdc4;Cc4;rootvg;VgTarget;\n  /dev/sdd1;Dd1;rootvg;VgTarget;\n  /dev/sde1;Ee1;datavg;VgData;\n";
+
+    #[test]
+    fn pvs_of_a_volume_group() {
+        let pvs = parse_pvs(PVS);
+        assert_eq!(pvs.len(), 4);
+        assert_eq!(pvs[1].vg_uuid, "VgTarget");
+
+        let on_sdc_and_sdd = |name: &str| name.starts_with("/dev/sdc") || name.starts_with("/dev/sdd");
+        let volume_group = group_pvs("/dev/sdc4", &pvs, on_sdc_and_sdd).unwrap();
+        assert_eq!(volume_group.pvs, vec!["/dev/sdc4", "/dev/sdd1"]);
+        assert!(!volume_group.is_incomplete() && !volume_group.is_partial());
+        assert!(verify_complete(&volume_group, false).is_ok());
+
+        // Only sdc got passed over
+        let volume_group = group_pvs("/dev/sdc4", &pvs, |name| name.starts_with("/dev/sdc")).unwrap();
+        assert_eq!(volume_group.pvs_elsewhere, vec!["/dev/sdd1"]);
+        assert!(verify_complete(&volume_group, true).is_err());
+
+        assert!(group_pvs("/dev/sdf1", &pvs, on_sdc_and_sdd).is_err());
+    }
+
+    #[test]
+    fn thin_and_cached_lvs() {
+        let lvs = parse_lvs(
+            "  [lvol0_pmspare];linear;\n  pool;thin-pool;\n  [pool_tdata];linear;\n  [pool_tmeta];linear;\n  rootlv;linear;\n  \
+             thinlv;thin;pool\n  homelv;cache;[cpool]\n  [homelv_corig];linear;\n  [cpool];cache-pool;\n  \
+             [cpool_cdata];linear;\n  [cpool_cmeta];linear;\n",
+        );
+        assert_eq!(lvs.len(), 11);
+        assert_eq!(lvs[5].pool.as_deref(), Some("pool"));
+        assert_eq!((lvs[6].kind, lvs[6].pool.as_deref()), (LvKind::Cached, Some("cpool")));
+        let checks: Vec<(String, &str)> = lvs.iter().filter_map(pool_metadata_check).collect();
+        assert_eq!(
+            checks,
+            vec![("pool_tmeta".to_string(), "thin_check"), ("cpool_cmeta".to_string(), "cache_check")]
+        );
+
+        let volume_groups = [VolumeGroup {
+            name: "rootvg".to_string(),
+            logical_volumes: lvs,
+            ..VolumeGroup::default()
+        }];
+        let mountable: Vec<&str> = [
+            "rootvg-rootlv", "rootvg-thinlv", "rootvg-homelv", "rootvg-pool", "rootvg-pool-tpool", "rootvg-pool_tdata",
+            "rootvg-homelv_corig", "rootvg-cpool_cdata",
+        ]
+        .into_iter()
+        .filter(|dm_name| is_mountable_dm_name(dm_name, &volume_groups))
+        .collect();
+        assert_eq!(mountable, vec!["rootvg-rootlv", "rootvg-thinlv", "rootvg-homelv"]);
+
+        // Without the list of LVs only the sub-LVs are recognized
+        assert!(!is_mountable_dm_name("datavg-pool_tmeta", &volume_groups));
+        assert!(is_mountable_dm_name("datavg-datalv", &volume_groups));
+    }
+
+    #[test]
+    fn colliding_volume_groups() {
+        let pvs = parse_pvs(PVS);
+        let volume_group = group_pvs("/dev/sdc4", &pvs, |name| name.starts_with("/dev/sdc") || name.starts_with("/dev/sdd")).unwrap();
+        let import = plan_import(&volume_group, &pvs, false);
+        assert_eq!(import.clone_name.as_deref(), Some("alar_vg1"));
+        assert_eq!(
+            import.repair_vm_renames,
+            vec![VgRename {
+                uuid: "VgRepair".to_string(),
+                from: "rootvg".to_string(),
+                to: "alar_vg2".to_string()
+            }]
+        );
+
+        // The VG datavg doesn't collide with anything
+        let volume_group = group_pvs("/dev/sde1", &pvs, |name| name.starts_with("/dev/sde")).unwrap();
+        assert_eq!(plan_import(&volume_group, &pvs, false), ImportPlan::default());
+
+        // Both disks got created from the same image, the PV of the repair VM is a duplicate
+        let pvs = parse_pvs("  /dev/sda2;Aa1;system;VgSame;\n  /dev/sdc2;Aa1;system;VgSame;\n  /dev/sdc3;;;;\n");
+        assert_eq!(pvs.len(), 2);
+        let volume_group = group_pvs("/dev/sdc2", &pvs, |name| name.starts_with("/dev/sdc")).unwrap();
+        assert!(!volume_group.is_incomplete());
+        let import = plan_import(&volume_group, &pvs, false);
+        assert_eq!(import.clone_name.as_deref(), Some("alar_vg1"));
+        assert_eq!(import.repair_vm_renames[0].to, "alar_vg2");
+
+        // With the private configuration the VG of the repair VM stays untouched
+        let import = plan_import(&volume_group, &pvs, true);
+        assert!(import.keeps_clone_name && import.repair_vm_renames.is_empty());
+        let volume_group = VolumeGroup { import, ..volume_group };
+        assert_eq!(volume_group.active_name(), "alar_vg1");
+
+        assert_eq!(split_dm_name("my--vg-root--lv"), Some(("my-vg".to_string(), "root-lv".to_string())));
+        assert_eq!(dm_name("my-vg", "root-lv"), "my--vg-root--lv");
+        assert!(is_root_lv("centos-root") && is_root_lv("rootvg-rootlv") && !is_root_lv("vg00-homelv"));
+        assert!(is_usr_lv("vg00-usrlv"));
+    }
+
+    #[test]
+    fn metadata_with_the_original_name() {
+        let metadata = "# Generated by LVM2\ncontents = \"Text Format Volume Group\"\n\nalar_vg1 {\n\tid = \"VgTarget\"\n\tlogical_volumes {\n\t\talar_vg1 {\n\t\t}\n\t}\n}\n";
+        let renamed = rename_in_metadata(metadata, "alar_vg1", "rootvg");
+        assert!(renamed.contains("\nrootvg {\n\tid = \"VgTarget\"\n"));
+        // A LV carrying the same name as the clone isn't touched
+        assert!(renamed.contains("\t\talar_vg1 {\n"));
+        assert!(!renamed.contains("\nalar_vg1 {"));
+    }
+
+    #[test]
+    fn private_configuration() {
+        let config = private_config(&["/dev/sdc".to_string(), "/dev/sdc2".to_string()]);
+        assert!(config.contains("\tuse_devicesfile = 1\n"));
+        assert!(config.contains("\tfilter = [ \"a|^/dev/sdc$|\", \"a|^/dev/sdc2$|\", \"r|.*|\" ]\n"));
+        assert!(config.contains("\tbackup = 0\n"));
+    }
+
+    #[test]
+    fn partial_volume_group() {
+        let pvs = parse_pvs("  /dev/sdc4;Cc4;rootvg;VgTarget;\n  [unknown];Dd1;rootvg;VgTarget;missing\n");
+        let volume_group = group_pvs("/dev/sdc4", &pvs, |_| true).unwrap();
+        assert_eq!(volume_group.missing_pvs, vec!["Dd1"]);
+        assert!(verify_complete(&volume_group, false).is_err());
+        assert!(verify_complete(&volume_group, true).is_ok());
+    }
+}
@@ -0,0 +1,507 @@
+use crate::cli::{CliInfo, Initiator};
+use crate::probe::{self, FsType};
+use crate::{blockdev, helper, lvm};
+use anyhow::{Context, Result, anyhow};
+use chrono::DateTime;
+use log::{debug, info, warn};
+use regex::Regex;
+use std::fmt::Display;
+use std::fs::{self, File};
+use std::io::{self, IsTerminal, Read, Seek, SeekFrom, Write};
+
+// The recovery of damaged LVM metadata. LVM writes each new version of the VG metadata as text into a circular buffer in
+// the metadata area of every PV. The older versions stay in the buffer until they get overwritten. The copies in
+// /etc/lvm/archive and /etc/lvm/backup are not reachable as long as the VG holding the root LV can't be activated.
+//
+// The metadata areas of the recovery disks are scanned for complete text copies. The chosen copy is restored like
+// 'vgcfgrestore' does it, a PV whose label got lost gets it back with its original UUID first.
+
+// The copies are written here, so that they can be looked at before one of them gets restored
+const METADATA_DIR: &str = "/tmp/alar-lvm-metadata";
+// The metadata area starts at 4 KiB and ends before the first PE, which is at 1 MiB by default
+const DEFAULT_SCAN_SIZE: u64 = 1 << 20;
+// A larger metadata area, as given by the PV header, is scanned up to this size
+const MAX_SCAN_SIZE: u64 = 256 << 20;
+const TEXT_FORMAT_MARKER: &str = "contents = \"Text Format Volume Group\"";
+// The mda_header at the start of each metadata area, the circular buffer follows it
+const MDA_HEADER_MAGIC: &[u8; 16] = b" LVM2 x[5A%r0N*>";
+const MDA_HEADER_SIZE: u64 = 512;
+
+#[derive(Debug, Clone, PartialEq)]
+struct MetadataCopy {
+    vg_name: String,
+    vg_uuid: String,
+    seqno: u64,
+    creation_time: Option<i64>,
+    creation_host: Option<String>,
+    // The command which wrote this version, i.e. "Created *after* executing 'lvextend -L+1G rootvg/rootlv'"
+    description: String,
+    pv_uuids: Vec<String>,
+    text: String,
+    // The devices the copy got found on
+    devices: Vec<String>,
+}
+
+// A device of the recovery disks which is, or was, a PV
+#[derive(Debug)]
+struct ScannedDevice {
+    device: String,
+    // None if the PV label is damaged
+    pv_uuid: Option<String>,
+}
+
+fn value_of(text: &str, key: &str) -> Option<String> {
+    let pattern = Regex::new(&format!(r#"(?m)^\s*{key} = "?([^"\n]*?)"?\s*(#.*)?$"#)).ok()?;
+    pattern.captures(text).map(|captures| captures[1].to_string())
+}
+
+// The braces of the VG section have to be balanced, quoted strings aside, and the closing part of the text written by
+// LVM has to be there. A copy which got partly overwritten fails either test.
+fn is_complete(text: &str) -> bool {
+    let mut depth: i32 = 0;
+    let mut is_quoted = false;
+    let mut was_closed = false;
+    let mut previous = ' ';
+    for character in text.chars() {
+        match character {
+            '"' if previous != '\\' => is_quoted = !is_quoted,
+            '{' if !is_quoted => depth += 1,
+            '}' if !is_quoted => {
+                depth -= 1;
+                was_closed |= depth == 0;
+            }
+            _ => {}
+        }
+        if depth < 0 {
+            return false;
+        }
+        previous = character;
+    }
+    depth == 0 && was_closed && !is_quoted && text.contains(TEXT_FORMAT_MARKER)
+}
+
+// Each copy starts with the VG section, right after the NUL byte ending the previous copy, and ends with a NUL byte:
+// rootvg {
+// id = "jKzeL1-..."
+// seqno = 7
+fn parse_copies(area: &str, device: &str) -> Vec<MetadataCopy> {
+    let start = Regex::new(r#"(?m)(?:^|\x00)([A-Za-z0-9+_.][A-Za-z0-9+_.-]*) \{\n\s*id = "([^"]+)"\n\s*seqno = (\d+)\n"#).unwrap();
+    let pv_uuid = Regex::new(r#"pv\d+ \{\s*id = "([^"]+)""#).unwrap();
+
+    start
+        .captures_iter(area)
+        .filter_map(|captures| {
+            let begin = captures.get(1)?.start();
+            let end = area[begin..].find('\0').map_or(area.len(), |end| begin + end);
+            let text = &area[begin..end];
+            if !is_complete(text) {
+                debug!("The copy of {} with seqno {} on {device} is incomplete", &captures[1], &captures[3]);
+                return None;
+            }
+            let pv_uuids: Vec<String> = pv_uuid.captures_iter(text).map(|pv| pv[1].to_string()).collect();
+            if pv_uuids.is_empty() {
+                return None;
+            }
+            Some(MetadataCopy {
+                vg_name: captures[1].to_string(),
+                vg_uuid: captures[2].to_string(),
+                seqno: captures[3].parse().ok()?,
+                creation_time: value_of(text, "creation_time").and_then(|time| time.parse().ok()),
+                creation_host: value_of(text, "creation_host"),
+                description: value_of(text, "description").unwrap_or_default(),
+                pv_uuids,
+                text: format!("{}\n", text.trim_end()),
+                devices: vec![device.to_string()],
+            })
+        })
+        .collect()
+}
+
+// The same version is found on every PV of the VG. The newest versions are listed first.
+fn merge_copies(copies: Vec<MetadataCopy>) -> Vec<MetadataCopy> {
+    let mut merged: Vec<MetadataCopy> = Vec::new();
+    for copy in copies {
+        match merged
+            .iter_mut()
+            .find(|known| known.vg_uuid == copy.vg_uuid && known.seqno == copy.seqno)
+        {
+            Some(known) => {
+                for device in copy.devices {
+                    if !known.devices.contains(&device) {
+                        known.devices.push(device);
+                    }
+                }
+            }
+            None => merged.push(copy),
+        }
+    }
+    merged.sort_by(|a, b| a.vg_name.cmp(&b.vg_name).then(b.seqno.cmp(&a.seqno)));
+    merged
+}
+
+fn u64_at(buffer: &[u8], offset: usize) -> Option<u64> {
+    buffer.get(offset..offset + 8).map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap()))
+}
+
+// The start and the size of the first metadata area as given by the PV header, None if there is no intact label.
+// The header holds the PV UUID, the device size and two lists of offset and size pairs, each ended by a zero pair:
+// the data areas and the metadata areas.
+fn metadata_area(buffer: &[u8]) -> Option<(u64, u64)> {
+    let label_start = (0..4)
+        .map(|sector| sector * 512)
+        .find(|&start| buffer.get(start..start + 8) == Some(b"LABELONE") && buffer.get(start + 24..start + 32) == Some(b"LVM2 001"))?;
+    let offset = u32::from_le_bytes(buffer.get(label_start + 20..label_start + 24)?.try_into().unwrap());
+    let mut locations = label_start + offset as usize + 40;
+    // Skip the data areas
+    while u64_at(buffer, locations)? != 0 || u64_at(buffer, locations + 8)? != 0 {
+        locations += 16;
+    }
+    locations += 16;
+    let (start, size) = (u64_at(buffer, locations)?, u64_at(buffer, locations + 8)?);
+    (size != 0).then_some((start, size))
+}
+
+// The copy the mda_header records as the current one. The header holds a checksum, the magic, the version, the start
+// and the size of the area and a list of raw locations, the first of them is the current copy. Its offset is relative
+// to the start of the area. A copy which runs past the end of the area continues right after the header.
+fn current_copy(area: &[u8], area_start: u64) -> Option<String> {
+    let start = area_start as usize;
+    if area.get(start + 4..start + 20)? != MDA_HEADER_MAGIC {
+        return None;
+    }
+    let area_size = u64_at(area, start + 32)?;
+    let (offset, size) = (u64_at(area, start + 40)?, u64_at(area, start + 48)?);
+    if offset < MDA_HEADER_SIZE || size == 0 || offset >= area_size {
+        return None;
+    }
+    let mut text = if offset + size <= area_size {
+        area.get(start + offset as usize..start + (offset + size) as usize)?.to_vec()
+    } else {
+        let wrapped = (offset + size - area_size) as usize;
+        let header_end = start + MDA_HEADER_SIZE as usize;
+        let head = area.get(start + offset as usize..start + area_size as usize)?;
+        [head, area.get(header_end..header_end + wrapped)?].concat()
+    };
+    while text.last() == Some(&0) {
+        text.pop();
+    }
+    Some(String::from_utf8_lossy(&text).to_string())
+}
+
+// The whole metadata area and the current copy as the mda_header locates it
+fn read_metadata_area(device: &str) -> Result<(String, Option<String>)> {
+    let mut file = File::open(device).with_context(|| format!("{device} can not be opened"))?;
+    let device_size = file.seek(SeekFrom::End(0))?;
+    let mut label = vec![0u8; 4096.min(device_size as usize)];
+    file.seek(SeekFrom::Start(0))?;
+    file.read_exact(&mut label)?;
+
+    let location = metadata_area(&label);
+    let scan_size = location
+        .map_or(DEFAULT_SCAN_SIZE, |(start, size)| start + size)
+        .clamp(DEFAULT_SCAN_SIZE, MAX_SCAN_SIZE)
+        .min(device_size);
+    let mut area = vec![0u8; scan_size as usize];
+    file.seek(SeekFrom::Start(0))?;
+    file.read_exact(&mut area)
+        .with_context(|| format!("The metadata area of {device} can not be read"))?;
+    let current = location.and_then(|(start, _)| current_copy(&area, start));
+    Ok((String::from_utf8_lossy(&area).to_string(), current))
+}
+
+// The current copy may be damaged. An older copy is then the newest one found, this is worth a warning.
+fn warn_if_outdated(current: &str, found: &[MetadataCopy], device: &str) {
+    let Some(current_seqno) = value_of(current, "seqno").and_then(|seqno| seqno.parse::<u64>().ok()) else {
+        return;
+    };
+    let newest = found.iter().map(|copy| copy.seqno).max();
+    if newest.is_none_or(|newest| newest < current_seqno) {
+        warn!(
+            "The current metadata of {device} has the seqno {current_seqno}, the newest complete copy found has the seqno {}",
+            newest.map_or("-".to_string(), |newest| newest.to_string())
+        );
+    }
+}
+
+// Every device of the recovery disks which is a PV, or which carries no known signature at all, as a PV with a damaged
+// label does
+fn scan(cli_info: &CliInfo) -> Result<(Vec<ScannedDevice>, Vec<MetadataCopy>)> {
+    let mut devices: Vec<ScannedDevice> = Vec::new();
+    let mut copies: Vec<MetadataCopy> = Vec::new();
+    for disk in helper::get_recovery_disk_paths(cli_info)? {
+        let mut paths = vec![disk.clone()];
+        paths.extend(blockdev::partitions(&disk)?.into_iter().map(|partition| partition.device));
+        for path in paths {
+            let pv_uuid = match probe::probe_path(&path) {
+                Ok(Some(probe)) if probe.fstype == FsType::Lvm2Member => probe.uuid,
+                Ok(None) => None,
+                Ok(Some(_)) => continue,
+                Err(e) => {
+                    warn!("{path} is skipped: {e:#}");
+                    continue;
+                }
+            };
+            let (area, current) = read_metadata_area(&path)?;
+            let mut found = parse_copies(&area, &path);
+            // A current copy which wraps around the end of the area is only found as a whole this way
+            if let Some(current) = current {
+                let wrapped: Vec<MetadataCopy> = parse_copies(&current, &path)
+                    .into_iter()
+                    .filter(|copy| {
+                        !found.iter().any(|known| known.vg_uuid == copy.vg_uuid && known.seqno == copy.seqno)
+                    })
+                    .collect();
+                found.extend(wrapped);
+                warn_if_outdated(&current, &found, &path);
+            }
+            info!("{path}: {} metadata copies found, PV label {}", found.len(), pv_uuid.as_deref().unwrap_or("missing"));
+            if pv_uuid.is_some() || !found.is_empty() {
+                devices.push(ScannedDevice { device: path, pv_uuid });
+            }
+            copies.extend(found);
+        }
+    }
+    Ok((devices, merge_copies(copies)))
+}
+
+struct CopyTable<'a>(&'a [MetadataCopy]);
+
+impl Display for CopyTable<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{:<4}{:<16}{:>7}  {:<21}{:<20}DESCRIPTION", "#", "VG", "SEQNO", "CREATED", "HOST")?;
+        for (number, copy) in self.0.iter().enumerate() {
+            let created = copy
+                .creation_time
+                .and_then(|time| DateTime::from_timestamp(time, 0))
+                .map_or("-".to_string(), |time| time.format("%Y-%m-%d %H:%M:%S").to_string());
+            writeln!(
+                f,
+                "{:<4}{:<16}{:>7}  {:<21}{:<20}{}",
+                number + 1,
+                copy.vg_name,
+                copy.seqno,
+                created,
+                copy.creation_host.as_deref().unwrap_or("-"),
+                copy.description
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn file_of(copy: &MetadataCopy) -> String {
+    format!("{METADATA_DIR}/{}-{}.vg", copy.vg_name, copy.seqno)
+}
+
+// The PVs of the copy, each with its device. A PV without a label is matched to the one device without a label that
+// carries the copy. If there is more than one of them, the assignment is up to the administrator.
+fn assign_devices(copy: &MetadataCopy, devices: &[ScannedDevice]) -> Result<Vec<(String, String, bool)>> {
+    let unlabeled: Vec<&ScannedDevice> = devices
+        .iter()
+        .filter(|device| device.pv_uuid.is_none() && copy.devices.contains(&device.device))
+        .collect();
+    let mut assigned = Vec::new();
+    let mut missing = Vec::new();
+    for pv_uuid in &copy.pv_uuids {
+        match devices.iter().find(|device| device.pv_uuid.as_ref() == Some(pv_uuid)) {
+            Some(device) => assigned.push((pv_uuid.clone(), device.device.clone(), false)),
+            None => missing.push(pv_uuid.clone()),
+        }
+    }
+    match (missing.as_slice(), unlabeled.as_slice()) {
+        ([], _) => {}
+        ([pv_uuid], [device]) => assigned.push((pv_uuid.clone(), device.device.clone(), true)),
+        _ => {
+            return Err(anyhow!(
+                "The PVs {} of {} can't be assigned to a device of the recovery disks. Devices without a PV label: {}",
+                missing.join(", "),
+                copy.vg_name,
+                unlabeled.iter().map(|device| device.device.as_str()).collect::<Vec<_>>().join(", ")
+            ));
+        }
+    }
+    Ok(assigned)
+}
+
+fn ask(question: &str) -> Result<String> {
+    print!("{question}");
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(answer.trim().to_string())
+}
+
+fn choose(copies: &[MetadataCopy], candidate: Option<usize>, is_interactive: bool) -> Result<usize> {
+    if let Some(number) = candidate {
+        return (1..=copies.len())
+            .contains(&number)
+            .then_some(number - 1)
+            .ok_or_else(|| anyhow!("There is no metadata copy number {number}"));
+    }
+    if !is_interactive {
+        return Err(anyhow!("Select the copy to be restored with '--candidate <NUMBER>'"));
+    }
+    loop {
+        let answer = ask(&format!("Select the copy to be restored [1-{}] or 'q' to quit: ", copies.len()))?;
+        match answer.as_str() {
+            "q" | "Q" | "" => return Err(anyhow!("No metadata copy got selected")),
+            answer => match answer.parse::<usize>() {
+                Ok(number) if (1..=copies.len()).contains(&number) => return Ok(number - 1),
+                _ => println!("'{answer}' is not one of the listed copies"),
+            },
+        }
+    }
+}
+
+// A VG of the repair VM with the same name or UUID would be taken for the restored one
+fn verify_no_collision(copy: &MetadataCopy, devices: &[ScannedDevice]) -> Result<()> {
+    if lvm::is_private() {
+        return Ok(());
+    }
+    let collision = lvm::physical_volumes()?.into_iter().find(|pv| {
+        (pv.vg_name == copy.vg_name || pv.vg_uuid == copy.vg_uuid)
+            && !devices.iter().any(|device| device.device == pv.name)
+    });
+    match collision {
+        Some(pv) => Err(anyhow!(
+            "The repair VM uses the VG {} on {}, which collides with the VG to be restored. Use a repair VM with another VG name",
+            pv.vg_name,
+            pv.name
+        )),
+        None => Ok(()),
+    }
+}
+
+fn restore(copy: &MetadataCopy, assigned: &[(String, String, bool)]) -> Result<()> {
+    let file = file_of(copy);
+    for (pv_uuid, device, needs_label) in assigned {
+        lvm::allow_device(device)?;
+        if *needs_label {
+            helper::run_cmd(&format!("pvcreate --uuid {pv_uuid} --restorefile {file} {device}"))
+                .with_context(|| format!("The PV label of {device} can't be re-created"))?;
+            info!("The PV label of {device} got re-created with the UUID {pv_uuid}");
+        }
+    }
+    helper::run_cmd(&format!("vgcfgrestore -f {file} {}", copy.vg_name))
+        .with_context(|| format!("vgcfgrestore of {} failed", copy.vg_name))?;
+    let _ = helper::run_cmd("pvscan --cache");
+    info!("The metadata of {} got restored from the copy with seqno {}", copy.vg_name, copy.seqno);
+    Ok(())
+}
+
+// Lists the metadata copies of the recovery disks and restores the chosen one after it got confirmed. The confirmation
+// is given by '--yes' if ALAR doesn't run on a terminal.
+pub(crate) fn recover(cli_info: &CliInfo, candidate: Option<usize>, is_confirmed: bool) -> Result<()> {
+    lvm::use_private_config(&helper::get_recovery_disk_paths(cli_info)?)?;
+    let (devices, copies) = scan(cli_info)?;
+    if copies.is_empty() {
+        return Err(anyhow!("No complete LVM metadata copy found on the recovery disks"));
+    }
+
+    fs::create_dir_all(METADATA_DIR)?;
+    for copy in &copies {
+        fs::write(file_of(copy), &copy.text)?;
+    }
+    println!("LVM metadata copies found on the recovery disks:\n{}", CopyTable(&copies));
+    println!("Each copy is saved as {METADATA_DIR}/<VG>-<SEQNO>.vg");
+
+    let is_interactive = matches!(cli_info.initiator, Initiator::Cli) && io::stdin().is_terminal();
+    let copy = &copies[choose(&copies, candidate, is_interactive)?];
+    verify_no_collision(copy, &devices)?;
+    let assigned = assign_devices(copy, &devices)?;
+
+    println!("The metadata of {} gets restored from the copy with seqno {}:", copy.vg_name, copy.seqno);
+    for (pv_uuid, device, needs_label) in &assigned {
+        let label = if *needs_label { ", the PV label gets re-created" } else { "" };
+        println!("  {device}: PV {pv_uuid}{label}");
+    }
+    let is_confirmed = is_confirmed
+        || (is_interactive && ask("Type 'yes' to restore it: ")?.eq_ignore_ascii_case("yes"));
+    if !is_confirmed {
+        return Err(anyhow!("The restore didn't get confirmed, nothing got changed. Pass '--yes' to confirm it"));
+    }
+    restore(copy, &assigned)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn copy_text(seqno: u32, description: &str) -> String {
+        format!(
+            "rootvg {{\nid = \"vg-uuid\"\nseqno = {seqno}\nformat = \"lvm2\"\nstatus = [\"RESIZEABLE\", \"READ\", \"WRITE\"]\n\
+             physical_volumes {{\n\npv0 {{\nid = \"pv-uuid\"\ndevice = \"/dev/sda4\"\n}}\n}}\n}}\n\
+             # Generated by LVM2 version 2.03.23(2)\n\n{TEXT_FORMAT_MARKER}\nversion = 1\n\n\
+             description = \"{description}\"\n\ncreation_host = \"rhel9\"\t# Linux rhel9\n\
+             creation_time = 1700000000\t# Tue Nov 14 22:13:20 2023\n\n"
+        )
+    }
+
+    #[test]
+    fn copies_of_a_metadata_area() {
+        let newest = copy_text(8, "");
+        let older = copy_text(7, "Created *after* executing 'lvextend -L+1G rootvg/rootlv'");
+        // The start of the oldest copy got overwritten by the newest one
+        let overwritten = &copy_text(6, "")[40..];
+        let area = format!("\0\0{older}\0{newest}\0{overwritten}\0\0\0");
+
+        let copies = merge_copies(
+            [parse_copies(&area, "/dev/sdc4"), parse_copies(&area, "/dev/sdd1")].concat(),
+        );
+        let summary: Vec<(u64, &str, Vec<String>)> = copies
+            .iter()
+            .map(|copy| (copy.seqno, copy.description.as_str(), copy.devices.clone()))
+            .collect();
+        let devices = vec!["/dev/sdc4".to_string(), "/dev/sdd1".to_string()];
+        assert_eq!(
+            summary,
+            vec![
+                (8, "", devices.clone()),
+                (7, "Created *after* executing 'lvextend -L+1G rootvg/rootlv'", devices),
+            ]
+        );
+        assert_eq!(copies[0].pv_uuids, vec!["pv-uuid"]);
+        assert_eq!(copies[0].creation_host.as_deref(), Some("rhel9"));
+        assert_eq!(copies[0].creation_time, Some(1700000000));
+        assert!(!is_complete(&copy_text(5, "")[..120]));
+    }
+
+    #[test]
+    fn metadata_area_of_the_pv_header() {
+        let mut label = vec![0u8; 4096];
+        label[512..520].copy_from_slice(b"LABELONE");
+        label[512 + 20..512 + 24].copy_from_slice(&32u32.to_le_bytes());
+        label[512 + 24..512 + 32].copy_from_slice(b"LVM2 001");
+        // One data area at 1 MiB, then the metadata area from 4 KiB to 2 MiB
+        let header = 512 + 32 + 40;
+        label[header..header + 8].copy_from_slice(&(1u64 << 20).to_le_bytes());
+        label[header + 32..header + 40].copy_from_slice(&4096u64.to_le_bytes());
+        label[header + 40..header + 48].copy_from_slice(&((2u64 << 20) - 4096).to_le_bytes());
+        assert_eq!(metadata_area(&label), Some((4096, (2 << 20) - 4096)));
+        assert_eq!(metadata_area(&[0u8; 4096]), None);
+    }
+
+    #[test]
+    fn current_copy_wrapped_around_the_area() {
+        let text = copy_text(9, "");
+        let (area_start, area_size) = (4096usize, 8192usize);
+        // The copy starts 100 bytes before the end of the area and continues right after the mda_header
+        let offset = area_size - 100;
+        let mut area = vec![0u8; area_start + area_size];
+        let header = area_start;
+        area[header + 4..header + 20].copy_from_slice(MDA_HEADER_MAGIC);
+        area[header + 24..header + 32].copy_from_slice(&(area_start as u64).to_le_bytes());
+        area[header + 32..header + 40].copy_from_slice(&(area_size as u64).to_le_bytes());
+        area[header + 40..header + 48].copy_from_slice(&(offset as u64).to_le_bytes());
+        area[header + 48..header + 56].copy_from_slice(&(text.len() as u64).to_le_bytes());
+        let (head, tail) = text.as_bytes().split_at(100);
+        area[area_start + offset..area_start + area_size].copy_from_slice(head);
+        let wrapped = area_start + MDA_HEADER_SIZE as usize;
+        area[wrapped..wrapped + tail.len()].copy_from_slice(tail);
+
+        assert!(parse_copies(&String::from_utf8_lossy(&area), "/dev/sdc4").is_empty());
+        let current = current_copy(&area, area_start as u64).unwrap();
+        assert_eq!(current, text);
+        assert_eq!(parse_copies(&current, "/dev/sdc4")[0].seqno, 9);
+    }
+}
@@ -0,0 +1,613 @@
+use crate::action::ActionResult;
+use crate::{constants, distro, helper, lvm};
+use anyhow::{Context, Result, anyhow};
+use chrono::Utc;
+use log::{debug, error, info};
+use regex::Regex;
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use std::{env, fs};
+
+// The builtin action 'lvmdevices' compares the LVM configuration of the recovered system with the PVs of its VGs.
+// A disk which got moved to another VM, or re-created from a snapshot, gets new device IDs. The devices file of the
+// recovered system then lists IDs no disk carries any more and the VM doesn't find its root VG at boot. The same
+// happens if the filter in lvm.conf only accepts device names the PVs don't have any more.
+//
+// The expected entries of the devices file are generated by 'lvmdevices --adddev' for the PVs of the recovery disks.
+// A device ID is a property of the disk, it is the same on the repair VM and on the recovered VM. The device names are
+// not, the names the PVs carry on the recovered VM are taken from its files.
+
+// Paths relative to the root of the recovered system
+const DEVICES_FILE: &str = "etc/lvm/devices/system.devices";
+const LVM_CONFIG_FILES: [&str; 2] = ["etc/lvm/lvm.conf", "etc/lvm/lvmlocal.conf"];
+const METADATA_BACKUP_DIR: &str = "etc/lvm/backup";
+const FILTER_SETTINGS: [&str; 2] = ["filter", "global_filter"];
+// The expected entries are written to this devices file of the LVM configuration ALAR uses
+const EXPECTED_DEVICES_FILE: &str = "alar-expected.devices";
+// Device names the recovered VM can't resolve
+const DEVNAME_ID_TYPE: &str = "devname";
+// Symlinks of the disk itself. The by-path and the Azure LUN links depend on the VM the disk is attached to.
+const STABLE_LINK_DIRS: [&str; 4] = ["disk/by-id/", "disk/by-uuid/", "disk/by-partuuid/", "disk/by-partlabel/"];
+
+// A line of a devices file: IDTYPE=sys_wwid IDNAME=naa.6002248... DEVNAME=/dev/sda2 PVID=RkMXaZ... PART=2
+// The fields are kept in their order. A field unknown to ALAR is left as it is.
+#[derive(Debug, Clone, PartialEq)]
+struct DeviceEntry {
+    fields: Vec<(String, String)>,
+}
+
+impl DeviceEntry {
+    fn parse(line: &str) -> Option<DeviceEntry> {
+        if line.trim_start().starts_with('#') {
+            return None;
+        }
+        let fields: Vec<(String, String)> = line
+            .split_whitespace()
+            .filter_map(|field| field.split_once('='))
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect();
+        let entry = DeviceEntry { fields };
+        (!entry.get("PVID").is_empty() && !entry.get("IDTYPE").is_empty()).then_some(entry)
+    }
+
+    fn get(&self, key: &str) -> &str {
+        self.fields
+            .iter()
+            .find(|(name, _)| name == key)
+            .map_or("", |(_, value)| value)
+    }
+
+    fn set(&mut self, key: &str, value: &str) {
+        match self.fields.iter_mut().find(|(name, _)| name == key) {
+            Some((_, current)) => *current = value.to_string(),
+            None => self.fields.push((key.to_string(), value.to_string())),
+        }
+    }
+
+    fn id(&self) -> String {
+        format!("{}={}", self.get("IDTYPE"), self.get("IDNAME"))
+    }
+
+    fn is_same_device(&self, other: &DeviceEntry) -> bool {
+        self.get("IDTYPE") == other.get("IDTYPE") && self.get("IDNAME") == other.get("IDNAME")
+    }
+
+    // The name the device carries on the recovered VM. LVM corrects DEVNAME itself, but an IDNAME of type devname
+    // has to be right.
+    fn with_device_name(&self, device_name: &str) -> DeviceEntry {
+        let mut entry = self.clone();
+        entry.set("DEVNAME", device_name);
+        if entry.get("IDTYPE") == DEVNAME_ID_TYPE {
+            entry.set("IDNAME", device_name);
+        }
+        entry
+    }
+}
+
+impl Display for DeviceEntry {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let fields: Vec<String> = self.fields.iter().map(|(key, value)| format!("{key}={value}")).collect();
+        write!(f, "{}", fields.join(" "))
+    }
+}
+
+// The devices file uses the PV UUID without the dashes
+fn pvid_of(pv_uuid: &str) -> String {
+    pv_uuid.replace('-', "")
+}
+
+#[derive(Debug, Default, PartialEq)]
+struct DevicesFileCheck {
+    mismatches: Vec<String>,
+    content: String,
+}
+
+// Compares the devices file of the recovered system with the entries the PVs require and returns the corrected file.
+// An entry matches if it names the PV by the same device ID. An entry of type devname is checked by LVM against the PVID,
+// it matches if the PVID is the same. An entry with the device ID of a PV but another PVID is left from before
+// vgimportclone gave the PV a new UUID. Entries of other devices are kept.
+fn check_devices_file(content: &str, expected: &[DeviceEntry]) -> DevicesFileCheck {
+    let mut check = DevicesFileCheck::default();
+    let mut is_placed = vec![false; expected.len()];
+    let mut lines: Vec<String> = Vec::new();
+
+    for line in content.lines() {
+        let Some(entry) = DeviceEntry::parse(line) else {
+            lines.push(line.to_string());
+            continue;
+        };
+        let position = expected
+            .iter()
+            .position(|pv| pv.get("PVID") == entry.get("PVID"))
+            .or_else(|| expected.iter().position(|pv| pv.is_same_device(&entry)));
+        let Some(position) = position else {
+            lines.push(line.to_string());
+            continue;
+        };
+
+        let pv = &expected[position];
+        let pvid = pv.get("PVID");
+        if is_placed[position] {
+            check.mismatches.push(format!("PV {pvid} is listed twice, the entry '{line}' is removed"));
+        } else if entry.get("PVID") != pvid {
+            check.mismatches.push(format!(
+                "The entry of {} carries the old PVID {} instead of {pvid}",
+                entry.id(),
+                entry.get("PVID")
+            ));
+            lines.push(pv.with_device_name(entry.get("DEVNAME")).to_string());
+        } else if pv.is_same_device(&entry) || entry.get("IDTYPE") == DEVNAME_ID_TYPE {
+            lines.push(line.to_string());
+        } else {
+            check.mismatches.push(format!("PV {pvid} is listed as {}, the disk has {}", entry.id(), pv.id()));
+            lines.push(pv.with_device_name(entry.get("DEVNAME")).to_string());
+        }
+        is_placed[position] = true;
+    }
+
+    for (pv, _) in expected.iter().zip(is_placed).filter(|(_, is_placed)| !is_placed) {
+        check.mismatches.push(format!("PV {} is not listed", pv.get("PVID")));
+        lines.push(pv.to_string());
+    }
+
+    check.content = lines.join("\n") + "\n";
+    check
+}
+
+// The PV UUIDs and the device names of the PVs as recorded by the metadata backup of the recovered system:
+//     pv0 {
+//         id = "RkMXaZ-..."
+//         device = "/dev/sda4"    # Hint only
+fn metadata_device_hints(content: &str) -> Vec<(String, String)> {
+    let pattern = Regex::new(r#"id = "([^"]+)"\s*\n\s*device = "([^"]+)""#).unwrap();
+    pattern
+        .captures_iter(content)
+        .map(|captures| (captures[1].to_string(), captures[2].to_string()))
+        .collect()
+}
+
+// Parses the output of 'lvmconfig devices/filter': filter=["a|^/dev/sda|","r|.*|"]
+fn parse_filter(output: &str) -> Vec<String> {
+    let Some((_, value)) = output.trim().split_once('=') else {
+        return Vec::new();
+    };
+    let mut patterns = Vec::new();
+    let mut pattern: Option<String> = None;
+    let mut chars = value.chars();
+    while let Some(character) = chars.next() {
+        match (&mut pattern, character) {
+            (None, '"') => pattern = Some(String::new()),
+            (Some(current), '\\') => current.extend(chars.next()),
+            (Some(_), '"') => patterns.extend(pattern.take()),
+            (Some(current), character) => current.push(character),
+            (None, _) => {}
+        }
+    }
+    patterns
+}
+
+// "a|^/dev/sda|" is split into the accept flag and the regular expression. The delimiter is the second character.
+fn split_pattern(pattern: &str) -> Option<(bool, Regex)> {
+    let mut chars = pattern.chars();
+    let is_accept = match chars.next()? {
+        'a' => true,
+        'r' => false,
+        _ => return None,
+    };
+    let delimiter = chars.next()?;
+    let expression = chars.as_str().strip_suffix(delimiter)?;
+    Regex::new(expression).ok().map(|regex| (is_accept, regex))
+}
+
+// Applies the rules of LVM: the first pattern which matches a name decides on it. A device is accepted if one of its
+// names is accepted, or if none of its names matches any pattern at all.
+fn is_accepted(patterns: &[String], names: &[String]) -> bool {
+    let patterns: Vec<(bool, Regex)> = patterns
+        .iter()
+        .filter_map(|pattern| {
+            let split = split_pattern(pattern);
+            if split.is_none() {
+                debug!("The filter pattern {pattern} is not understood and ignored");
+            }
+            split
+        })
+        .collect();
+    let mut is_rejected = false;
+    for name in names {
+        match patterns.iter().find(|(_, regex)| regex.is_match(name)) {
+            Some((true, _)) => return true,
+            Some((false, _)) => is_rejected = true,
+            None => {}
+        }
+    }
+    !is_rejected
+}
+
+// The name of the udev link every PV gets, on the repair VM as well as on the recovered VM
+fn pv_uuid_link(pv_uuid: &str) -> String {
+    format!("/dev/disk/by-id/lvm-pv-uuid-{pv_uuid}")
+}
+
+fn accept_pattern(pv_uuid: &str) -> String {
+    format!("a|^{}$|", pv_uuid_link(pv_uuid))
+}
+
+// Inserts the patterns at the beginning of the filter setting. None if the file doesn't set it.
+fn prepend_to_setting(content: &str, key: &str, patterns: &[String]) -> Option<String> {
+    let mut offset = 0;
+    for line in content.split_inclusive('\n') {
+        let is_setting = line
+            .trim_start()
+            .strip_prefix(key)
+            .is_some_and(|rest| rest.trim_start().starts_with('='));
+        if is_setting {
+            let bracket = offset + content[offset..].find('[')?;
+            let inserted: Vec<String> = patterns.iter().map(|pattern| format!(" \"{pattern}\",")).collect();
+            return Some(format!(
+                "{}{}{}",
+                &content[..=bracket],
+                inserted.concat(),
+                &content[bracket + 1..]
+            ));
+        }
+        offset += line.len();
+    }
+    None
+}
+
+// A PV of the recovered system as it is found now
+#[derive(Debug)]
+struct TargetPv {
+    uuid: String,
+    expected: DeviceEntry,
+    // All names of the PV which the recovered VM knows too
+    names: Vec<String>,
+}
+
+fn target_path(relative: &str) -> PathBuf {
+    Path::new(constants::RESCUE_ROOT).join(relative)
+}
+
+// Runs a command of the recovered system with its own LVM configuration
+fn run_in_target(command: &str) -> Result<String> {
+    helper::run_fun(&format!(
+        "env -u LVM_SYSTEM_DIR chroot {} /bin/bash -c '{command}'",
+        constants::RESCUE_ROOT
+    ))
+}
+
+fn is_in_target(command: &str) -> bool {
+    run_in_target(&format!("command -v {command}")).is_ok_and(|output| !output.trim().is_empty())
+}
+
+// The devices file of the recovered system is ignored if it turned off the use of it
+fn is_devices_file_enabled() -> bool {
+    let output = run_in_target("lvmconfig --typeconfig full devices/use_devicesfile").unwrap_or_default();
+    output.trim() != "use_devicesfile=0"
+}
+
+fn expected_entries(devices: &[String]) -> Result<Vec<DeviceEntry>> {
+    let lvm_dir = if lvm::is_private() {
+        constants::PRIVATE_LVM_DIR.to_string()
+    } else {
+        env::var("LVM_SYSTEM_DIR").unwrap_or_else(|_| "/etc/lvm".to_string())
+    };
+    let devices_dir = format!("{lvm_dir}/devices");
+    let is_new_dir = !Path::new(&devices_dir).exists();
+    fs::create_dir_all(&devices_dir)?;
+    let path = format!("{devices_dir}/{EXPECTED_DEVICES_FILE}");
+    let _ = fs::remove_file(&path);
+
+    let added = devices.iter().try_for_each(|device| {
+        helper::run_cmd(&format!("lvmdevices --yes --devicesfile {EXPECTED_DEVICES_FILE} --adddev {device}"))
+            .with_context(|| format!("The device ID of {device} can't be determined with lvmdevices"))
+    });
+    let content = fs::read_to_string(&path).unwrap_or_default();
+    let _ = fs::remove_file(&path);
+    if is_new_dir {
+        let _ = fs::remove_dir(&devices_dir);
+    }
+    added?;
+    Ok(content.lines().filter_map(DeviceEntry::parse).collect())
+}
+
+fn udev_links(device: &str) -> Vec<String> {
+    helper::run_fun(&format!("udevadm info --query=symlink --name={device}"))
+        .unwrap_or_default()
+        .split_whitespace()
+        .filter(|link| STABLE_LINK_DIRS.iter().any(|dir| link.starts_with(dir)))
+        .map(|link| format!("/dev/{link}"))
+        .collect()
+}
+
+fn target_pvs(distro: &distro::Distro, devices_file: &str) -> Result<Vec<TargetPv>> {
+    let devices: Vec<String> = distro
+        .volume_groups
+        .iter()
+        .flat_map(|volume_group| volume_group.pvs.iter().cloned())
+        .collect();
+    let pvs = lvm::physical_volumes()?;
+    let expected = expected_entries(&devices)?;
+
+    let mut hints: Vec<(String, String)> = Vec::new();
+    if let Ok(entries) = fs::read_dir(target_path(METADATA_BACKUP_DIR)) {
+        for entry in entries.flatten() {
+            hints.extend(metadata_device_hints(&fs::read_to_string(entry.path()).unwrap_or_default()));
+        }
+    }
+    let listed: Vec<DeviceEntry> = devices_file.lines().filter_map(DeviceEntry::parse).collect();
+
+    let mut target_pvs = Vec::new();
+    for device in &devices {
+        let uuid = pvs
+            .iter()
+            .find(|pv| &pv.name == device)
+            .map(|pv| pv.uuid.clone())
+            .ok_or_else(|| anyhow!("{device} is not known to LVM as a PV"))?;
+        let entry = expected
+            .iter()
+            .find(|entry| entry.get("PVID") == pvid_of(&uuid))
+            .ok_or_else(|| anyhow!("lvmdevices didn't list {device}"))?;
+
+        let mut names = vec![pv_uuid_link(&uuid)];
+        names.extend(udev_links(device));
+        let hint = hints
+            .iter()
+            .find(|(hint_uuid, _)| *hint_uuid == uuid)
+            .map(|(_, name)| name.clone());
+        names.extend(hint.clone());
+        names.extend(
+            listed
+                .iter()
+                .filter(|listed| listed.get("PVID") == entry.get("PVID") || listed.is_same_device(entry))
+                .map(|listed| listed.get("DEVNAME").to_string()),
+        );
+        names.retain(|name| !name.is_empty());
+        names.sort();
+        names.dedup();
+
+        target_pvs.push(TargetPv {
+            expected: match &hint {
+                Some(name) => entry.with_device_name(name),
+                None => entry.clone(),
+            },
+            uuid,
+            names,
+        });
+    }
+    Ok(target_pvs)
+}
+
+// The original file is kept next to it
+fn backup_and_write(relative: &str, content: &str) -> Result<String> {
+    let path = target_path(relative);
+    let backup = format!("{}.alar-{}", path.display(), Utc::now().format("%Y%m%dT%H%M%SZ"));
+    fs::copy(&path, &backup).with_context(|| format!("{} can't be backed up", path.display()))?;
+    fs::write(&path, content).with_context(|| format!("{} can't be written", path.display()))?;
+    Ok(backup)
+}
+
+fn initramfs_images() -> Vec<String> {
+    let Ok(entries) = fs::read_dir(target_path("boot")) else {
+        return Vec::new();
+    };
+    let mut images: Vec<String> = entries
+        .flatten()
+        .map(|entry| entry.file_name().to_string_lossy().to_string())
+        .filter(|name| {
+            !name.contains("kdump")
+                && ((name.starts_with("initramfs-") && name.ends_with(".img"))
+                    || name.starts_with("initrd.img-")
+                    || name.starts_with("initrd-"))
+        })
+        .map(|name| format!("/boot/{name}"))
+        .collect();
+    images.sort();
+    images
+}
+
+// An initramfs which carries one of the changed files boots with the old configuration until it gets rebuilt.
+// If the content can't be listed it gets rebuilt to be on the safe side.
+fn is_rebuild_needed(changed: &[&str]) -> bool {
+    let lister = ["lsinitrd", "lsinitramfs"].into_iter().find(|lister| is_in_target(lister));
+    initramfs_images().iter().any(|image| {
+        let listing = lister.and_then(|lister| run_in_target(&format!("{lister} {image}")).ok());
+        match listing {
+            Some(listing) if !listing.trim().is_empty() => listing
+                .lines()
+                .any(|line| changed.iter().any(|file| line.trim_end().ends_with(file))),
+            _ => true,
+        }
+    })
+}
+
+fn rebuild_initramfs() -> Result<String> {
+    let command = if is_in_target("dracut") {
+        "dracut -f --regenerate-all"
+    } else if is_in_target("update-initramfs") {
+        "update-initramfs -u -k all"
+    } else {
+        return Err(anyhow!("Neither dracut nor update-initramfs is available to rebuild the initramfs"));
+    };
+    helper::run_cmd(&format!(
+        "env -u LVM_SYSTEM_DIR chroot {} /bin/bash -c '{command}'",
+        constants::RESCUE_ROOT
+    ))?;
+    Ok(command.to_string())
+}
+
+struct Report {
+    output: String,
+}
+
+impl Report {
+    fn line(&mut self, line: &str) {
+        info!("[{}] {line}", constants::LVMDEVICES);
+        self.output.push_str(line);
+        self.output.push('\n');
+    }
+}
+
+fn repair(distro: &distro::Distro, report: &mut Report) -> Result<()> {
+    if distro.volume_groups.is_empty() {
+        report.line("The recovered system doesn't use LVM, nothing to do");
+        return Ok(());
+    }
+
+    let devices_file_path = target_path(DEVICES_FILE);
+    let devices_file = fs::read_to_string(&devices_file_path).ok();
+    let pvs = target_pvs(distro, devices_file.as_deref().unwrap_or_default())?;
+    debug!("PVs of the recovered system: {pvs:#?}");
+    let mut changed: Vec<&str> = Vec::new();
+
+    match devices_file {
+        None => report.line(&format!("/{DEVICES_FILE} doesn't exist, LVM uses all devices")),
+        Some(_) if !is_devices_file_enabled() => {
+            report.line(&format!("/{DEVICES_FILE} exists but its use is turned off in lvm.conf"))
+        }
+        Some(content) => {
+            let expected: Vec<DeviceEntry> = pvs.iter().map(|pv| pv.expected.clone()).collect();
+            let check = check_devices_file(&content, &expected);
+            if check.mismatches.is_empty() {
+                report.line(&format!("/{DEVICES_FILE} matches the PVs"));
+            } else {
+                check.mismatches.iter().for_each(|mismatch| report.line(mismatch));
+                let backup = backup_and_write(DEVICES_FILE, &check.content)?;
+                report.line(&format!("/{DEVICES_FILE} got rewritten, the original is saved as {backup}"));
+                changed.push(DEVICES_FILE);
+            }
+        }
+    }
+
+    for key in FILTER_SETTINGS {
+        let patterns = parse_filter(&run_in_target(&format!("lvmconfig devices/{key}")).unwrap_or_default());
+        if patterns.is_empty() {
+            continue;
+        }
+        let rejected: Vec<&TargetPv> = pvs.iter().filter(|pv| !is_accepted(&patterns, &pv.names)).collect();
+        if rejected.is_empty() {
+            report.line(&format!("The {key} accepts all PVs"));
+            continue;
+        }
+        for pv in &rejected {
+            report.line(&format!("The {key} rejects PV {} with the names {}", pv.uuid, pv.names.join(", ")));
+        }
+
+        let accepted: Vec<String> = rejected.iter().map(|pv| accept_pattern(&pv.uuid)).collect();
+        let file = LVM_CONFIG_FILES.into_iter().find_map(|file| {
+            let content = fs::read_to_string(target_path(file)).ok()?;
+            prepend_to_setting(&content, key, &accepted).map(|content| (file, content))
+        });
+        let Some((file, content)) = file else {
+            return Err(anyhow!("The {key} setting can't be found in {}", LVM_CONFIG_FILES.join(" or ")));
+        };
+        let backup = backup_and_write(file, &content)?;
+        report.line(&format!("The {key} in /{file} accepts the PVs by their UUID now, the original is saved as {backup}"));
+        if !changed.contains(&file) {
+            changed.push(file);
+        }
+    }
+
+    if changed.is_empty() {
+        return Ok(());
+    }
+    if is_rebuild_needed(&changed) {
+        let command = rebuild_initramfs().context("The initramfs can't be rebuilt")?;
+        report.line(&format!("The initramfs got rebuilt with '{command}'"));
+    } else {
+        report.line("The initramfs doesn't contain the changed files, it is left as it is");
+    }
+    Ok(())
+}
+
+pub(crate) fn run(distro: &distro::Distro) -> ActionResult {
+    let start = Instant::now();
+    let mut report = Report { output: String::new() };
+    let exit_code = match repair(distro, &mut report) {
+        Ok(()) => 0,
+        Err(e) => {
+            error!("[{}] {e:#}", constants::LVMDEVICES);
+            report.output.push_str(&format!("{e:#}\n"));
+            1
+        }
+    };
+    ActionResult {
+        name: constants::LVMDEVICES.to_string(),
+        exit_code: Some(exit_code),
+        signal: None,
+        duration: start.elapsed(),
+        output: report.output,
+        timed_out: false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(line: &str) -> DeviceEntry {
+        DeviceEntry::parse(line).unwrap()
+    }
+
+    #[test]
+    fn devices_file_of_a_moved_disk() {
+        let content = "\
+# LVM uses devices listed in this file.
+VERSION=1.1.2
+IDTYPE=sys_wwid IDNAME=naa.600224800001 DEVNAME=/dev/sda4 PVID=aaaa PART=4
+IDTYPE=sys_wwid IDNAME=naa.6002248000ff DEVNAME=/dev/sdb1 PVID=dddd
+";
+        let expected = [
+            entry("IDTYPE=sys_wwid IDNAME=naa.600224800002 DEVNAME=/dev/sdc4 PVID=aaaa PART=4"),
+            entry("IDTYPE=devname IDNAME=/dev/sdd1 DEVNAME=/dev/sdd1 PVID=bbbb").with_device_name("/dev/sdb2"),
+        ];
+        let check = check_devices_file(content, &expected);
+        assert_eq!(check.mismatches.len(), 2);
+        assert_eq!(
+            check.content,
+            "\
+# LVM uses devices listed in this file.
+VERSION=1.1.2
+IDTYPE=sys_wwid IDNAME=naa.600224800002 DEVNAME=/dev/sda4 PVID=aaaa PART=4
+IDTYPE=sys_wwid IDNAME=naa.6002248000ff DEVNAME=/dev/sdb1 PVID=dddd
+IDTYPE=devname IDNAME=/dev/sdb2 DEVNAME=/dev/sdb2 PVID=bbbb
+"
+        );
+
+        // Nothing to do once the file got corrected
+        assert!(check_devices_file(&check.content, &expected).mismatches.is_empty());
+    }
+
+    #[test]
+    fn filter_of_the_recovered_system() {
+        let patterns = parse_filter(r#"filter=["a|^/dev/sda[0-9]*$|","r|.*|"]"#);
+        assert_eq!(patterns, vec!["a|^/dev/sda[0-9]*$|", "r|.*|"]);
+
+        let names = |names: &[&str]| names.iter().map(|name| name.to_string()).collect::<Vec<String>>();
+        assert!(is_accepted(&patterns, &names(&["/dev/disk/by-id/lvm-pv-uuid-x", "/dev/sda4"])));
+        assert!(!is_accepted(&patterns, &names(&["/dev/disk/by-id/lvm-pv-uuid-x", "/dev/nvme0n1p4"])));
+        assert!(is_accepted(&[], &names(&["/dev/sdc4"])));
+
+        let conf = "devices {\n\t# filter = [ \"a|.*|\" ]\n\tfilter = [ \"a|^/dev/sda[0-9]*$|\", \"r|.*|\" ]\n}\n";
+        let rewritten = prepend_to_setting(conf, "filter", &[accept_pattern("x")]).unwrap();
+        assert_eq!(
+            rewritten,
+            "devices {\n\t# filter = [ \"a|.*|\" ]\n\tfilter = [ \"a|^/dev/disk/by-id/lvm-pv-uuid-x$|\", \"a|^/dev/sda[0-9]*$|\", \"r|.*|\" ]\n}\n"
+        );
+        assert!(is_accepted(
+            &parse_filter(&format!("filter={}", &rewritten[rewritten.rfind('[').unwrap()..])),
+            &names(&["/dev/disk/by-id/lvm-pv-uuid-x", "/dev/nvme0n1p4"])
+        ));
+        assert_eq!(prepend_to_setting(conf, "global_filter", &[accept_pattern("x")]), None);
+    }
+
+    #[test]
+    fn device_names_of_the_metadata_backup() {
+        let content = "physical_volumes {\n\n\t\tpv0 {\n\t\t\tid = \"RkMXaZ-1\"\n\t\t\tdevice = \"/dev/sda4\"\t# Hint only\n";
+        assert_eq!(
+            metadata_device_hints(content),
+            vec![("RkMXaZ-1".to_string(), "/dev/sda4".to_string())]
+        );
+    }
+}
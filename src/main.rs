@@ -1,24 +1,54 @@
 mod action;
 mod ade;
+mod blockdev;
+mod btrfs;
 mod cli;
+mod config;
 mod constants;
+mod disk_select;
 mod distro;
+mod fstab;
+mod gpt;
 mod helper;
+mod inspect;
+mod lvm;
+mod lvm_metadata;
+mod lvmdevices;
+mod mbr;
 mod mount;
+mod plan;
 mod prepare_chroot;
+mod probe;
+mod runlog;
+mod signals;
+mod teardown;
 mod telemetry;
-mod nvme;
-use anyhow::Result;
+use anyhow::{Context, Result, anyhow};
 use env_logger::Env;
-use log::{debug, error, info, log_enabled, Level};
+use log::{debug, error, info, log_enabled, warn, Level};
+use std::time::Instant;
 use std::{env, process};
 
 fn main() -> Result<()> {
+    // The run timeout covers the whole run of ALAR, not only the actions
+    let run_start = Instant::now();
+
     //Initialize the logger
-    env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
+    // Every log record is written to stderr and to the log file of this run
+    env_logger::Builder::from_env(Env::default().default_filter_or("info"))
+        .target(env_logger::Target::Pipe(Box::new(runlog::LogTee)))
+        .init();
 
     // First verify we have the right amount of information to operate
     let mut cli_info = cli::cli()?;
+    telemetry::set_mode(cli_info.telemetry);
+    info!("{}", telemetry::notice(cli_info.telemetry));
+
+    // Listing the actions doesn't touch any disk, thus it is done before anything else
+    if cli_info.mode == cli::Mode::ListActions {
+        action::print_action_list(&action::list_actions(&cli_info)?);
+        return Ok(());
+    }
 
     // are we root?
     if !helper::is_root_user()? {
@@ -26,96 +56,214 @@ fn main() -> Result<()> {
         process::exit(1);
     }
 
+    // From here on the disk may get changed. An interruption has to clean up the same way an error does.
+    signals::install().context("The signal handlers could not be installed")?;
+    if let Some(run_deadline) = cli_info.run_timeout.and_then(|timeout| run_start.checked_add(timeout)) {
+        signals::start_watchdog(run_deadline);
+    }
+
+    // The records logged before, like the telemetry notice, are kept until the log file is open
+    match runlog::init(&cli_info.log_directory) {
+        Ok(path) => info!("The log of this run is written to {}", path.display()),
+        Err(e) => warn!("No log file is written for this run: {e:#}"),
+    }
+
     if log_enabled!(Level::Debug) {
         let arguments: Vec<_> = env::args().collect();
         debug!("Arguments passed to ALAR: ");
         arguments.iter().for_each(|arg| debug!("{arg}"));
     }
 
+    // Every error ends up here. Whatever got set up until then is undone in one place.
+    let result = run(&mut cli_info, run_start);
+    signals::finish_run();
+    teardown::run();
+
+    // The signal handler and the watchdog only stop the running processes, the run ends with their exit code
+    if let Some(exit_code) = signals::interruption_exit_code() {
+        process::exit(exit_code);
+    }
+
+    match result {
+        Ok(action::ActionStatus::Success) => Ok(()),
+        Ok(worst_status) => {
+            error!("Not all recovery actions succeeded.");
+            process::exit(worst_status.exit_code());
+        }
+        Err(e) => {
+            error!("{e:#}");
+            error!("ALAR is not able to proceed. Exiting.");
+            process::exit(1);
+        }
+    }
+}
+
+// Runs the recovery and returns the worst action result. Nothing in here cleans up, this is done by the teardown.
+fn run(cli_info: &mut cli::CliInfo, run_start: Instant) -> Result<action::ActionStatus> {
+    cli_info.recovery_disk = disk_select::select(cli_info)
+        .inspect_err(|e| {
+            let _ = telemetry::send_envelope(&telemetry::create_exception_envelope(
+                telemetry::SeverityLevel::Error,
+                "ALAR EXCEPTION",
+                "Error selecting the recovery disk.",
+                &format!("disk_select::select() returned error: {e:#}"),
+                cli_info,
+                &distro::Distro::default(),
+            ))
+            .inspect_err(|e| error!("Failed to send telemetry: {}", e));
+        })
+        .context("Error getting recover disk info")?;
+    info!("Recovery disk: {}", cli_info.recovery_disk);
+    cli_info.additional_recovery_disks = disk_select::select_additional(cli_info)?;
+
+    // Damaged LVM metadata lets the detection below fail, thus the metadata gets restored before
+    if let cli::Mode::LvmMetadata { candidate, is_confirmed } = cli_info.mode {
+        lvm_metadata::recover(cli_info, candidate, is_confirmed)?;
+        return Ok(action::ActionStatus::Success);
+    }
+
     // Create a new distro object
     // The distro object will be used to determine the distro of the VM we are trying to recover
-    let distro = distro::Distro::new(&mut cli_info);
+    let distro = distro::Distro::new(cli_info)?;
     info!("Distro details collected : {:#?}", distro);
+    let cli_info = &*cli_info;
 
-    // After we have collected all the required information we can start the actuall recover process.
-    // If we have finished the recovery process it is important to rename the VG 'oldvg' back to 'rootvg'.
-    // Otherwise the recovery VM might not boot up correctly
+    // The inspection only reports what got detected. Like the dry-run it doesn't change anything.
+    if let cli::Mode::Inspect(format) = cli_info.mode {
+        let recovery_disk = helper::get_recovery_disk_path(cli_info)?;
+        let report = inspect::InspectReport::new(&distro, &recovery_disk);
+        println!("{}", report.render(format)?);
+        return Ok(action::ActionStatus::Success);
+    }
 
+    // In dry-run mode we stop here. Nothing got changed on the disk, only the read-only mounts are undone.
+    if cli_info.dry_run {
+        println!("{}", plan::Plan::build(&distro, cli_info)?);
+        return Ok(action::ActionStatus::Success);
+    }
 
     // download_action_scripts_or will download the action scripts from GIT if explicitly requested or utilize a custom script if available,
     // otherwise the builtin ones will be used.
-    match helper::download_action_scripts_or(&cli_info) {
-        Ok(_) => {}
-        Err(e) => {
-            error!("An issue with the action scripts happend: {}", e);
-            helper::cleanup(&distro, &cli_info)?;
-            process::exit(1);
-        }
-    }
+    helper::download_action_scripts_or(cli_info)
+        .context("An issue with the action scripts happend")?;
 
     // Let us verify whether the action to be executed is available
     for action in cli_info.actions.split(',') {
         if !action::is_action_available(action)? {
-            error!("The action {action} is not available. Exiting.");
-            helper::cleanup(&distro, &cli_info)?;
-            telemetry::send_envelope(&telemetry::create_exception_envelope(
+            let _ = telemetry::send_envelope(&telemetry::create_exception_envelope(
                 telemetry::SeverityLevel::Warning,
                 "ActionNotFound",
                 &format!("The action {action} is not available"),
                 "",
-                &cli_info,
+                cli_info,
                 &distro,
-            ))?;
-            process::exit(1);
+            ))
+            .inspect_err(|e| error!("Failed to send telemetry: {}", e));
+            return Err(anyhow!("The action {action} is not available"));
         }
     }
 
+    // Leave a record of this run on the recovered disk, also if the run fails. It is copied before the disk gets unmounted.
+    teardown::register(teardown::Step::CopyRunLog(constants::RESCUE_ROOT.to_string()));
+
     // Prepare and setup the environment for the recovery process
-    if prepare_chroot::prepare_chroot(&distro, &cli_info).is_err() {
-        error!("Failed to prepare the chroot environment. Exiting.");
-        mount::umount(constants::RESCUE_ROOT, true)?;
-        helper::cleanup(&distro, &cli_info)?;
-        process::exit(1);
-    }
+    prepare_chroot::prepare_chroot(&distro, cli_info)
+        .context("Failed to prepare the chroot environment")?;
 
     // Run the repair scripts
+    let mut action_results: Vec<action::ActionResult> = Vec::new();
     if cli_info.actions.contains(constants::CHROOT_CLI) {
         match action::is_tmux_installed() {
             Ok(true) => {
                 action::execute_chroot_cli()?;
             }
             Ok(false) => {
-                error!("tmux is not installed. Please install it manually. tmux is required if action 'chroot-cli' is selected");
-                mount::umount(constants::RESCUE_ROOT, true)?;
-                helper::cleanup(&distro, &cli_info)?;
-                process::exit(1);
+                return Err(anyhow!("tmux is not installed. Please install it manually. tmux is required if action 'chroot-cli' is selected"));
             }
             Err(e) => {
-                error!("A tmux or action script error happened: {}", e);
-                mount::umount(constants::RESCUE_ROOT, true)?;
-                helper::cleanup(&distro, &cli_info)?;
-                process::exit(1);
+                return Err(e.context("A tmux or action script error happened"));
             }
         }
     } else {
+        let run_deadline = cli_info.run_timeout.and_then(|timeout| run_start.checked_add(timeout));
         for action_name in cli_info.actions.split(',') {
+            if signals::is_interrupted() {
+                return Err(anyhow!("ALAR got interrupted. The action {} and any following are not started.", action_name.trim()));
+            }
+            if run_deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                error!("The run timeout expired. The action {} and any following are not started.", action_name.trim());
+                let mut result = action::ActionResult::not_started(
+                    action_name.trim(),
+                    &anyhow::anyhow!("the run timeout expired"),
+                );
+                result.timed_out = true;
+                action_results.push(result);
+                break;
+            }
+
+            let deadline = action::deadline(Instant::now(), cli_info.action_timeout, run_deadline);
+
             debug!("Running action script: {}", action_name.trim());
-            action::run_repair_script(action_name.trim())?;
+            let result = if action_name.trim() == constants::LVMDEVICES {
+                lvmdevices::run(&distro)
+            } else {
+                match action::run_repair_script(action_name.trim(), deadline) {
+                    Ok(result) => result,
+                    Err(e) => action::ActionResult::not_started(action_name.trim(), &e),
+                }
+            };
+            let is_failed = result.status() != action::ActionStatus::Success;
+            action_results.push(result);
+            if is_failed && cli_info.on_action_failure == cli::ActionFailurePolicy::Stop {
+                error!("The action {} did not succeed. The remaining actions are skipped.", action_name.trim());
+                break;
+            }
         }
     }
 
-    // Finally send telemetry information
-    let trace_message = telemetry::create_trace_envelope(
-        telemetry::SeverityLevel::Information,
-        "Recovery action(s) completed",
-        &cli_info,
-        &distro,
-    );
-    telemetry::send_envelope(&trace_message)?;
+    info!("Action results:");
+    for result in &action_results {
+        if result.status() == action::ActionStatus::Success {
+            info!("{result}");
+        } else {
+            error!("{result}");
+            result
+                .output_tail(10)
+                .iter()
+                .for_each(|line| error!("  {} | {line}", result.name));
+        }
+    }
+    let worst_status = action_results
+        .iter()
+        .map(|result| result.status())
+        .max()
+        .unwrap_or(action::ActionStatus::Success);
 
-    // Umount and cleanup the resources
-    mount::umount(constants::RESCUE_ROOT, true)?;
-    helper::cleanup(&distro, &cli_info)?;
+    // Finally send telemetry information. A failure to send it doesn't change the result of the run.
+    if worst_status == action::ActionStatus::Success {
+        let trace_message = telemetry::create_trace_envelope(
+            telemetry::SeverityLevel::Information,
+            "Recovery action(s) completed",
+            cli_info,
+            &distro,
+        );
+        let _ = telemetry::send_envelope(&trace_message).inspect_err(|e| error!("Failed to send telemetry: {}", e));
+    } else {
+        for result in action_results
+            .iter()
+            .filter(|result| result.status() != action::ActionStatus::Success)
+        {
+            let _ = telemetry::send_envelope(&telemetry::create_exception_envelope(
+                telemetry::SeverityLevel::Error,
+                "ActionFailed",
+                &result.to_string(),
+                "main() -> action::run_repair_script() did not succeed",
+                cli_info,
+                &distro,
+            ))
+            .inspect_err(|e| error!("Failed to send telemetry: {}", e));
+        }
+    }
 
-    Ok(())
+    Ok(worst_status)
 }
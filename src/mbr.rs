@@ -0,0 +1,201 @@
+use anyhow::{Context, Result, anyhow};
+use log::debug;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+
+// Reads a msdos partition table: the four primary entries and the chain of logical partitions in an extended one.
+// The partitions are numbered like the kernel does, the logical ones start at 5.
+
+// The MBR addresses the disk in units of 512 byte sectors on all disks ALAR deals with
+const SECTOR_SIZE: u64 = 512;
+const BOOT_SIGNATURE: [u8; 2] = [0x55, 0xAA];
+const PROTECTIVE_MBR_TYPE: u8 = 0xEE;
+const EXTENDED_TYPES: [u8; 3] = [0x05, 0x0F, 0x85];
+const FIRST_LOGICAL_NUMBER: u32 = 5;
+// Guards against a loop in a damaged chain of extended boot records
+const MAX_LOGICAL_PARTITIONS: usize = 256;
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct MbrPartition {
+    pub(crate) number: u32,
+    pub(crate) type_id: u8,
+    pub(crate) is_bootable: bool,
+    pub(crate) first_lba: u64,
+    pub(crate) sectors: u64,
+}
+
+impl MbrPartition {
+    // The GPT short code of the same type, as sgdisk converts it: 0x83 is 8300, 0x8e is 8E00, 0xef is EF00
+    // and 0x82 is 8200. This way a partition is classified the same way on both partition tables.
+    pub(crate) fn type_code(&self) -> String {
+        format!("{:02X}00", self.type_id)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct MbrTable {
+    // Used by the kernel as the first part of the PARTUUID
+    pub(crate) disk_signature: u32,
+    pub(crate) partitions: Vec<MbrPartition>,
+}
+
+#[derive(Debug)]
+struct Entry {
+    type_id: u8,
+    is_bootable: bool,
+    start: u64,
+    sectors: u64,
+}
+
+fn read_sector<R: Read + Seek>(reader: &mut R, lba: u64) -> Result<[u8; SECTOR_SIZE as usize]> {
+    let mut sector = [0u8; SECTOR_SIZE as usize];
+    reader.seek(SeekFrom::Start(lba * SECTOR_SIZE))?;
+    reader.read_exact(&mut sector)?;
+    Ok(sector)
+}
+
+fn u32_at(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+// Returns the four entries of a MBR or an extended boot record. Empty entries are None.
+fn parse_entries(sector: &[u8], lba: u64) -> Result<[Option<Entry>; 4]> {
+    if sector[510..512] != BOOT_SIGNATURE {
+        return Err(anyhow!("no boot signature in the sector at LBA {lba}"));
+    }
+    Ok(std::array::from_fn(|index| {
+        let entry = &sector[446 + index * 16..446 + (index + 1) * 16];
+        let type_id = entry[4];
+        let sectors = u32_at(entry, 12) as u64;
+        (type_id != 0 && sectors != 0).then_some(Entry {
+            type_id,
+            is_bootable: entry[0] == 0x80,
+            start: u32_at(entry, 8) as u64,
+            sectors,
+        })
+    }))
+}
+
+// Each extended boot record holds one logical partition, relative to itself, and the link to the next record,
+// relative to the start of the extended partition
+fn read_logical_partitions<R: Read + Seek>(reader: &mut R, extended_start: u64) -> Result<Vec<MbrPartition>> {
+    let mut partitions = Vec::new();
+    let mut visited = HashSet::new();
+    let mut ebr_lba = extended_start;
+
+    while partitions.len() < MAX_LOGICAL_PARTITIONS && visited.insert(ebr_lba) {
+        let sector = read_sector(reader, ebr_lba)
+            .with_context(|| format!("The extended boot record at LBA {ebr_lba} can not be read"))?;
+        let [logical, next, ..] = parse_entries(&sector, ebr_lba)?;
+
+        if let Some(logical) = logical {
+            partitions.push(MbrPartition {
+                number: FIRST_LOGICAL_NUMBER + partitions.len() as u32,
+                type_id: logical.type_id,
+                is_bootable: logical.is_bootable,
+                first_lba: ebr_lba + logical.start,
+                sectors: logical.sectors,
+            });
+        }
+        match next {
+            Some(next) if EXTENDED_TYPES.contains(&next.type_id) => ebr_lba = extended_start + next.start,
+            _ => break,
+        }
+    }
+    Ok(partitions)
+}
+
+pub(crate) fn read<R: Read + Seek>(reader: &mut R) -> Result<MbrTable> {
+    let mbr = read_sector(reader, 0).context("The first sector can not be read")?;
+    let entries = parse_entries(&mbr, 0).context("No msdos partition table found")?;
+    if entries.iter().flatten().any(|entry| entry.type_id == PROTECTIVE_MBR_TYPE) {
+        return Err(anyhow!("The MBR is a protective one, the disk is partitioned with GPT"));
+    }
+
+    let mut partitions = Vec::new();
+    for (index, entry) in entries.iter().enumerate() {
+        let Some(entry) = entry else { continue };
+        // The extended partition is only a container, it doesn't show up in the list
+        if EXTENDED_TYPES.contains(&entry.type_id) {
+            partitions.extend(read_logical_partitions(reader, entry.start)?);
+        } else {
+            partitions.push(MbrPartition {
+                number: index as u32 + 1,
+                type_id: entry.type_id,
+                is_bootable: entry.is_bootable,
+                first_lba: entry.start,
+                sectors: entry.sectors,
+            });
+        }
+    }
+    partitions.sort_by_key(|partition| partition.number);
+    debug!("MBR partition entries: {partitions:#?}");
+
+    Ok(MbrTable {
+        disk_signature: u32_at(&mbr, 440),
+        partitions,
+    })
+}
+
+pub(crate) fn read_from_path(path: &str) -> Result<MbrTable> {
+    let mut file = File::open(path).with_context(|| format!("{path} can not be opened"))?;
+    read(&mut file).with_context(|| format!("The msdos partition table of {path} can not be read"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn write_entry(disk: &mut [u8], sector_lba: u64, index: usize, type_id: u8, start: u32, sectors: u32) {
+        let offset = sector_lba as usize * 512 + 446 + index * 16;
+        disk[offset + 4] = type_id;
+        disk[offset + 8..offset + 12].copy_from_slice(&start.to_le_bytes());
+        disk[offset + 12..offset + 16].copy_from_slice(&sectors.to_le_bytes());
+        let signature = sector_lba as usize * 512 + 510;
+        disk[signature..signature + 2].copy_from_slice(&BOOT_SIGNATURE);
+    }
+
+    // A CentOS 7 like layout: /boot, root, and an extended partition holding swap and a LVM PV
+    #[test]
+    fn read_mbr_with_logical_partitions() {
+        let mut disk = vec![0u8; 4096 * 512];
+        disk[440..444].copy_from_slice(&0x1234abcdu32.to_le_bytes());
+        write_entry(&mut disk, 0, 0, 0x83, 2048, 1024);
+        disk[446] = 0x80;
+        write_entry(&mut disk, 0, 1, 0x83, 3072, 512);
+        write_entry(&mut disk, 0, 2, 0x05, 3584, 512);
+        // First EBR: swap, link to the second EBR
+        write_entry(&mut disk, 3584, 0, 0x82, 1, 127);
+        write_entry(&mut disk, 3584, 1, 0x05, 128, 384);
+        // Second EBR: LVM, end of chain
+        write_entry(&mut disk, 3712, 0, 0x8e, 1, 383);
+
+        let table = read(&mut Cursor::new(disk)).unwrap();
+        assert_eq!(table.disk_signature, 0x1234abcd);
+        let summary: Vec<(u32, String, u64, u64)> = table
+            .partitions
+            .iter()
+            .map(|partition| (partition.number, partition.type_code(), partition.first_lba, partition.sectors))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                (1, "8300".to_string(), 2048, 1024),
+                (2, "8300".to_string(), 3072, 512),
+                (5, "8200".to_string(), 3585, 127),
+                (6, "8E00".to_string(), 3713, 383),
+            ]
+        );
+        assert!(table.partitions[0].is_bootable);
+    }
+
+    #[test]
+    fn read_mbr_rejects_gpt_and_blank_disks() {
+        let mut disk = vec![0u8; 64 * 512];
+        assert!(read(&mut Cursor::new(disk.clone())).is_err());
+        write_entry(&mut disk, 0, 0, PROTECTIVE_MBR_TYPE, 1, 63);
+        assert!(read(&mut Cursor::new(disk)).is_err());
+    }
+}
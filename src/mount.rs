@@ -1,16 +1,20 @@
-use crate::cli::CliInfo;
+use crate::blockdev;
+use crate::cli::{CliInfo, FsckPolicy};
 use crate::constants;
 use crate::distro;
 use crate::helper;
 use crate::helper::is_nvme_controller_present;
+use crate::lvm;
+use crate::probe;
 use crate::telemetry;
-use anyhow::Result;
+use crate::teardown::{self, Step};
+use anyhow::{Context, Result, anyhow};
 use log::debug;
 use log::error;
 use log::info;
+use log::warn;
 use log::log_enabled;
 use std::collections::HashMap;
-use std::path::Path;
 use std::process::Stdio;
 use std::{fs, process};
 
@@ -18,6 +22,7 @@ pub(crate) fn mkdir_assert() -> Result<()> {
     fs::create_dir_all(constants::ASSERT_PATH).inspect_err(|open_error| {
         error!("Error while creating the assert directory: {open_error}");
     })?;
+    teardown::register(Step::RemoveDir(constants::ASSERT_PATH.to_string()));
     Ok(())
 }
 
@@ -28,7 +33,43 @@ pub(crate) fn mkdir_rescue_root() -> Result<()> {
     Ok(())
 }
 
+// Every mount is registered for the teardown. It gets forgotten again if umount() is called for it.
 pub(crate) fn mount(source: &str, destination: &str, option: &str, is_relaxed: bool) -> Result<()> {
+    mount_with_flags(source, destination, option, is_relaxed, sys_mount::MountFlags::empty())?;
+    teardown::register(Step::Umount(destination.to_string()));
+    Ok(())
+}
+
+// An empty tmpfs, i.e. for a /dev of the chroot environment which differs from the one of the repair VM
+pub(crate) fn mount_tmpfs(destination: &str) -> Result<()> {
+    sys_mount::Mount::builder()
+        .fstype("tmpfs")
+        .data("mode=0755")
+        .mount("tmpfs", destination)
+        .with_context(|| format!("Failed to mount a tmpfs on {destination}"))?;
+    teardown::register(Step::Umount(destination.to_string()));
+    Ok(())
+}
+
+// Used by the dry-run mode and the disk selection. The filesystem is mounted read-only so that the disk to be recovered isn't altered.
+pub(crate) fn mount_readonly(source: &str, destination: &str, option: &str, is_relaxed: bool) -> Result<()> {
+    mount_with_flags(source, destination, option, is_relaxed, sys_mount::MountFlags::RDONLY)?;
+    teardown::register(Step::Umount(destination.to_string()));
+    Ok(())
+}
+
+// Restores a mount of the repair VM itself, i.e. /boot. Those mounts must stay in place after ALAR has finished.
+fn remount_repair_vm(source: &str, destination: &str) -> Result<()> {
+    mount_with_flags(source, destination, "", false, sys_mount::MountFlags::empty())
+}
+
+fn mount_with_flags(
+    source: &str,
+    destination: &str,
+    option: &str,
+    is_relaxed: bool,
+    flags: sys_mount::MountFlags,
+) -> Result<()> {
     // There is an issue on Ubuntu that the XFS filesystem is not enabled by default
     // We need to load the driver first
     process::Command::new("modprobe").arg("xfs").status().inspect_err(|open_error| {
@@ -46,7 +87,6 @@ pub(crate) fn mount(source: &str, destination: &str, option: &str, is_relaxed: b
         Ok(supported) => supported,
         Err(open_error) => {
             error!("Failed to get supported file systems: Detail {open_error}");
-            error!("This is a severe issue for ALAR. Aborting.");
             let _ = telemetry::send_envelope(&telemetry::create_exception_envelope(
                 telemetry::SeverityLevel::Error,
                 "ALAR EXCEPTION",
@@ -55,19 +95,18 @@ pub(crate) fn mount(source: &str, destination: &str, option: &str, is_relaxed: b
                 &CliInfo::default(),
                 &distro::Distro::default(),
             ));
-            process::exit(1);
+            return Err(anyhow!("Failed to get supported file systems: {open_error}"));
         }
     };
 
     sys_mount::Mount::builder()
         .fstype(&supported)
-        .flags(sys_mount::MountFlags::empty())
+        .flags(flags)
         .data(option)
         .mount(source, destination)
         .inspect_err(|mount_error| {
             error!("Failed to mount {source} on {destination}: {mount_error}");
             if !is_relaxed {
-                error!("This is a severe issue for ALAR. Aborting.");
                 let _ = telemetry::send_envelope(&telemetry::create_exception_envelope(
                     telemetry::SeverityLevel::Error,
                     "ALAR EXCEPTION",
@@ -76,16 +115,16 @@ pub(crate) fn mount(source: &str, destination: &str, option: &str, is_relaxed: b
                     &CliInfo::default(),
                     &distro::Distro::default(),
                 ));
-                process::exit(1);
             }
-        })?;
+        })
+        .with_context(|| format!("Failed to mount {source} on {destination}"))?;
 
     Ok(())
 }
 
 pub(crate) fn umount(destination: &str, recursive: bool) -> Result<()> {
     if recursive {
-        process::Command::new("umount")
+        let status = process::Command::new("umount")
             .arg("-R")
             .arg(destination)
             .status()
@@ -93,6 +132,10 @@ pub(crate) fn umount(destination: &str, recursive: bool) -> Result<()> {
                 error!("Failed to unmount {destination}: {umount_error}");
                 error!("This could cause severe issues.");
             })?;
+        // If it failed the teardown tries it once more
+        if status.success() {
+            teardown::forget_mount(destination, true);
+        }
         Ok(())
     } else {
         sys_mount::unmount(destination, sys_mount::UnmountFlags::DETACH).inspect_err(
@@ -101,19 +144,27 @@ pub(crate) fn umount(destination: &str, recursive: bool) -> Result<()> {
                 error!("This shouldn't cause a severe issue for ALAR.");
             },
         )?;
+        teardown::forget_mount(destination, false);
         Ok(())
     }
 }
 
-pub(crate) fn fsck_partition(partition_path: &str) -> Result<()> {
+pub(crate) fn fsck_partition(partition_path: &str, policy: FsckPolicy) -> Result<()> {
+    match policy {
+        FsckPolicy::Skip => {
+            info!("fsck for {partition_path} is skipped as requested by the fsck policy");
+            return Ok(());
+        }
+        FsckPolicy::Check => return fsck_check_only(partition_path),
+        FsckPolicy::Auto => {}
+    }
+
     let mut exit_code = Some(0i32);
-    let partition_filesystem =
-        if let Ok(pfs) = distro::Distro::get_partition_filesystem(partition_path) {
-            pfs
-        } else {
-            error!("Failed to get the partition filesystem. ALAR is not able to proceed further!");
-            process::exit(1);
-        };
+    let Some(partition_filesystem) =
+        checkable_filesystem(partition_path).context("Failed to get the partition filesystem")?
+    else {
+        return Ok(());
+    };
 
     match partition_filesystem.as_str() {
         "xfs" => {
@@ -136,9 +187,7 @@ pub(crate) fn fsck_partition(partition_path: &str) -> Result<()> {
                         exit_code = stat.code();
                         debug!("Inside fsck for XFS first mount : xfs_repair returned with exit code: {:?}", exit_code);
                     }
-                    error!("Stopping ALAR. Please do a manual recover of the FS for disk {partition_path}. Error code of xfs_repair: {:?}", exit_code);
-                    umount(constants::ASSERT_PATH, false)?;
-                    process::exit(1);
+                    return Err(anyhow!("Please do a manual recover of the FS for disk {partition_path}. Error code of xfs_repair: {:?}", exit_code));
                 }
             }
             umount(constants::ASSERT_PATH, false)?;
@@ -201,18 +250,14 @@ pub(crate) fn fsck_partition(partition_path: &str) -> Result<()> {
     match exit_code {
         // error 4 is returned by fsck.ext4 only
         Some(_code @ 4) => {
-            error!(
+            return Err(anyhow!(
                 "Partition {} can not be repaired in auto mode",
                 &partition_path
-            );
-            error!("Stopping ALAR");
-            process::exit(1);
+            ));
         }
         // xfs_repair -n returns 1 if the fs is corrupted.
         // Also fsck may raise this error but we ignore it as even a normal recover is raising it. FALSE-NEGATIVE
         Some(_code @ 1) if partition_filesystem == "xfs" => {
-            error!("A general error occured while trying to recover the device {partition_path}.");
-            error!("Stopping ALAR");
             let _ = telemetry::send_envelope(&telemetry::create_exception_envelope(
                 telemetry::SeverityLevel::Error,
                 "ALAR EXCEPTION",
@@ -223,7 +268,9 @@ pub(crate) fn fsck_partition(partition_path: &str) -> Result<()> {
                 &CliInfo::default(),
                 &distro::Distro::default(),
             ));
-            process::exit(1);
+            return Err(anyhow!(
+                "A general error occured while trying to recover the device {partition_path}."
+            ));
         }
         None => {
             let _ = telemetry::send_envelope(&telemetry::create_exception_envelope(
@@ -234,8 +281,7 @@ pub(crate) fn fsck_partition(partition_path: &str) -> Result<()> {
                 &CliInfo::default(),
                 &distro::Distro::default(),
             ));
-            eprintln!( "fsck operation terminated by signal error. ALAR is not able to proceed further!");
-            process::exit(1);
+            return Err(anyhow!("fsck operation terminated by signal error"));
         }
 
         // Any other error state is not of interest for us
@@ -247,12 +293,82 @@ pub(crate) fn fsck_partition(partition_path: &str) -> Result<()> {
     Ok(())
 }
 
+// Only a filesystem gets checked. Swap, LVM PVs, LUKS containers and unknown contents are left alone.
+fn checkable_filesystem(partition_path: &str) -> Result<Option<String>> {
+    match probe::probe_path(partition_path)? {
+        Some(probe) if probe.fstype.is_filesystem() => Ok(Some(probe.fstype.to_string())),
+        Some(probe) => {
+            info!("{partition_path} contains {}, there is no filesystem to check", probe.fstype);
+            Ok(None)
+        }
+        None => {
+            info!("No known filesystem found on {partition_path}, thus it is not checked");
+            Ok(None)
+        }
+    }
+}
+
+// The command and its arguments to check a filesystem without modifying it
+fn fsck_check_only_command(filesystem: &str, partition_path: &str) -> (String, Vec<String>) {
+    match filesystem {
+        "xfs" => ("xfs_repair".to_string(), vec!["-n".to_string(), partition_path.to_string()]),
+        "fat16" | "vfat" => ("fsck.vfat".to_string(), vec!["-n".to_string(), partition_path.to_string()]),
+        _ => (format!("fsck.{filesystem}"), vec!["-n".to_string(), partition_path.to_string()]),
+    }
+}
+
+// Only reports the state of the filesystem. Nothing gets repaired, thus a damaged filesystem doesn't stop ALAR
+fn fsck_check_only(partition_path: &str) -> Result<()> {
+    let Some(partition_filesystem) = checkable_filesystem(partition_path)? else {
+        return Ok(());
+    };
+    let (command, args) = fsck_check_only_command(&partition_filesystem, partition_path);
+    info!("Checking {partition_filesystem} on {partition_path} without repairing it");
+
+    let output = process::Command::new(&command).args(&args).output()?;
+    match output.status.code() {
+        Some(0) => info!("{command} found no problems on {partition_path}"),
+        code => {
+            warn!("{command} reported problems on {partition_path}, exit code: {code:?}. Nothing got repaired because of the fsck policy 'check'");
+            String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .chain(String::from_utf8_lossy(&output.stderr).lines())
+                .for_each(|line| warn!("  {line}"));
+        }
+    }
+    Ok(())
+}
+
+// Describes the command fsck_partition() runs for a given filesystem. Used to print the dry-run plan.
+pub(crate) fn fsck_command(filesystem: &str, partition_path: &str, policy: FsckPolicy) -> String {
+    match policy {
+        FsckPolicy::Skip => return format!("no check of {partition_path} (fsck policy 'skip')"),
+        FsckPolicy::Check => {
+            let (command, args) = fsck_check_only_command(filesystem, partition_path);
+            return format!("{command} {} (fsck policy 'check', nothing gets repaired)", args.join(" "));
+        }
+        FsckPolicy::Auto => {}
+    }
+    match filesystem {
+        "xfs" => format!(
+            "mount -o nouuid {partition_path} {} (log replay); umount; xfs_repair {partition_path}",
+            constants::ASSERT_PATH
+        ),
+        "fat16" | "vfat" => format!("fsck.vfat -p {partition_path}"),
+        _ => format!("fsck.{filesystem} -p {partition_path}"),
+    }
+}
+
 pub(crate) fn rmdir(path: &str) -> Result<()> {
     fs::remove_dir_all(path)?;
+    teardown::forget(&Step::RemoveDir(path.to_string()));
     Ok(())
 }
 
-pub(crate) fn importvg(cli_info: &crate::cli::CliInfo, partition_number: i32) -> Result<()> {
+// The VG is cloned with all of its PVs. The disks holding them are detached at the end, thus the VG of the repair VM
+// can get its name back.
+pub(crate) fn importvg(cli_info: &crate::cli::CliInfo, volume_group: &lvm::VolumeGroup) -> Result<()> {
+    let recovery_disk_path = helper::get_recovery_disk_path(cli_info)?;
     debug!("Inside importvg.");
     /*
        Save the old mounts
@@ -271,92 +387,132 @@ pub(crate) fn importvg(cli_info: &crate::cli::CliInfo, partition_number: i32) ->
         items.insert(key, value);
     });
 
-    helper::run_cmd("pvscan --cache")?;
-    let volume_groups = helper::run_fun("pvs --noheadings -o vg_name")?;
-
-    // If we have found the rescuevg to be available then we can skip the import
-    if volume_groups.contains("rescuevg") {
+    let import = &volume_group.import;
+    let Some(clone_name) = &import.clone_name else {
+        debug!("The VG {} doesn't collide with a VG of the repair VM. Skipping the import.", volume_group.name);
         return Ok(());
+    };
+    if volume_group.is_partial() {
+        return Err(anyhow!(
+            "The VG {} is partial and collides with a VG of the repair VM. vgimportclone requires all PVs of the VG",
+            volume_group.name
+        ));
     }
 
-    // Either this is a RedHat based distro where the rootvg is also in use
-    // or the rootvg became activated while adding the broken disk to the recover VM
-    // some extra stuff is to be performed
-    if Path::new("/dev/rootvg").is_dir() {
-        // Let us figure out whether there are more than two rootvg's
-        // If there are more than two rootvg's we need to do the import
-        let result_string = helper::run_fun(r"pvs -a --noheadings -o vg_name")?;
-        let mut rootvg_count = 0;
-
-        result_string.lines().for_each(|line| {
-            if line.contains("rootvg") {
-                rootvg_count += 1;
-            }
+    verify_renames_undoable(volume_group)?;
+
+    debug!("The VG {} collides with a VG of the repair VM. It gets imported as {clone_name}", volume_group.name);
+    helper::run_cmd(&format!(
+        "vgimportclone -n {clone_name} {}; vgscan --mknodes",
+        volume_group.pvs.join(" ")
+    ))?;
+
+    // The VGs of the repair VM are invisible with the private LVM configuration. The VG keeps the clone name
+    // while it is active and gets its original name back after it is deactivated.
+    if import.keeps_clone_name {
+        helper::run_cmd(&format!("vgchange -ay {clone_name}"))?;
+        teardown::register(Step::RestoreVgName {
+            from: clone_name.clone(),
+            to: volume_group.name.clone(),
         });
+        return Ok(());
+    }
 
-        debug!("Number of rootvg's found: {rootvg_count}");
-
-        if rootvg_count == 1 {
-            debug!("Only one rootvg found. Skipping the import.");
-            Ok(())
-        } else {
-            debug!("The rootvg is in use. We need to rename the rootvg to oldvg and the rescuevg to rootvg");
-
-            helper::run_cmd(&format!(
-                "vgimportclone -n rescuevg {}{}; vgscan --mknodes",
-                helper::get_recovery_disk_path(cli_info),
-                partition_number
-            ))?;
-
-            helper::run_cmd("vgrename rootvg oldvg; vgrename rescuevg rootvg; vgchange -ay")?;
+    rename_repair_vm_vgs(volume_group, &recovery_disk_path)?;
+    helper::run_cmd(&format!("vgrename {clone_name} {}; vgchange -ay {}", volume_group.name, volume_group.name))?;
 
-            if items.contains_key("/boot/efi") {
-                match umount("/boot/efi", false) {
-                    Ok(_) => {}
-                    Err(e) => {
-                        error!("Error umount /boot/efi : {e}");
-                    }
-                }
+    if items.contains_key("/boot/efi") {
+        match umount("/boot/efi", false) {
+            Ok(_) => {}
+            Err(e) => {
+                error!("Error umount /boot/efi : {e}");
             }
+        }
+    }
 
-            if items.contains_key("/boot") {
-                match umount("/boot", false) {
-                    Ok(_) => {}
-                    Err(e) => {
-                        error!("Error umount /boot : {e}");
-                    }
-                }
+    if items.contains_key("/boot") {
+        match umount("/boot", false) {
+            Ok(_) => {}
+            Err(e) => {
+                error!("Error umount /boot : {e}");
             }
+        }
+    }
 
-            if let Some(device_boot) = items.get("/boot") {
-                mount(&format!("/dev/{device_boot}"), "/boot", "", false)?;
-            }
+    if let Some(device_boot) = items.get("/boot") {
+        remount_repair_vm(&format!("/dev/{device_boot}"), "/boot")?;
+    }
 
-            if let Some(device_efi) = items.get("/boot/efi") {
-                mount(&format!("/dev/{device_efi}"), "/boot/efi", "", false)?;
-            }
+    if let Some(device_efi) = items.get("/boot/efi") {
+        remount_repair_vm(&format!("/dev/{device_efi}"), "/boot/efi")?;
+    }
 
-            Ok(())
-        }
-    } else {
-        // No import is necessary
-        Ok(())
+    Ok(())
+}
+
+// The original name can only be given back to a VG of the repair VM once the recovery disks are detached. A detach
+// isn't possible for NVMe disks, the repair VM would keep a renamed VG. Returns why the renames are refused.
+pub(crate) fn rename_refusal(volume_group: &lvm::VolumeGroup) -> Option<String> {
+    let rename = volume_group.import.repair_vm_renames.first()?;
+    if !is_nvme_controller_present().unwrap_or(false) {
+        return None;
     }
+    Some(format!(
+        "The VG {} collides with the VG {} of the repair VM, which would have to be renamed. Its name can't be given back, as the NVMe recovery disk can't be detached. Please use a repair VM whose VGs have other names.",
+        volume_group.name, rename.from
+    ))
 }
 
-pub(crate) fn rename_oldvg() {
-    // Only used for scsi disk. NVMe is currently not supported
-    // It is run at the end of the recovery process to rename oldvg to rootvg
-    // If it fails it is considered non fatal as the recovery process has finished anyway
+// Runs before vgimportclone, nothing is changed on the repair VM if the renames can't be undone
+pub(crate) fn verify_renames_undoable(volume_group: &lvm::VolumeGroup) -> Result<()> {
+    match rename_refusal(volume_group) {
+        Some(refusal) => Err(anyhow!(refusal)),
+        None => Ok(()),
+    }
+}
 
-    if let Ok(true) = is_nvme_controller_present() {
-        return;
+// Renames the VGs of the repair VM which collide with the VG. The teardown detaches the recovery disks, gives the
+// VGs their names back and adds the disks again, in this order.
+pub(crate) fn rename_repair_vm_vgs(volume_group: &lvm::VolumeGroup, recovery_disk_path: &str) -> Result<()> {
+    let renames = &volume_group.import.repair_vm_renames;
+    if renames.is_empty() {
+        return Ok(());
+    }
+    teardown::register(Step::RescanHost);
+    for rename in renames {
+        debug!("Renaming the VG {} of the repair VM to {}", rename.from, rename.to);
+        helper::run_cmd(&format!("vgrename {} {}", rename.uuid, rename.to))?;
+        teardown::register(Step::RenameVg {
+            from: rename.to.clone(),
+            to: rename.from.clone(),
+        });
     }
+    for disk in vg_disks(volume_group, recovery_disk_path) {
+        teardown::register(Step::DetachDisk(disk));
+    }
+    Ok(())
+}
 
-    debug!("Inside rename_oldvg");
+// The disks holding the PVs of the VG, the recovery disk comes first
+pub(crate) fn vg_disks(volume_group: &lvm::VolumeGroup, recovery_disk_path: &str) -> Vec<String> {
+    let mut disks = vec![recovery_disk_path.to_string()];
+    for pv in &volume_group.pvs {
+        for disk in blockdev::backing_disks(pv).unwrap_or_default() {
+            let disk = format!("/dev/{disk}");
+            if !disks.contains(&disk) {
+                disks.push(disk);
+            }
+        }
+    }
+    disks
+}
 
-    if helper::run_cmd("vgrename oldvg rootvg").is_err() {
-        error!("Failed to rename oldvg to rootvg. Can be ignored if the recovery VM is based on a non LVM disk or on a VG with a different name than rootvg.");
+// The name of the VG the PV belongs to
+pub(crate) fn vg_name_of(pv: &str) -> Result<String> {
+    let vg_name = helper::run_fun(&format!("pvs --noheadings -o vg_name {pv}"))?;
+    match vg_name.trim() {
+        "" => Err(anyhow!("{pv} doesn't belong to any VG")),
+        vg_name => Ok(vg_name.to_string()),
     }
 }
 
@@ -364,7 +520,6 @@ pub(crate) fn rescan_host() -> Result<()> {
     // Only used for scsi disk. NVMe is currently not supported
     // Rescan can't be run on a NVMe it is not possible to select a distinct disk
     // It is verified at the start of the recover process whether the recover VM is basedon LVM or not
-    //  If it is LVM based a LVM recover is no supported on a VG with the name rootvg, like we have on RedHat
     
     if is_nvme_controller_present()? {
         return Ok(());
@@ -420,12 +575,12 @@ pub(crate) fn rescan_host() -> Result<()> {
 
     if let Some(device_boot) = items.get("/boot") {
         println!("Device boot: {}", device_boot);
-        mount(&format!("/dev/{device_boot}"), "/boot", "", false)?;
+        remount_repair_vm(&format!("/dev/{device_boot}"), "/boot")?;
     }
 
     if let Some(device_efi) = items.get("/boot/efi") {
         println!("Device efi: {}", device_efi);
-        mount(&format!("/dev/{device_efi}"), "/boot/efi", "", false)?;
+        remount_repair_vm(&format!("/dev/{device_efi}"), "/boot/efi")?;
     }
 
     if log_enabled!(log::Level::Debug) {
@@ -436,15 +591,15 @@ pub(crate) fn rescan_host() -> Result<()> {
 }
 
 // This function does support only scsi backed devices
-pub(crate) fn disable_broken_disk(cli_info: &CliInfo) -> Result<()> {
+// The VG of the disk is deactivated already by the teardown before the disk gets detached
+pub(crate) fn disable_broken_disk(recovery_disk_path: &str) -> Result<()> {
     // If we have an NVMe controller we skip the next steps as they are not applicable
     if helper::is_nvme_controller_present().unwrap_or(false) {
         return Ok(());
     }
     
     debug!("Inside disable_broken_disk");
-    let recover_disk = helper::get_recovery_disk_path(cli_info).replace("/dev/", "");
-    helper::run_cmd("vgchange -an rootvg")?;
+    let recover_disk = recovery_disk_path.replace("/dev/", "");
 
     fs::write(format!("/sys/block/{}/device/delete", recover_disk), b"1")?;
     Ok(())
@@ -457,6 +612,7 @@ pub(crate) fn bind_mount(source: &str, destination: &str) -> Result<()> {
         .fstype(&supported_fs)
         .flags(sys_mount::MountFlags::BIND)
         .mount(source, destination)?;
+    teardown::register(Step::Umount(destination.to_string()));
     debug!("Bind mount {source} to {destination} was successful");
     Ok(())
 }
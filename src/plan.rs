@@ -0,0 +1,540 @@
+use crate::ade;
+use crate::cli::{CliInfo, FsckPolicy};
+use crate::constants;
+use crate::distro::{Distro, LogicalVolumesType};
+use crate::helper;
+use crate::lvm::{self, LvKind};
+use crate::mount;
+use crate::prepare_chroot;
+use crate::teardown::Step;
+use anyhow::Result;
+use std::fmt::Display;
+use std::path::Path;
+
+// The plan is the result of a dry-run. It describes each step ALAR would perform
+// on the disk to be recovered, in the order they would be executed.
+#[derive(Debug, Default)]
+pub(crate) struct Plan {
+    sections: Vec<(&'static str, Vec<String>)>,
+}
+
+impl Plan {
+    fn add(&mut self, section: &'static str, steps: Vec<String>) {
+        let steps = if steps.is_empty() {
+            vec!["none".to_string()]
+        } else {
+            steps
+        };
+        self.sections.push((section, steps));
+    }
+
+    pub(crate) fn build(distro: &Distro, cli_info: &CliInfo) -> Result<Plan> {
+        let mut plan = Plan::default();
+        let recovery_disk_path = helper::get_recovery_disk_path(cli_info)?;
+
+        let mut recovery_disks = vec![recovery_disk_path.clone()];
+        recovery_disks.extend(
+            cli_info
+                .additional_recovery_disks
+                .iter()
+                .map(|disk| format!("{disk} (additional)")),
+        );
+        recovery_disks.push(format!(
+            "Distro: {} {} ({})",
+            if distro.distro_name_version.name.is_empty() {
+                "unknown"
+            } else {
+                &distro.distro_name_version.name
+            },
+            distro.distro_name_version.version_id,
+            distro.architecture
+        ));
+        recovery_disks.push(format!("ADE: {}, LVM: {}", distro.is_ade, distro.is_lvm));
+        plan.add("Recovery disk", recovery_disks);
+
+        plan.add(
+            "Volume groups",
+            distro
+                .volume_groups
+                .iter()
+                .map(|volume_group| {
+                    let mut description = format!("{} on {}", volume_group.name, volume_group.pvs.join(", "));
+                    if volume_group.is_partial() {
+                        description.push_str(&format!(" (partial, missing {})", volume_group.missing_pvs.join(", ")));
+                    }
+                    description
+                })
+                .collect(),
+        );
+
+        plan.add(
+            "Partitions",
+            distro
+                .partitions
+                .iter()
+                .map(|part| {
+                    format!(
+                        "{} type {} filesystem {}{}",
+                        part.device,
+                        part.part_type,
+                        part.fstype,
+                        if part.contains_os { " (OS)" } else { "" }
+                    )
+                })
+                .collect(),
+        );
+
+        plan.add(
+            "Logical volumes",
+            distro
+                .partitions
+                .iter()
+                .filter_map(|part| match &part.logical_volumes {
+                    LogicalVolumesType::Some(lvs) => Some(lvs),
+                    LogicalVolumesType::None => None,
+                })
+                .flatten()
+                .map(|lv| format!("/dev/mapper/{} filesystem {}", lv.name, lv.fstype))
+                .collect(),
+        );
+
+        if let Some(layout) = &distro.btrfs {
+            let mut steps = vec![format!(
+                "default subvolume {} (ID {})",
+                layout.default_subvolume.path, layout.default_subvolume.id
+            )];
+            steps.extend(layout.snapshots.iter().map(|snapshot| {
+                format!(
+                    "snapshot {} {} {} {}{}",
+                    snapshot.number,
+                    snapshot.kind,
+                    snapshot.date,
+                    snapshot.description,
+                    if snapshot.is_default { " (default)" } else { "" }
+                )
+            }));
+            plan.add("Btrfs subvolumes and snapshots", steps);
+        }
+
+        if distro.is_ade {
+            plan.add("ADE unlock", vec![ade::describe_unlock_method(cli_info)]);
+        }
+
+        plan.add("LVM setup", lvm_setup_steps(distro, cli_info)?);
+        plan.add("VG renames", vg_rename_steps(distro));
+        plan.add("Thin and cache pools", pool_steps(distro, cli_info));
+        plan.add("File system checks", fsck_steps(distro, cli_info));
+
+        let required_mounts = prepare_chroot::required_mounts(distro)?;
+        let mut mounts: Vec<String> = required_mounts
+            .iter()
+            .map(|target| {
+                if target.options.is_empty() {
+                    format!("mount {} {}", target.source, target.target)
+                } else {
+                    format!(
+                        "mount -o {} {} {}",
+                        target.options, target.source, target.target
+                    )
+                }
+            })
+            .collect();
+        let clone_names = prepare_chroot::clone_names(distro);
+        mounts.extend(constants::SUPPORT_FILESYSTEMS.split(' ').map(|fs| {
+            if fs == "dev" && !clone_names.is_empty() {
+                let renames: Vec<String> = clone_names.iter().map(|(clone_name, name)| format!("{clone_name} as {name}")).collect();
+                format!(
+                    "mount -t tmpfs tmpfs {0}dev; cp -a --one-file-system /dev/. {0}dev (the LVs of {1})",
+                    constants::RESCUE_ROOT,
+                    renames.join(", ")
+                )
+            } else {
+                format!("mount --bind /{fs}/ {}{fs}", constants::RESCUE_ROOT)
+            }
+        }));
+        plan.add("Mount targets", mounts);
+        // The mountpoints are only known once the root filesystem is mounted
+        plan.add(
+            "Mountpoints created on the recovered root filesystem if missing",
+            required_mounts
+                .iter()
+                .filter(|target| target.target != constants::RESCUE_ROOT)
+                .map(|target| format!("mkdir -p {}", target.target))
+                .collect(),
+        );
+        if let Some(resolution) = prepare_chroot::fstab_resolution(distro) {
+            plan.add(
+                "Unresolved fstab entries",
+                resolution
+                    .unresolved
+                    .iter()
+                    .map(|unresolved| {
+                        format!("{} {}: {}", unresolved.entry.spec, unresolved.entry.mountpoint, unresolved.reason)
+                    })
+                    .collect(),
+            );
+        }
+
+        plan.add("Action scripts", action_steps(cli_info));
+        plan.add("Cleanup", cleanup_steps(distro, &recovery_disk_path));
+        Ok(plan)
+    }
+}
+
+impl Display for Plan {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "ALAR dry-run. Nothing got changed. The following steps would be executed:")?;
+        for (section, steps) in &self.sections {
+            writeln!(f)?;
+            writeln!(f, "{section}:")?;
+            for step in steps {
+                writeln!(f, "  - {step}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Mirrors the LVM commands Distro::new() runs before the VGs get imported
+fn lvm_setup_steps(distro: &Distro, cli_info: &CliInfo) -> Result<Vec<String>> {
+    let mut steps = Vec::new();
+    if lvm::is_devices_file_in_use() {
+        steps.push(format!(
+            "write the private LVM configuration {}/lvm.conf accepting only the recovery disks, the repair VM uses a devices file",
+            constants::PRIVATE_LVM_DIR
+        ));
+        let pvs: Vec<&String> = distro.volume_groups.iter().flat_map(|volume_group| &volume_group.pvs).collect();
+        if pvs.is_empty() {
+            steps.push(format!(
+                "lvmdevices --adddev for each PV on {}",
+                helper::get_recovery_disk_paths(cli_info)?.join(", ")
+            ));
+        }
+        steps.extend(pvs.iter().map(|pv| format!("lvmdevices --adddev {pv}")));
+    }
+    if !distro.partitions.iter().any(|part| part.part_type.contains("8E00")) {
+        return Ok(steps);
+    }
+    steps.push("vgchange -ay".to_string());
+    steps.push("pvscan --cache".to_string());
+    steps.extend(
+        distro
+            .volume_groups
+            .iter()
+            .filter(|volume_group| volume_group.is_partial())
+            .map(|volume_group| format!("vgchange -ay --activationmode partial --select vg_uuid={}", volume_group.uuid)),
+    );
+    Ok(steps)
+}
+
+// Mirrors the renames done by mount::importvg() and ade::ade_importvg()
+fn vg_rename_steps(distro: &Distro) -> Vec<String> {
+    let mut steps = Vec::new();
+    // In dry-run mode the LUKS container isn't opened, the VG within it is unknown
+    if distro.is_ade && distro.volume_groups.is_empty() {
+        steps.push(format!(
+            "the VG on {} gets clone-imported if its name or UUID collides with a VG of the repair VM",
+            constants::ADE_OSENCRYPT_PATH
+        ));
+    }
+
+    for volume_group in &distro.volume_groups {
+        let Some(clone_name) = &volume_group.import.clone_name else {
+            continue;
+        };
+        let pv = if distro.is_ade {
+            constants::ADE_OSENCRYPT_PATH.to_string()
+        } else {
+            volume_group.pvs.join(" ")
+        };
+        if let Some(refusal) = mount::rename_refusal(volume_group) {
+            steps.push(format!("refused before vgimportclone: {refusal}"));
+            continue;
+        }
+        steps.push(format!("vgimportclone -n {clone_name} {pv}"));
+        if volume_group.import.keeps_clone_name {
+            steps.push(format!(
+                "the VG {} keeps the name {clone_name} while ALAR works with it, the VGs of the repair VM stay untouched",
+                volume_group.name
+            ));
+            steps.push(format!(
+                "vgcfgbackup -f {0}/{clone_name}.vg {clone_name}; vgcfgrestore --force --yes -f {0}/{clone_name}.vg {1} (the original name in the metadata, before the chroot)",
+                constants::PRIVATE_LVM_DIR,
+                volume_group.name
+            ));
+            continue;
+        }
+        steps.extend(volume_group.import.repair_vm_renames.iter().map(|rename| {
+            format!("vgrename {} {} (the VG {} of the repair VM)", rename.uuid, rename.to, rename.from)
+        }));
+        steps.push(format!("vgrename {clone_name} {}", volume_group.name));
+    }
+    steps
+}
+
+// Mirrors lvm::activate_stacks(). The pools and the sub-LVs are never mounted.
+fn pool_steps(distro: &Distro, cli_info: &CliInfo) -> Vec<String> {
+    let mut steps = Vec::new();
+    for volume_group in &distro.volume_groups {
+        let vg_name = volume_group.active_name();
+        for pool in &volume_group.logical_volumes {
+            let Some((metadata_lv, tool)) = lvm::pool_metadata_check(pool) else {
+                continue;
+            };
+            let repair = match (cli_info.fsck_policy, pool.kind) {
+                (FsckPolicy::Skip, _) => {
+                    steps.push(format!("no check of the pool {vg_name}/{} (fsck policy 'skip')", pool.name));
+                    continue;
+                }
+                (FsckPolicy::Check, _) => "a damage is only reported".to_string(),
+                (FsckPolicy::Auto, LvKind::ThinPool) => format!("lvconvert --repair {vg_name}/{} if damaged", pool.name),
+                (FsckPolicy::Auto, _) => "a damage stops ALAR".to_string(),
+            };
+            steps.push(format!(
+                "{tool} /dev/mapper/{} with the pool {vg_name}/{} inactive, {repair}",
+                lvm::dm_name(vg_name, &metadata_lv),
+                pool.name
+            ));
+        }
+        let unmounted: Vec<&str> = volume_group
+            .logical_volumes
+            .iter()
+            .filter(|lv| !lv.is_mountable())
+            .map(|lv| lv.name.as_str())
+            .collect();
+        if !unmounted.is_empty() {
+            steps.push(format!("not mounted in {vg_name}: {}", unmounted.join(", ")));
+        }
+    }
+    steps
+}
+
+// Mirrors the order in which Distro::new() checks the partitions while it searches the OS partition
+fn fsck_steps(distro: &Distro, cli_info: &CliInfo) -> Vec<String> {
+    let mut steps = Vec::new();
+    for part in distro
+        .partitions
+        .iter()
+        .filter(|part| !part.part_type.contains("EF00"))
+    {
+        match &part.logical_volumes {
+            LogicalVolumesType::Some(lvs) => {
+                for lv in lvs
+                    .iter()
+                    .filter(|lv| lv.name.contains("rootlv") || lv.name.contains("usrlv"))
+                {
+                    steps.push(mount::fsck_command(
+                        &lv.fstype,
+                        &format!("/dev/mapper/{}", lv.name),
+                        cli_info.fsck_policy,
+                    ));
+                }
+                return steps;
+            }
+            LogicalVolumesType::None if part.fstype == "crypt?" => {
+                steps.push(format!(
+                    "fsck of {} after the disk got unlocked",
+                    constants::ADE_OSENCRYPT_PATH
+                ));
+            }
+            LogicalVolumesType::None => {
+                let path = if distro.is_ade && part.contains_os {
+                    constants::ADE_OSENCRYPT_PATH.to_string()
+                } else {
+                    part.device.clone()
+                };
+                steps.push(mount::fsck_command(&part.fstype, &path, cli_info.fsck_policy));
+            }
+        }
+        if part.contains_os {
+            break;
+        }
+    }
+    steps
+}
+
+fn action_steps(cli_info: &CliInfo) -> Vec<String> {
+    cli_info
+        .actions
+        .split(',')
+        .map(|action| {
+            let action = action.trim();
+            if action == constants::CHROOT_CLI {
+                return format!("{action}: interactive shell in {} within tmux", constants::RESCUE_ROOT);
+            }
+            if action == constants::LVMDEVICES {
+                return format!(
+                    "{action}: check the LVM devices file and filter in {} against the PVs, rewrite them and rebuild the initramfs if required (builtin)",
+                    constants::RESCUE_ROOT
+                );
+            }
+
+            let (source, script) = if cli_info.download_action_scripts {
+                (
+                    format!("download from {}", constants::TARBALL),
+                    format!("{action}-impl.sh or {action}-impl.py"),
+                )
+            } else if !cli_info.local_action_directory.is_empty() {
+                let script = [".py", ".sh"]
+                    .iter()
+                    .map(|suffix| format!("{action}-impl{suffix}"))
+                    .find(|name| {
+                        Path::new(&cli_info.local_action_directory)
+                            .join(name)
+                            .is_file()
+                    })
+                    .unwrap_or_else(|| "NOT AVAILABLE".to_string());
+                (format!("directory {}", cli_info.local_action_directory), script)
+            } else {
+                let script = constants::BUILTIN_ACTION_FILES
+                    .iter()
+                    .map(|(name, _)| *name)
+                    .find(|name| {
+                        *name == format!("{action}-impl.py") || *name == format!("{action}-impl.sh")
+                    })
+                    .unwrap_or("NOT AVAILABLE")
+                    .to_string();
+                ("builtin".to_string(), script)
+            };
+            format!(
+                "{action}: chroot {} /bin/bash -c {}/{script} ({source})",
+                constants::RESCUE_ROOT,
+                constants::ACTION_IMPL_DIR
+            )
+        })
+        .collect()
+}
+
+// Mirrors the steps registered for the teardown, in the order they are undone
+fn cleanup_steps(distro: &Distro, recovery_disk_path: &str) -> Vec<String> {
+    let mut steps = vec![
+        Step::CopyRunLog(constants::RESCUE_ROOT.to_string()),
+        Step::Umount(constants::RESCUE_ROOT.to_string()),
+    ];
+    let has_lvs = distro
+        .partitions
+        .iter()
+        .any(|part| matches!(part.logical_volumes, LogicalVolumesType::Some(_)));
+    let rename_back_steps = |volume_group: &lvm::VolumeGroup| {
+        volume_group
+            .import
+            .repair_vm_renames
+            .iter()
+            .rev()
+            .map(|rename| Step::RenameVg {
+                from: rename.to.clone(),
+                to: rename.from.clone(),
+            })
+            .collect::<Vec<Step>>()
+    };
+
+    // A VG which keeps its clone name has its original name back once the chroot is prepared
+    if distro.is_ade || has_lvs {
+        for volume_group in distro.volume_groups.iter().rev() {
+            steps.push(Step::DeactivateVg(volume_group.name.clone()));
+        }
+    }
+    if distro.is_ade {
+        steps.push(Step::CloseCrypt("rescueencrypt".to_string()));
+    }
+    if distro.is_ade || has_lvs {
+        // Mirrors mount::rename_repair_vm_vgs(), the teardown undoes its steps in reverse order of their registration
+        for volume_group in distro.volume_groups.iter().rev() {
+            if volume_group.import.repair_vm_renames.is_empty() {
+                continue;
+            }
+            for disk in mount::vg_disks(volume_group, recovery_disk_path).into_iter().rev() {
+                steps.push(Step::DetachDisk(disk));
+            }
+            steps.extend(rename_back_steps(volume_group));
+            steps.push(Step::RescanHost);
+        }
+    }
+    if lvm::is_devices_file_in_use() {
+        steps.push(Step::RemoveTree(constants::PRIVATE_LVM_DIR.to_string()));
+    }
+    steps.iter().map(|step| step.to_string()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::distro::{LogicalVolume, PartInfo};
+
+    fn partition(number: i32, part_type: &str, fstype: &str) -> PartInfo {
+        PartInfo {
+            number,
+            device: format!("/dev/sdc{number}"),
+            part_type: part_type.to_string(),
+            fstype: fstype.to_string(),
+            ..PartInfo::default()
+        }
+    }
+
+    #[test]
+    fn plan_of_a_colliding_lvm_disk() {
+        let lv = |name: &str| LogicalVolume {
+            name: name.to_string(),
+            fstype: "xfs".to_string(),
+        };
+        let distro = Distro {
+            partitions: vec![
+                partition(1, "EF00", "vfat"),
+                partition(2, "8300", "xfs"),
+                PartInfo {
+                    contains_os: true,
+                    logical_volumes: LogicalVolumesType::Some(vec![lv("rootvg-rootlv"), lv("rootvg-usrlv")]),
+                    ..partition(4, "8E00", "LVM2_member")
+                },
+            ],
+            is_lvm: true,
+            volume_groups: vec![lvm::VolumeGroup {
+                name: "rootvg".to_string(),
+                uuid: "VgTarget".to_string(),
+                pvs: vec!["/dev/sdc4".to_string()],
+                import: lvm::ImportPlan {
+                    clone_name: Some("alar_vg1".to_string()),
+                    repair_vm_renames: vec![lvm::VgRename {
+                        uuid: "VgRepair".to_string(),
+                        from: "rootvg".to_string(),
+                        to: "alar_vg2".to_string(),
+                    }],
+                    ..lvm::ImportPlan::default()
+                },
+                ..lvm::VolumeGroup::default()
+            }],
+            ..Distro::default()
+        };
+        let cli_info = CliInfo {
+            recovery_disk: "/dev/sdc".to_string(),
+            actions: "fstab".to_string(),
+            dry_run: true,
+            ..CliInfo::default()
+        };
+
+        let plan = Plan::build(&distro, &cli_info).unwrap().to_string();
+        assert!(plan.starts_with("ALAR dry-run. Nothing got changed."));
+        assert!(plan.contains("Logical volumes:\n  - /dev/mapper/rootvg-rootlv filesystem xfs\n  - /dev/mapper/rootvg-usrlv filesystem xfs\n"));
+        assert!(plan.contains("  - vgchange -ay\n  - pvscan --cache\n"));
+        assert!(plan.contains(
+            "VG renames:\n  - vgimportclone -n alar_vg1 /dev/sdc4\n  - vgrename VgRepair alar_vg2 (the VG rootvg of the repair VM)\n  - vgrename alar_vg1 rootvg\n"
+        ));
+        assert!(plan.contains("; xfs_repair /dev/mapper/rootvg-rootlv\n"));
+        assert!(plan.contains(&format!(
+            "Mount targets:\n  - mount -o nouuid /dev/mapper/rootvg-rootlv {0}\n  - mount -o nouuid /dev/mapper/rootvg-usrlv {0}usr\n  - mount -o nouuid /dev/sdc2 {0}boot\n",
+            constants::RESCUE_ROOT
+        )));
+        assert!(plan.contains(&format!(
+            "Mountpoints created on the recovered root filesystem if missing:\n  - mkdir -p {0}usr\n  - mkdir -p {0}boot\n",
+            constants::RESCUE_ROOT
+        )));
+        assert!(plan.contains(&format!(
+            "Action scripts:\n  - fstab: chroot {} /bin/bash -c {}/fstab-impl.py (builtin)\n",
+            constants::RESCUE_ROOT,
+            constants::ACTION_IMPL_DIR
+        )));
+        assert!(plan.contains(
+            "Cleanup:\n  - copy the run log to /srv/rescue-root/var/log/alar\n  - umount -R /srv/rescue-root/\n  - vgchange -an rootvg\n"
+        ));
+    }
+}
@@ -3,22 +3,30 @@ use crate::constants;
 use crate::distro;
 use crate::distro::LogicalVolumesType;
 use crate::distro::PartInfo;
+use crate::fstab;
 use crate::helper;
+use crate::lvm;
 use crate::mount;
+use crate::probe;
 use crate::telemetry;
-use anyhow::Result;
-use log::debug;
+use anyhow::{Context, Result, anyhow};
+use log::{debug, error, warn};
 use std::collections::HashMap;
 use std::env;
+use std::ffi::OsString;
 use std::fs;
+use std::path::{Component, Path, PathBuf};
 
 pub(crate) fn prepare_chroot(distro: &distro::Distro, cli: &cli::CliInfo) -> Result<()> {
     let mut partition_details: HashMap<&str, &PartInfo> = HashMap::new();
 
     mount_required_partitions(distro, cli, &mut partition_details)?;
+    for volume_group in &distro.volume_groups {
+        lvm::restore_name_for_chroot(volume_group)?;
+    }
     mkdir_support_filesystems()?;
-    mount_support_filesystems()?;
-    set_environment(distro, cli, partition_details);
+    mount_support_filesystems(distro)?;
+    set_environment(distro, cli, partition_details)?;
     Ok(())
 }
 
@@ -83,7 +91,8 @@ pub fn set_environment(
     distro: &distro::Distro,
     cli_info: &cli::CliInfo,
     partitions: HashMap<&str, &PartInfo>,
-) {
+) -> Result<()> {
+    let recovery_disk_path = helper::get_recovery_disk_path(cli_info)?;
     let distroname = &distro.distro_name_version.name;
     let distroversion = &distro.distro_name_version.version_id;
     let distrokind = get_distro_kind(distro);
@@ -94,10 +103,17 @@ pub fn set_environment(
         env::set_var("DISTRONAME", format!("'{}'", distroname.as_str()));
         env::set_var("DISTROVERSION", distroversion.as_str());
         env::set_var("isLVM", convert_bool(distro.is_lvm));
-        env::set_var(
-            "RECOVER_DISK_PATH",
-            helper::get_recovery_disk_path(cli_info),
-        );
+        env::set_var("RECOVER_DISK_PATH", &recovery_disk_path);
+        env::set_var("isBtrfs", convert_bool(distro.btrfs.is_some()));
+        // The snapshot the action snapshot-rollback makes the default one
+        let rollback_snapshot = distro.btrfs.as_ref().and_then(|layout| {
+            cli_info
+                .rollback_snapshot
+                .or(layout.rollback_candidate().map(|snapshot| snapshot.number))
+        });
+        if let Some(number) = rollback_snapshot {
+            env::set_var("ROLLBACK_SNAPSHOT", number.to_string());
+        }
         env::set_var(
             "OS_PARTITION",
             partitions.get("os").unwrap().number.to_string(),
@@ -109,11 +125,7 @@ pub fn set_environment(
             );
             env::set_var(
                 "boot_part_path",
-                format!(
-                    "{}{}",
-                    helper::get_recovery_disk_path(cli_info),
-                    partitions.get("boot").unwrap().number
-                ),
+                &partitions.get("boot").unwrap().device,
             );
         }
         if partitions.contains_key("efi") {
@@ -123,11 +135,7 @@ pub fn set_environment(
             );
             env::set_var(
                 "efi_part_path",
-                format!(
-                    "{}{}",
-                    helper::get_recovery_disk_path(cli_info),
-                    partitions.get("efi").unwrap().number
-                ),
+                &partitions.get("efi").unwrap().device,
             );
         }
 
@@ -169,19 +177,26 @@ pub fn set_environment(
             env::set_var("DISTROSUBTYPE", "UNKNOWN");
         },
     }
+    Ok(())
 }
 
-fn mount_required_partitions<'a>(
-    distro: &'a distro::Distro,
-    cli: &cli::CliInfo,
-    partitions: &mut HashMap<&str, &'a PartInfo>,
-) -> Result<()> {
-    let os_part = distro
+#[derive(Debug)]
+pub(crate) struct MountTarget {
+    pub(crate) source: String,
+    pub(crate) target: String,
+    pub(crate) options: String,
+}
+
+fn select_partitions(distro: &distro::Distro) -> HashMap<&'static str, &PartInfo> {
+    let mut partitions: HashMap<&str, &PartInfo> = HashMap::new();
+
+    if let Some(os_part) = distro
         .partitions
         .iter()
         .find(|partition| partition.contains_os)
-        .unwrap(); // unwrap is safe as we have always an OS partition
-    partitions.insert("os", os_part);
+    {
+        partitions.insert("os", os_part);
+    }
 
     let efi_part = distro
         .partitions
@@ -198,173 +213,441 @@ fn mount_required_partitions<'a>(
     if let Some(boot_part) = boot_part {
         partitions.insert("boot", boot_part);
     }
+    partitions
+}
 
-    // A closure is required to build the correct path for the rescue disk
-    let cl_get_rescue_disk_path = || -> String {
-        if distro.is_ade {
-            constants::ADE_OSENCRYPT_PATH.to_string()
+// The partitions and LVs the entries of the fstab are resolved against. The UUID and label of a LV are read from its
+// superblock, they are not collected during the distro detection.
+fn fstab_volumes(distro: &distro::Distro) -> Vec<fstab::Volume> {
+    let mut volumes: Vec<fstab::Volume> = Vec::new();
+    for partition in &distro.partitions {
+        // No signature is found on an ADE partition, a LUKS container on the whole disk is the only volume of the disk
+        let is_crypt = distro.is_ade && (partition.fs_uuid.is_none() || partition.is_whole_disk());
+        let volume = if is_crypt && !partition.part_type.contains("8E00") {
+            // The filesystem is stored within the opened LUKS container
+            let probe = probe::probe_path(constants::ADE_OSENCRYPT_PATH).ok().flatten();
+            fstab::Volume {
+                device: constants::ADE_OSENCRYPT_PATH.to_string(),
+                fstype: partition.fstype.clone(),
+                uuid: probe.as_ref().and_then(|probe| probe.uuid.clone()),
+                label: probe.and_then(|probe| probe.label),
+                ..fstab::Volume::default()
+            }
         } else {
-            format!(
-                "{}{}",
-                helper::get_recovery_disk_path(cli),
-                partitions.get("os").unwrap().number
-            )
+            fstab::Volume {
+                device: partition.device.clone(),
+                fstype: partition.fstype.clone(),
+                uuid: partition.fs_uuid.clone(),
+                label: partition.fs_label.clone(),
+                ..fstab::Volume::default()
+            }
+        };
+        volumes.push(fstab::Volume {
+            part_uuid: partition.part_uuid.clone(),
+            partition_number: (!partition.is_whole_disk()).then_some(partition.number),
+            ..volume
+        });
+
+        if let LogicalVolumesType::Some(lvs) = &partition.logical_volumes {
+            for lv in lvs {
+                let device = format!("/dev/mapper/{}", lv.name);
+                let probe = probe::probe_path(&device).ok().flatten();
+                volumes.push(fstab::Volume {
+                    fstype: lv.fstype.clone(),
+                    uuid: probe.as_ref().and_then(|probe| probe.uuid.clone()),
+                    label: probe.and_then(|probe| probe.label),
+                    lv_name: Some(original_lv_name(distro, &lv.name)),
+                    device,
+                    ..fstab::Volume::default()
+                });
+            }
         }
+    }
+    volumes
+}
+
+// The fstab refers to a LV by the original name of its VG, also while the VG carries a temporary name
+fn original_lv_name(distro: &distro::Distro, dm_name: &str) -> String {
+    let Some((vg, lv)) = lvm::split_dm_name(dm_name) else {
+        return dm_name.to_string();
     };
+    distro
+        .volume_groups
+        .iter()
+        .find(|volume_group| volume_group.active_name() == vg)
+        .map(|volume_group| lvm::dm_name(&volume_group.name, &lv))
+        .unwrap_or_else(|| dm_name.to_string())
+}
 
-    debug!("rescue_disk_path : {}", cl_get_rescue_disk_path());
-    debug!("os_partition : {:?}", partitions.get("os"));
-    debug!("efi_partition : {:?}", partitions.get("efi"));
-    debug!("boot_partition : {:?}", partitions.get("boot"));
+// The mounts the fstab of the recovered system asks for. None if the fstab is missing or empty.
+pub(crate) fn fstab_resolution(distro: &distro::Distro) -> Option<fstab::Resolution> {
+    if distro.fstab.is_empty() {
+        return None;
+    }
+    Some(fstab::resolve(&distro.fstab, &fstab_volumes(distro)))
+}
 
-    // Create the rescue root directory
-    mount::mkdir_rescue_root()?;
+fn rescue_root_path(mountpoint: &str) -> String {
+    format!("{}{}", constants::RESCUE_ROOT, mountpoint.trim_start_matches('/'))
+}
 
-    // Mount each lv if we have them available
-    // This does mount ADE and non ADE partitions/lvs
-    if let LogicalVolumesType::Some(lv_set) = &partitions.get("os").unwrap().logical_volumes {
-        // First mount the rootlv, otherwise we get mount errors if we continue with the wrong order
-        lv_set
-            .iter()
-            .filter(|root_lv| root_lv.name == "rootvg-rootlv")
-            .for_each(|root_lv| {
-                let options = if root_lv.fstype == "xfs" {
-                    "nouuid"
-                } else {
-                    ""
-                };
-                match mount::mount(
-                    &format!("{}{}", "/dev/mapper/", root_lv.name),
-                    constants::RESCUE_ROOT,
-                    options,
-                    false,
-                ) {
-                    Ok(()) => {}
-                    Err(e) => {
-                        let _ = helper::cleanup(distro, cli);
-                        eprintln!(
-                            "Unable to mount the logical volume : {} Error is: {}",
-                            root_lv.name, e
-                        );
-                        std::process::exit(1);
-                    }
-                }
+// Resolves a path below the rescue root the way the chroot sees it. A symlink is followed within the recovered system,
+// an absolute link target starts at the rescue root. A path which ends up outside of the rescue root is refused.
+fn resolve_in_root(root: &Path, path: &Path) -> Result<PathBuf> {
+    fn parts_of(path: &Path) -> Vec<OsString> {
+        path.components()
+            .filter_map(|component| match component {
+                Component::Normal(name) => Some(name.to_os_string()),
+                Component::ParentDir => Some(OsString::from("..")),
+                _ => None,
+            })
+            .collect()
+    }
+
+    if path.components().any(|component| component == Component::ParentDir) {
+        return Err(anyhow!("The mountpoint {} contains '..'", path.display()));
+    }
+    let mut resolved = PathBuf::new();
+    let mut pending = parts_of(path);
+    pending.reverse();
+    let mut links = 0;
+    while let Some(part) = pending.pop() {
+        if part == ".." {
+            if !resolved.pop() {
+                return Err(anyhow!("The mountpoint {} points outside of {}", path.display(), root.display()));
+            }
+            continue;
+        }
+        let candidate = root.join(&resolved).join(&part);
+        if !candidate.symlink_metadata().is_ok_and(|metadata| metadata.file_type().is_symlink()) {
+            resolved.push(part);
+            continue;
+        }
+        links += 1;
+        if links > 40 {
+            return Err(anyhow!("The mountpoint {} has too many levels of symlinks", path.display()));
+        }
+        let link = fs::read_link(&candidate)
+            .with_context(|| format!("The symlink {} can not be read", candidate.display()))?;
+        if link.is_absolute() {
+            resolved.clear();
+        }
+        pending.extend(parts_of(&link).into_iter().rev());
+    }
+    Ok(root.join(resolved))
+}
+
+// Determines what gets mounted where in the chroot environment. The order of the returned list is the mount order.
+// This is used to mount the partitions as well as to print the plan in dry-run mode.
+pub(crate) fn required_mounts(distro: &distro::Distro) -> Result<Vec<MountTarget>> {
+    let partitions = select_partitions(distro);
+    let mut mounts: Vec<MountTarget> = Vec::new();
+
+    fn nouuid_option(fstype: &str) -> String {
+        if fstype == "xfs" { "nouuid".to_string() } else { String::new() }
+    }
+
+    if let Some(os_part) = partitions.get("os") {
+        // Mount each lv if we have them available
+        // This does mount ADE and non ADE partitions/lvs
+        if let LogicalVolumesType::Some(lv_set) = &os_part.logical_volumes {
+            // First mount the rootlv, otherwise we get mount errors if we continue with the wrong order
+            lv_set
+                .iter()
+                .filter(|root_lv| lvm::is_root_lv(&root_lv.name))
+                .for_each(|root_lv| {
+                    mounts.push(MountTarget {
+                        source: format!("{}{}", "/dev/mapper/", root_lv.name),
+                        target: constants::RESCUE_ROOT.to_string(),
+                        options: nouuid_option(&root_lv.fstype),
+                    })
+                });
+        } else {
+            // RAW disks gets mounted here
+            // mind the XFS double UUID issue
+            let source = if distro.is_ade {
+                constants::ADE_OSENCRYPT_PATH.to_string()
+            } else {
+                os_part.device.clone()
+            };
+            // On btrfs the default subvolume is the root, the other subvolumes are mounted as the fstab lists them
+            let options = match &distro.btrfs {
+                Some(layout) => layout.root_option(),
+                None => nouuid_option(&os_part.fstype),
+            };
+            mounts.push(MountTarget {
+                source,
+                target: constants::RESCUE_ROOT.to_string(),
+                options,
             });
-        lv_set
-            .iter()
-            .filter(|volume| volume.name == "rootvg-usrlv" || volume.name == "rootvg-varlv" || volume.name == "rootvg-tmplv")
-            .for_each(|lv| {
-                let options = if lv.fstype == "xfs" { "nouuid" } else { "" };
-                match mount::mount(
-                    &format!("{}{}", "/dev/mapper/", lv.name),
-                    &format!(
-                        "{}{}",
-                        constants::RESCUE_ROOT,
-                        lv.name
-                            .strip_prefix("rootvg-")
-                            .unwrap()
-                            .strip_suffix("lv")
-                            .unwrap()
-                    ),
+        }
+    }
+
+    if let Some(resolution) = fstab_resolution(distro) {
+        // The root is already in place, everything else is mounted the way the recovered system does it
+        let mut fstab_mounts: Vec<(String, MountTarget)> = resolution
+            .mounts
+            .into_iter()
+            .map(|mount| {
+                let target = MountTarget {
+                    source: mount.device,
+                    target: rescue_root_path(&mount.mountpoint),
+                    options: mount.options,
+                };
+                (mount.mountpoint, target)
+            })
+            .collect();
+
+        // /boot and /boot/efi are required by most of the actions, even if the fstab doesn't list them properly
+        for (name, mountpoint) in [("boot", "/boot"), ("efi", "/boot/efi")] {
+            let Some(partition) = partitions.get(name) else {
+                continue;
+            };
+            if fstab_mounts.iter().any(|(known, _)| known == mountpoint) {
+                continue;
+            }
+            warn!("The fstab doesn't mount {} on {mountpoint}. It gets mounted nevertheless.", partition.device);
+            let options = if name == "boot" { nouuid_option(&partition.fstype) } else { String::new() };
+            fstab_mounts.push((
+                mountpoint.to_string(),
+                MountTarget {
+                    source: partition.device.clone(),
+                    target: rescue_root_path(mountpoint),
                     options,
-                    false,
-                ) {
-                    Ok(()) => {}
-                    Err(e) => {
-                        telemetry::send_envelope(&telemetry::create_exception_envelope(
-                            telemetry::SeverityLevel::Error,
-                            "ALAR EXCEPTION",
-                            &format!("Unable to mount the logical volume : {}", lv.name),
-                            "prepare_chroot() -> mount() raised an error",
-                            cli,
-                            distro,
-                        ))
-                        .ok();
-                        eprintln!(
-                            "Unable to mount the logical volume : {} Error is: {}",
-                            lv.name, e
-                        );
-                        std::process::exit(1);
-                    }
-                }
-            });
-    } else {
-        // RAW disks gets mounted here
-        // mind the XFS double UUID issue
-        let command = format!("lsblk -nf -o FSTYPE {}", cl_get_rescue_disk_path());
-        let filesystem = helper::run_fun(&command)?;
-        if filesystem.trim() == "xfs" {
-            mount::mount(
-                &cl_get_rescue_disk_path(),
-                constants::RESCUE_ROOT,
-                "nouuid",
-                false,
-            )?;
-        } else {
-            mount::mount(
-                &cl_get_rescue_disk_path(),
-                constants::RESCUE_ROOT,
-                "",
-                false,
-            )?;
+                },
+            ));
         }
+        fstab_mounts.sort_by_key(|(mountpoint, _)| fstab::mount_order(mountpoint));
+        mounts.extend(fstab_mounts.into_iter().map(|(_, target)| target));
+        return Ok(mounts);
     }
 
-    // Even if we have an ADE encrpted disk the boot partition and the efi partition are not encrypted
-    let rescue_disk_path = helper::get_recovery_disk_path(cli);
+    // Without a fstab the well-known layout is assumed
+    if let Some(LogicalVolumesType::Some(lv_set)) = partitions.get("os").map(|os_part| &os_part.logical_volumes) {
+        for lv in lv_set {
+            let Some((_, lv_name)) = lvm::split_dm_name(&lv.name) else {
+                continue;
+            };
+            if let Some(directory @ ("usr" | "var" | "tmp")) = lv_name.strip_suffix("lv") {
+                mounts.push(MountTarget {
+                    source: format!("{}{}", "/dev/mapper/", lv.name),
+                    target: format!("{}{directory}", constants::RESCUE_ROOT),
+                    options: nouuid_option(&lv.fstype),
+                });
+            }
+        }
+    }
 
     // The order is again important. First /boot then /boot/efi
     // Verify also if we have a boot partition, Ubuntu doesn't have one for example
-    if partitions.get("boot").is_some() {
-        // mind the XFS double UUID issue
-        if let Some(boot_partition) = partitions.get("boot") {
-            if partitions.get("boot").unwrap().fstype == "xfs" {
-                mount::mount(
-                    &format!("{}{}", rescue_disk_path, boot_partition.number),
-                    constants::RESCUE_ROOT_BOOT,
-                    "nouuid",
-                    false,
-                )?;
-            } else {
-                mount::mount(
-                    &format!("{}{}", rescue_disk_path, boot_partition.number),
-                    constants::RESCUE_ROOT_BOOT,
-                    "",
-                    false,
-                )?;
-            }
-        }
+    if let Some(boot_partition) = partitions.get("boot") {
+        mounts.push(MountTarget {
+            source: boot_partition.device.clone(),
+            target: constants::RESCUE_ROOT_BOOT.to_string(),
+            options: nouuid_option(&boot_partition.fstype),
+        });
     }
 
     // Also be carefull with the efi partition, not all distros have one
-    if partitions.get("efi").is_some()
-        && let Some(efi_partition) = partitions.get("efi")
-    {
-        mount::mount(
-            &format!("{}{}", rescue_disk_path, efi_partition.number),
-            constants::RESCUE_ROOT_BOOT_EFI,
-            "",
-            false,
-        )?;
+    if let Some(efi_partition) = partitions.get("efi") {
+        mounts.push(MountTarget {
+            source: efi_partition.device.clone(),
+            target: constants::RESCUE_ROOT_BOOT_EFI.to_string(),
+            options: String::new(),
+        });
+    }
+
+    Ok(mounts)
+}
+
+fn mount_required_partitions<'a>(
+    distro: &'a distro::Distro,
+    cli: &cli::CliInfo,
+    partitions: &mut HashMap<&str, &'a PartInfo>,
+) -> Result<()> {
+    partitions.extend(select_partitions(distro));
+
+    debug!("os_partition : {:?}", partitions.get("os"));
+    debug!("efi_partition : {:?}", partitions.get("efi"));
+    debug!("boot_partition : {:?}", partitions.get("boot"));
+
+    // Create the rescue root directory
+    mount::mkdir_rescue_root()?;
+
+    if let Some(resolution) = fstab_resolution(distro) {
+        for unresolved in &resolution.unresolved {
+            warn!(
+                "The fstab entry {} {} is not mounted: {}",
+                unresolved.entry.spec, unresolved.entry.mountpoint, unresolved.reason
+            );
+        }
+    }
+
+    for mount_target in required_mounts(distro)? {
+        debug!("Mounting {:?}", mount_target);
+        let relative = Path::new(&mount_target.target)
+            .strip_prefix(constants::RESCUE_ROOT)
+            .with_context(|| format!("The mountpoint {} is not below {}", mount_target.target, constants::RESCUE_ROOT))?;
+        let target = resolve_in_root(Path::new(constants::RESCUE_ROOT), relative)?;
+        let target = target.to_string_lossy();
+        // A mountpoint may be missing if the filesystem containing it is damaged. Creating it changes the recovered root filesystem.
+        if !Path::new(target.as_ref()).is_dir() {
+            warn!("The mountpoint {target} is missing. It gets created on the recovered root filesystem");
+            fs::create_dir_all(target.as_ref())
+                .with_context(|| format!("The missing mountpoint {target} can not be created"))?;
+        }
+        if let Err(e) = mount::mount(&mount_target.source, &target, &mount_target.options, false) {
+            telemetry::send_envelope(&telemetry::create_exception_envelope(
+                telemetry::SeverityLevel::Error,
+                "ALAR EXCEPTION",
+                &format!("Unable to mount : {}", mount_target.source),
+                "prepare_chroot() -> mount() raised an error",
+                cli,
+                distro,
+            ))
+            .ok();
+            error!(
+                "Unable to mount : {} Error is: {}",
+                mount_target.source, e
+            );
+            return Err(e);
+        }
     }
 
     Ok(())
 }
 
-fn mount_support_filesystems() -> Result<()> {
+// The VGs which keep their clone name on the repair VM, with their original names
+pub(crate) fn clone_names(distro: &distro::Distro) -> Vec<(String, String)> {
+    distro
+        .volume_groups
+        .iter()
+        .filter(|volume_group| volume_group.import.keeps_clone_name)
+        .filter_map(|volume_group| {
+            volume_group
+                .import
+                .clone_name
+                .as_ref()
+                .map(|clone_name| (clone_name.clone(), volume_group.name.clone()))
+        })
+        .collect()
+}
+
+fn mount_support_filesystems(distro: &distro::Distro) -> Result<()> {
+    let clone_names = clone_names(distro);
     for fs in constants::SUPPORT_FILESYSTEMS.split(' ') {
-        mount::bind_mount(
-            format!("/{fs}/").as_str(),
-            format!("{}{fs}", constants::RESCUE_ROOT).as_str(),
-        )?;
+        let destination = format!("{}{fs}", constants::RESCUE_ROOT);
+        // The mountpoints below /dev are hidden by a copy of /dev
+        fs::create_dir_all(&destination)?;
+        if fs == "dev" && !clone_names.is_empty() {
+            mount_dev_copy(&destination, &clone_names)?;
+            continue;
+        }
+        mount::bind_mount(format!("/{fs}/").as_str(), destination.as_str())?;
     }
 
     Ok(())
 }
 
+// grub2-mkconfig and dracut take the VG and LV names from the device-mapper devices and the LVM metadata. The chroot
+// gets a copy of /dev in which the LVs of a VG with a clone name show up under their original names. The LVs of a VG
+// of the repair VM with the same name are left out of the copy. The /dev of the repair VM stays untouched.
+fn mount_dev_copy(destination: &str, clone_names: &[(String, String)]) -> Result<()> {
+    mount::mount_tmpfs(destination)?;
+    helper::run_cmd(&format!("cp -a --one-file-system /dev/. {destination}"))
+        .with_context(|| format!("/dev can't be copied to {destination}"))?;
+    show_original_names(Path::new(destination), clone_names)
+}
+
+fn show_original_names(dev: &Path, clone_names: &[(String, String)]) -> Result<()> {
+    let mapper = dev.join("mapper");
+    for (clone_name, name) in clone_names {
+        let vg_directory = dev.join(name);
+        if vg_directory.exists() {
+            fs::remove_dir_all(&vg_directory)?;
+        }
+        if dev.join(clone_name).is_dir() {
+            fs::rename(dev.join(clone_name), &vg_directory)?;
+        }
+
+        let mut entries: Vec<(String, String, String)> = Vec::new();
+        for entry in fs::read_dir(&mapper)? {
+            let file_name = entry?.file_name().to_string_lossy().to_string();
+            if let Some((vg, lv)) = lvm::split_dm_name(&file_name) {
+                entries.push((file_name, vg, lv));
+            }
+        }
+        for (file_name, _, _) in entries.iter().filter(|(_, vg, _)| vg == name) {
+            fs::remove_file(mapper.join(file_name))?;
+        }
+        for (file_name, _, lv) in entries.iter().filter(|(_, vg, _)| vg == clone_name) {
+            fs::rename(mapper.join(file_name), mapper.join(lvm::dm_name(name, lv)))?;
+        }
+    }
+    Ok(())
+}
+
 fn mkdir_support_filesystems() -> Result<()> {
     for fs in constants::SUPPORT_FILESYSTEMS.split(' ') {
         fs::create_dir_all(format!("{}/{}", constants::RESCUE_ROOT, fs))?;
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+
+    #[test]
+    fn mountpoints_within_the_rescue_root() {
+        let root = env::temp_dir().join(format!("alar-resolve-{}", std::process::id()));
+        fs::create_dir_all(root.join("srv/data")).unwrap();
+        symlink("/srv/data", root.join("data")).unwrap();
+        symlink("srv", root.join("relative")).unwrap();
+        symlink("../../..", root.join("srv/up")).unwrap();
+
+        let resolve = |path: &str| resolve_in_root(&root, Path::new(path));
+        assert_eq!(resolve("var/log").unwrap(), root.join("var/log"));
+        assert_eq!(resolve("data/www").unwrap(), root.join("srv/data/www"));
+        assert_eq!(resolve("relative/data").unwrap(), root.join("srv/data"));
+        assert!(resolve("srv/../../etc").is_err());
+        assert!(resolve("srv/up/etc").is_err());
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn chroot_dev_with_original_names() {
+        let dev = std::env::temp_dir().join(format!("alar-chroot-dev-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dev);
+        fs::create_dir_all(dev.join("mapper")).unwrap();
+        // rootvg of the repair VM and the recovery disk's rootvg, imported as alar_vg1
+        for (vg, lv, dm) in [("rootvg", "rootlv", "dm-0"), ("alar_vg1", "rootlv", "dm-3"), ("alar_vg1", "usr-lv", "dm-4")] {
+            fs::create_dir_all(dev.join(vg)).unwrap();
+            symlink(format!("../{dm}"), dev.join(vg).join(lv)).unwrap();
+            symlink(format!("../{dm}"), dev.join("mapper").join(lvm::dm_name(vg, lv))).unwrap();
+        }
+
+        show_original_names(&dev, &[("alar_vg1".to_string(), "rootvg".to_string())]).unwrap();
+        let mut mapper: Vec<(String, String)> = fs::read_dir(dev.join("mapper"))
+            .unwrap()
+            .map(|entry| {
+                let path = entry.unwrap().path();
+                let target = fs::read_link(&path).unwrap().display().to_string();
+                (path.file_name().unwrap().to_string_lossy().to_string(), target)
+            })
+            .collect();
+        mapper.sort();
+        assert_eq!(
+            mapper,
+            vec![
+                ("rootvg-rootlv".to_string(), "../dm-3".to_string()),
+                ("rootvg-usr--lv".to_string(), "../dm-4".to_string()),
+            ]
+        );
+        assert_eq!(fs::read_link(dev.join("rootvg/rootlv")).unwrap().display().to_string(), "../dm-3");
+        assert!(!dev.join("alar_vg1").exists());
+
+        let _ = fs::remove_dir_all(&dev);
+    }
+}
@@ -0,0 +1,428 @@
+use anyhow::{Context, Result};
+use log::debug;
+use std::fmt::Display;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+
+// Identifies what a partition or LV contains by the signature of its superblock, the same way blkid does.
+// Nothing is mounted or modified. The names of the types are the ones blkid uses.
+
+// Covers every superblock looked at from the start of the device, the last one are the ZFS uberblocks
+const PROBE_SIZE: usize = 256 * 1024;
+const MD_MAGIC: u32 = 0xa92b4efc;
+const ZFS_UBERBLOCK_MAGIC: u64 = 0x00bab10c;
+// The swap signature sits at the end of the first page. aarch64 kernels may use 64K pages.
+const SWAP_PAGE_SIZES: [usize; 2] = [4096, 65536];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum FsType {
+    Xfs,
+    Ext2,
+    Ext3,
+    Ext4,
+    Btrfs,
+    Vfat,
+    Swap,
+    Luks1,
+    Luks2,
+    Lvm2Member,
+    ZfsMember,
+    LinuxRaidMember,
+    BitLocker,
+}
+
+impl Display for FsType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            FsType::Xfs => "xfs",
+            FsType::Ext2 => "ext2",
+            FsType::Ext3 => "ext3",
+            FsType::Ext4 => "ext4",
+            FsType::Btrfs => "btrfs",
+            FsType::Vfat => "vfat",
+            FsType::Swap => "swap",
+            FsType::Luks1 | FsType::Luks2 => "crypto_LUKS",
+            FsType::Lvm2Member => "LVM2_member",
+            FsType::ZfsMember => "zfs_member",
+            FsType::LinuxRaidMember => "linux_raid_member",
+            FsType::BitLocker => "BitLocker",
+        };
+        write!(f, "{name}")
+    }
+}
+
+impl FsType {
+    // Only those can be checked and mounted directly. Everything else is a container or swap.
+    pub(crate) fn is_filesystem(&self) -> bool {
+        matches!(
+            self,
+            FsType::Xfs | FsType::Ext2 | FsType::Ext3 | FsType::Ext4 | FsType::Btrfs | FsType::Vfat
+        )
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Probe {
+    pub(crate) fstype: FsType,
+    pub(crate) uuid: Option<String>,
+    pub(crate) label: Option<String>,
+    // Flags that matter for the repair, i.e. the XFS version or a pending ext4 journal recovery
+    pub(crate) features: Vec<String>,
+}
+
+impl Probe {
+    fn new(fstype: FsType, uuid: Option<String>, label: Option<String>, features: Vec<String>) -> Probe {
+        Probe {
+            fstype,
+            uuid,
+            label,
+            features,
+        }
+    }
+}
+
+fn u16_le(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(bytes[offset..offset + 2].try_into().unwrap())
+}
+
+fn u32_le(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn u32_be(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn u64_le(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+fn has_bytes(buffer: &[u8], offset: usize, expected: &[u8]) -> bool {
+    buffer.get(offset..offset + expected.len()) == Some(expected)
+}
+
+// The UUID as it is printed by blkid, stored big endian on disk
+fn uuid_string(bytes: &[u8]) -> Option<String> {
+    if bytes.iter().all(|&byte| byte == 0) {
+        return None;
+    }
+    let hex: String = bytes.iter().map(|byte| format!("{byte:02x}")).collect();
+    Some(format!("{}-{}-{}-{}-{}", &hex[0..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..32]))
+}
+
+// A label padded with zeros or spaces. An empty one is None.
+fn label_string(bytes: &[u8]) -> Option<String> {
+    let end = bytes.iter().position(|&byte| byte == 0).unwrap_or(bytes.len());
+    let label = String::from_utf8_lossy(&bytes[..end]).trim_end().to_string();
+    (!label.is_empty()).then_some(label)
+}
+
+fn flag_names(value: u32, flags: &[(u32, &str)]) -> Vec<String> {
+    flags
+        .iter()
+        .filter(|(flag, _)| value & flag != 0)
+        .map(|(_, name)| name.to_string())
+        .collect()
+}
+
+fn probe_xfs(buffer: &[u8]) -> Option<Probe> {
+    if !has_bytes(buffer, 0, b"XFSB") {
+        return None;
+    }
+    let version = u16::from_be_bytes([buffer[100], buffer[101]]) & 0x000f;
+    let mut features = vec![format!("v{version}")];
+    if version == 5 {
+        features.extend(flag_names(
+            u32_be(buffer, 212),
+            &[(0x1, "finobt"), (0x2, "rmapbt"), (0x4, "reflink"), (0x8, "inobtcount")],
+        ));
+        features.extend(flag_names(
+            u32_be(buffer, 216),
+            &[
+                (0x1, "ftype"),
+                (0x2, "sparse_inodes"),
+                (0x4, "meta_uuid"),
+                (0x8, "bigtime"),
+                (0x10, "needsrepair"),
+                (0x20, "nrext64"),
+            ],
+        ));
+    }
+    Some(Probe::new(
+        FsType::Xfs,
+        uuid_string(&buffer[32..48]),
+        label_string(&buffer[108..120]),
+        features,
+    ))
+}
+
+fn probe_ext(buffer: &[u8]) -> Option<Probe> {
+    let sb = buffer.get(1024..2048)?;
+    if u16_le(sb, 56) != 0xef53 {
+        return None;
+    }
+    let compat = u32_le(sb, 92);
+    let incompat = u32_le(sb, 96);
+    let ro_compat = u32_le(sb, 100);
+    // An external journal carries the same magic, but there is nothing to mount
+    if incompat & 0x8 != 0 {
+        return None;
+    }
+    // Anything beyond the feature set of ext3 requires ext4
+    let fstype = if incompat & !0x17 != 0 || ro_compat & !0x7 != 0 {
+        FsType::Ext4
+    } else if compat & 0x4 != 0 {
+        FsType::Ext3
+    } else {
+        FsType::Ext2
+    };
+    let mut features = flag_names(compat, &[(0x4, "has_journal")]);
+    features.extend(flag_names(
+        incompat,
+        &[
+            (0x4, "needs_recovery"),
+            (0x40, "extents"),
+            (0x80, "64bit"),
+            (0x10000, "encrypt"),
+            (0x20000, "casefold"),
+        ],
+    ));
+    features.extend(flag_names(ro_compat, &[(0x400, "metadata_csum")]));
+    Some(Probe::new(fstype, uuid_string(&sb[104..120]), label_string(&sb[120..136]), features))
+}
+
+fn probe_btrfs(buffer: &[u8]) -> Option<Probe> {
+    let sb = buffer.get(65536..65536 + 4096)?;
+    if !has_bytes(sb, 64, b"_BHRfS_M") {
+        return None;
+    }
+    Some(Probe::new(FsType::Btrfs, uuid_string(&sb[32..48]), label_string(&sb[299..555]), Vec::new()))
+}
+
+fn probe_swap(buffer: &[u8]) -> Option<Probe> {
+    let page_size = SWAP_PAGE_SIZES.into_iter().find(|&page_size| {
+        has_bytes(buffer, page_size - 10, b"SWAPSPACE2") || has_bytes(buffer, page_size - 10, b"SWAP-SPACE")
+    })?;
+    let features = if page_size == 4096 { Vec::new() } else { vec![format!("pagesize={page_size}")] };
+    Some(Probe::new(
+        FsType::Swap,
+        uuid_string(&buffer[1036..1052]),
+        label_string(&buffer[1052..1068]),
+        features,
+    ))
+}
+
+fn probe_luks(buffer: &[u8]) -> Option<Probe> {
+    if !has_bytes(buffer, 0, b"LUKS\xba\xbe") {
+        return None;
+    }
+    let uuid = label_string(&buffer[168..208]);
+    match u16::from_be_bytes([buffer[6], buffer[7]]) {
+        1 => Some(Probe::new(FsType::Luks1, uuid, None, vec!["luks1".to_string()])),
+        2 => Some(Probe::new(FsType::Luks2, uuid, label_string(&buffer[24..72]), vec!["luks2".to_string()])),
+        _ => None,
+    }
+}
+
+// The label can be in any of the first four sectors. The PV UUID is printed the way LVM does, in groups of 6-4-4-4-4-4-6.
+fn probe_lvm2(buffer: &[u8]) -> Option<Probe> {
+    let label_start = (0..4)
+        .map(|sector| sector * 512)
+        .find(|&start| has_bytes(buffer, start, b"LABELONE") && has_bytes(buffer, start + 24, b"LVM2 001"))?;
+    let pv_header = label_start + u32_le(buffer, label_start + 20) as usize;
+    let uuid = buffer.get(pv_header..pv_header + 32).map(|raw| {
+        let raw = String::from_utf8_lossy(raw);
+        [0..6, 6..10, 10..14, 14..18, 18..22, 22..26, 26..32]
+            .into_iter()
+            .filter_map(|range| raw.get(range))
+            .collect::<Vec<_>>()
+            .join("-")
+    });
+    Some(Probe::new(FsType::Lvm2Member, uuid, None, Vec::new()))
+}
+
+fn probe_bitlocker(buffer: &[u8]) -> Option<Probe> {
+    has_bytes(buffer, 3, b"-FVE-FS-").then(|| Probe::new(FsType::BitLocker, None, None, Vec::new()))
+}
+
+// The uberblock ring of the first vdev label starts at 128K
+fn probe_zfs(buffer: &[u8]) -> Option<Probe> {
+    let ring = buffer.get(128 * 1024..256 * 1024)?;
+    ring.chunks_exact(1024)
+        .any(|uberblock| {
+            let magic = u64_le(uberblock, 0);
+            magic == ZFS_UBERBLOCK_MAGIC || magic.swap_bytes() == ZFS_UBERBLOCK_MAGIC
+        })
+        .then(|| Probe::new(FsType::ZfsMember, None, None, Vec::new()))
+}
+
+fn probe_vfat(buffer: &[u8]) -> Option<Probe> {
+    if !has_bytes(buffer, 510, &[0x55, 0xAA]) || !matches!(u16_le(buffer, 11), 512 | 1024 | 2048 | 4096) {
+        return None;
+    }
+    let (variant, serial_offset, label_offset) = if has_bytes(buffer, 82, b"FAT32   ") {
+        ("fat32", 67, 71)
+    } else if has_bytes(buffer, 54, b"FAT16   ") {
+        ("fat16", 39, 43)
+    } else if has_bytes(buffer, 54, b"FAT12   ") {
+        ("fat12", 39, 43)
+    } else {
+        return None;
+    };
+    let serial = u32_le(buffer, serial_offset);
+    let label = label_string(&buffer[label_offset..label_offset + 11]).filter(|label| label != "NO NAME");
+    Some(Probe::new(
+        FsType::Vfat,
+        Some(format!("{:04X}-{:04X}", serial >> 16, serial & 0xffff)),
+        label,
+        vec![variant.to_string()],
+    ))
+}
+
+type ProbeFn = fn(&[u8]) -> Option<Probe>;
+
+// The order matters: the weak FAT signature comes last, after all the others have been ruled out
+const PROBES: [ProbeFn; 9] = [
+    probe_luks,
+    probe_lvm2,
+    probe_bitlocker,
+    probe_xfs,
+    probe_ext,
+    probe_btrfs,
+    probe_swap,
+    probe_zfs,
+    probe_vfat,
+];
+
+// A RAID member may carry a filesystem at its start as well, thus the md superblock is looked for first.
+// Version 1.1 and 1.2 are at the start, 1.0 and 0.90 at the end of the device.
+fn probe_mdraid<R: Read + Seek>(buffer: &[u8], reader: &mut R, size: u64) -> Option<Probe> {
+    let sectors = size / 512;
+    let candidates = [
+        (0u64, "1.1"),
+        (4096, "1.2"),
+        ((sectors.saturating_sub(16) & !7) * 512, "1.0"),
+        ((sectors & !127).saturating_sub(128) * 512, "0.90"),
+    ];
+    for (offset, version) in candidates {
+        let superblock = if (offset as usize) + 64 <= buffer.len() {
+            buffer[offset as usize..offset as usize + 64].to_vec()
+        } else {
+            let mut superblock = vec![0u8; 64];
+            if reader.seek(SeekFrom::Start(offset)).is_err() || reader.read_exact(&mut superblock).is_err() {
+                continue;
+            }
+            superblock
+        };
+        if u32_le(&superblock, 0) != MD_MAGIC {
+            continue;
+        }
+        let (uuid, label) = if version == "0.90" {
+            (None, None)
+        } else {
+            (uuid_string(&superblock[16..32]), label_string(&superblock[32..64]))
+        };
+        return Some(Probe::new(FsType::LinuxRaidMember, uuid, label, vec![format!("metadata={version}")]));
+    }
+    None
+}
+
+pub(crate) fn probe<R: Read + Seek>(reader: &mut R) -> Result<Option<Probe>> {
+    let size = reader.seek(SeekFrom::End(0))?;
+    // A device smaller than the probed area reads as zeros beyond its end
+    let mut buffer = vec![0u8; PROBE_SIZE];
+    reader.seek(SeekFrom::Start(0))?;
+    reader.read_exact(&mut buffer[..PROBE_SIZE.min(size as usize)])?;
+
+    if let Some(probe) = probe_mdraid(&buffer, reader, size) {
+        return Ok(Some(probe));
+    }
+    Ok(PROBES.iter().find_map(|probe| probe(&buffer)))
+}
+
+// None means no known signature got found. On an ADE disk this is the encrypted partition, its LUKS header is kept elsewhere.
+pub(crate) fn probe_path(path: &str) -> Result<Option<Probe>> {
+    let mut file = File::open(path).with_context(|| format!("{path} can not be opened"))?;
+    let probe = probe(&mut file).with_context(|| format!("The superblock of {path} can not be read"))?;
+    debug!("Probe of {path}: {probe:?}");
+    Ok(probe)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn device() -> Vec<u8> {
+        vec![0u8; 1024 * 1024]
+    }
+
+    #[test]
+    fn probe_filesystems() {
+        let mut xfs = device();
+        xfs[..4].copy_from_slice(b"XFSB");
+        xfs[32..48].copy_from_slice(&[0x11; 16]);
+        xfs[100..102].copy_from_slice(&0xb4a5u16.to_be_bytes());
+        xfs[108..112].copy_from_slice(b"root");
+        xfs[216..220].copy_from_slice(&0x9u32.to_be_bytes());
+        let result = probe(&mut Cursor::new(xfs)).unwrap().unwrap();
+        assert_eq!(result.fstype.to_string(), "xfs");
+        assert_eq!(result.uuid.as_deref(), Some("11111111-1111-1111-1111-111111111111"));
+        assert_eq!(result.label.as_deref(), Some("root"));
+        assert_eq!(result.features, vec!["v5", "ftype", "bigtime"]);
+
+        let mut ext4 = device();
+        ext4[1024 + 56..1024 + 58].copy_from_slice(&0xef53u16.to_le_bytes());
+        ext4[1024 + 92..1024 + 96].copy_from_slice(&0x4u32.to_le_bytes());
+        ext4[1024 + 96..1024 + 100].copy_from_slice(&0x2c6u32.to_le_bytes());
+        ext4[1024 + 120..1024 + 132].copy_from_slice(b"cloudimg-rfs");
+        let result = probe(&mut Cursor::new(ext4)).unwrap().unwrap();
+        assert_eq!(result.fstype, FsType::Ext4);
+        assert_eq!(result.uuid, None);
+        assert_eq!(result.label.as_deref(), Some("cloudimg-rfs"));
+        assert_eq!(result.features, vec!["has_journal", "needs_recovery", "extents", "64bit"]);
+
+        // A FAT label that contains "crypt" is still a FAT filesystem
+        let mut vfat = device();
+        vfat[11..13].copy_from_slice(&512u16.to_le_bytes());
+        vfat[67..71].copy_from_slice(&0x1234abcdu32.to_le_bytes());
+        vfat[71..82].copy_from_slice(b"CRYPTFAT   ");
+        vfat[82..90].copy_from_slice(b"FAT32   ");
+        vfat[510..512].copy_from_slice(&[0x55, 0xAA]);
+        let result = probe(&mut Cursor::new(vfat)).unwrap().unwrap();
+        assert_eq!(result.fstype, FsType::Vfat);
+        assert_eq!(result.uuid.as_deref(), Some("1234-ABCD"));
+        assert_eq!(result.label.as_deref(), Some("CRYPTFAT"));
+    }
+
+    #[test]
+    fn probe_containers() {
+        let mut lvm = device();
+        lvm[512..520].copy_from_slice(b"LABELONE");
+        lvm[512 + 20..512 + 24].copy_from_slice(&32u32.to_le_bytes());
+        lvm[512 + 24..512 + 32].copy_from_slice(b"LVM2 001");
+        lvm[544..576].copy_from_slice(b"abcdef0123456789abcdef0123456789");
+        let result = probe(&mut Cursor::new(lvm)).unwrap().unwrap();
+        assert_eq!(result.fstype.to_string(), "LVM2_member");
+        assert_eq!(result.uuid.as_deref(), Some("abcdef-0123-4567-89ab-cdef-0123-456789"));
+
+        let mut luks = device();
+        luks[..6].copy_from_slice(b"LUKS\xba\xbe");
+        luks[6..8].copy_from_slice(&2u16.to_be_bytes());
+        luks[168..204].copy_from_slice(b"0b8f9a5e-55b6-4b3b-a4a6-6d4b1f9e2c11");
+        let result = probe(&mut Cursor::new(luks)).unwrap().unwrap();
+        assert_eq!(result.fstype, FsType::Luks2);
+        assert_eq!(result.uuid.as_deref(), Some("0b8f9a5e-55b6-4b3b-a4a6-6d4b1f9e2c11"));
+
+        let mut raid = device();
+        raid[4096..4100].copy_from_slice(&MD_MAGIC.to_le_bytes());
+        // The filesystem inside of the array must not hide the RAID member
+        raid[..4].copy_from_slice(b"XFSB");
+        let result = probe(&mut Cursor::new(raid)).unwrap().unwrap();
+        assert_eq!(result.fstype, FsType::LinuxRaidMember);
+        assert_eq!(result.features, vec!["metadata=1.2"]);
+
+        // No signature at all, like the ADE encrypted root partition
+        assert_eq!(probe(&mut Cursor::new(device())).unwrap(), None);
+    }
+}
@@ -0,0 +1,95 @@
+use crate::constants;
+use anyhow::{Context, Result};
+use chrono::Utc;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+// The log of the current run. It receives the log records of ALAR as well as the output of the actions.
+// A copy of it is written to the recovered disk, so that the administrator of the VM finds a record of what got changed.
+enum RunLog {
+    // The log directory is only known once the command line is parsed. The records until then are kept here.
+    Pending(Vec<u8>),
+    Open(PathBuf, File),
+    Off,
+}
+
+static RUN_LOG: Mutex<RunLog> = Mutex::new(RunLog::Pending(Vec::new()));
+
+// Opens the log file and writes the records logged so far to it. Without a log file nothing more is kept.
+pub(crate) fn init(directory: &str) -> Result<PathBuf> {
+    let mut run_log = RUN_LOG.lock().unwrap_or_else(|e| e.into_inner());
+    let pending = match std::mem::replace(&mut *run_log, RunLog::Off) {
+        RunLog::Pending(pending) => pending,
+        _ => Vec::new(),
+    };
+    let (path, mut file) = open(directory)?;
+    // A failing log file must not stop the recovery
+    let _ = file.write_all(&pending);
+    *run_log = RunLog::Open(path.clone(), file);
+    Ok(path)
+}
+
+fn open(directory: &str) -> Result<(PathBuf, File)> {
+    fs::create_dir_all(directory)
+        .with_context(|| format!("Directory {directory} can not be created"))?;
+    let path = Path::new(directory).join(format!(
+        "alar-{}.log",
+        Utc::now().format("%Y%m%dT%H%M%SZ")
+    ));
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .with_context(|| format!("Log file {} can not be created", path.display()))?;
+    Ok((path, file))
+}
+
+fn write_to_file(buf: &[u8]) {
+    match &mut *RUN_LOG.lock().unwrap_or_else(|e| e.into_inner()) {
+        RunLog::Pending(pending) => pending.extend_from_slice(buf),
+        // A failing log file must not stop the recovery
+        RunLog::Open(_, file) => {
+            let _ = file.write_all(buf);
+        }
+        RunLog::Off => {}
+    }
+}
+
+// Writes a line of action output to the run log. The caller is responsible for the console output.
+pub(crate) fn write_line(line: &str) {
+    write_to_file(format!("{line}\n").as_bytes());
+}
+
+// Copies the run log into /var/log/alar of the recovered disk
+pub(crate) fn copy_to_recovered_disk(rescue_root: &str) -> Result<()> {
+    let guard = RUN_LOG.lock().unwrap_or_else(|e| e.into_inner());
+    let RunLog::Open(path, file) = &*guard else {
+        return Ok(());
+    };
+    file.sync_all()?;
+
+    let destination_dir = Path::new(rescue_root).join(constants::RUN_LOG_DIR.trim_start_matches('/'));
+    fs::create_dir_all(&destination_dir)
+        .with_context(|| format!("Directory {} can not be created", destination_dir.display()))?;
+    let destination = destination_dir.join(path.file_name().unwrap_or_default());
+    fs::copy(path, &destination)
+        .with_context(|| format!("Copying the log to {} failed", destination.display()))?;
+    Ok(())
+}
+
+// Used as the target of the logger. Each record is written to stderr and to the run log.
+pub(crate) struct LogTee;
+
+impl Write for LogTee {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        write_to_file(buf);
+        io::stderr().write_all(buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        io::stderr().flush()
+    }
+}
@@ -0,0 +1,231 @@
+use crate::constants;
+use anyhow::{Result, anyhow};
+use log::{debug, error, warn};
+use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM};
+use signal_hook::flag;
+use signal_hook::iterator::Signals;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+use std::{fs, process, thread};
+
+const HANDLED_SIGNALS: [i32; 3] = [SIGINT, SIGTERM, SIGHUP];
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+static RUN_TIMED_OUT: AtomicBool = AtomicBool::new(false);
+// Set once the run is over. The children of the teardown which follows are never stopped.
+static IS_FINISHED: AtomicBool = AtomicBool::new(false);
+// Held while the children get stopped, the run can't finish in the middle of it
+static STOPPING: Mutex<()> = Mutex::new(());
+
+// The first SIGINT, SIGTERM or SIGHUP stops the running child processes. Main then returns from the run, undoes the
+// setup on the repair VM and ends ALAR with EXIT_INTERRUPTED. A second one ends ALAR right away, without any cleanup.
+pub(crate) fn install() -> Result<()> {
+    let got_signal = Arc::new(AtomicBool::new(false));
+    for signal in HANDLED_SIGNALS {
+        // Registered first, thus it only fires if the flag got already set by an earlier signal
+        flag::register_conditional_shutdown(signal, constants::EXIT_INTERRUPTED, Arc::clone(&got_signal))?;
+        flag::register(signal, Arc::clone(&got_signal))?;
+    }
+
+    let mut signals = Signals::new(HANDLED_SIGNALS)?;
+    thread::spawn(move || {
+        if let Some(signal) = signals.forever().next() {
+            interrupt(signal);
+        }
+    });
+    Ok(())
+}
+
+// Ends ALAR once the deadline of '--run-timeout' passed, wherever it is at this moment: the disk selection, the VG
+// import, a fsck or an action. The running processes are stopped and the run ends like for a signal.
+pub(crate) fn start_watchdog(deadline: Instant) {
+    thread::spawn(move || {
+        thread::sleep(deadline.saturating_duration_since(Instant::now()));
+        if IS_FINISHED.load(Ordering::SeqCst) {
+            return;
+        }
+        RUN_TIMED_OUT.store(true, Ordering::SeqCst);
+        stop_run("The run timeout expired. The running processes are stopped and the repair VM gets cleaned up.");
+    });
+}
+
+pub(crate) fn finish_run() {
+    let _stopping = STOPPING.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+    IS_FINISHED.store(true, Ordering::SeqCst);
+}
+
+// No further command is started once the run got interrupted, only the teardown runs commands then
+pub(crate) fn verify_not_interrupted() -> Result<()> {
+    if is_interrupted() && !IS_FINISHED.load(Ordering::SeqCst) {
+        return Err(anyhow!("ALAR got interrupted, no further command is started"));
+    }
+    Ok(())
+}
+
+pub(crate) fn is_interrupted() -> bool {
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+// The exit code of ALAR if the run got cut short by a signal or by the run timeout
+pub(crate) fn interruption_exit_code() -> Option<i32> {
+    if RUN_TIMED_OUT.load(Ordering::SeqCst) {
+        Some(constants::EXIT_RUN_TIMEOUT)
+    } else if is_interrupted() {
+        Some(constants::EXIT_INTERRUPTED)
+    } else {
+        None
+    }
+}
+
+fn interrupt(signal: i32) {
+    stop_run(&format!(
+        "ALAR got interrupted by {}. The running processes are stopped and the repair VM gets cleaned up. Send the signal again to exit immediately.",
+        signal_name(signal)
+    ))
+}
+
+// Only stops the children, main notices the interruption and unwinds into its teardown. Children which are started
+// until then get stopped as well.
+fn stop_run(message: &str) {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+    error!("{message}");
+    loop {
+        {
+            let _stopping = STOPPING.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+            if IS_FINISHED.load(Ordering::SeqCst) {
+                return;
+            }
+            stop_children();
+        }
+        thread::sleep(std::time::Duration::from_millis(100));
+    }
+}
+
+fn signal_name(signal: i32) -> &'static str {
+    match signal {
+        SIGINT => "SIGINT",
+        SIGTERM => "SIGTERM",
+        SIGHUP => "SIGHUP",
+        _ => "a signal",
+    }
+}
+
+// A process as listed in /proc
+#[derive(Debug, PartialEq)]
+struct Process {
+    pid: u32,
+    ppid: u32,
+    pgrp: u32,
+}
+
+// Parses /proc/<pid>/stat. The command name in braces may contain spaces, thus the fields are read after the last ')'.
+// Zombies are left out, they are gone already and only wait to be reaped.
+fn parse_stat(stat: &str) -> Option<Process> {
+    let pid = stat.split_whitespace().next()?.parse().ok()?;
+    let mut fields = stat.get(stat.rfind(')')? + 1..)?.split_whitespace();
+    let state = fields.next()?;
+    let ppid = fields.next()?.parse().ok()?;
+    let pgrp = fields.next()?.parse().ok()?;
+    (state != "Z").then_some(Process { pid, ppid, pgrp })
+}
+
+fn processes() -> Vec<Process> {
+    let Ok(entries) = fs::read_dir("/proc") else {
+        return Vec::new();
+    };
+    entries
+        .flatten()
+        .filter(|entry| entry.file_name().to_string_lossy().chars().all(|c| c.is_ascii_digit()))
+        .filter_map(|entry| fs::read_to_string(entry.path().join("stat")).ok())
+        .filter_map(|stat| parse_stat(&stat))
+        .collect()
+}
+
+// What gets killed for the processes started by ALAR, directly or by one of its children. The process group of each
+// of them is killed as a whole, so that nothing an action or a command started survives. The group of ALAR itself
+// would include ALAR, its members are killed one by one.
+fn kill_targets(processes: &[Process], own_pid: u32) -> Vec<String> {
+    let own_pgrp = processes.iter().find(|process| process.pid == own_pid).map(|process| process.pgrp);
+    let mut parents = vec![own_pid];
+    let mut targets = Vec::new();
+    while let Some(parent) = parents.pop() {
+        for child in processes.iter().filter(|process| process.ppid == parent) {
+            parents.push(child.pid);
+            let target = if Some(child.pgrp) == own_pgrp {
+                child.pid.to_string()
+            } else {
+                format!("-{}", child.pgrp)
+            };
+            if !targets.contains(&target) {
+                targets.push(target);
+            }
+        }
+    }
+    targets
+}
+
+// The kill targets of the processes running below ALAR right now
+fn running_targets() -> Vec<String> {
+    kill_targets(&processes(), process::id())
+}
+
+fn kill(target: &str, signal: &str) {
+    match process::Command::new("kill").args([signal, "--", target]).status() {
+        Ok(status) if status.success() => debug!("kill {signal} {target} sent"),
+        Ok(status) => warn!("kill {signal} {target} returned {status}"),
+        Err(e) => warn!("kill {signal} {target} was not possible: {e}"),
+    }
+}
+
+// The children get SIGTERM first. Those still running after the grace period get SIGKILL.
+fn stop_children() {
+    let running = running_targets();
+    if running.is_empty() {
+        return;
+    }
+    running.iter().for_each(|target| kill(target, "-TERM"));
+
+    let deadline = Instant::now() + constants::KILL_GRACE_PERIOD;
+    while Instant::now() < deadline {
+        if running_targets().is_empty() {
+            return;
+        }
+        thread::sleep(std::time::Duration::from_millis(100));
+    }
+    running_targets().iter().for_each(|target| kill(target, "-KILL"));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_proc_stat() {
+        assert_eq!(
+            parse_stat("4242 (bash -c) S 100 4242 100 0 -1 4194560"),
+            Some(Process {
+                pid: 4242,
+                ppid: 100,
+                pgrp: 4242
+            })
+        );
+        assert_eq!(parse_stat("4244 (fsck) Z 100 100 100 0 -1 4194560"), None);
+    }
+
+    #[test]
+    fn process_groups_to_kill() {
+        let process = |pid, ppid, pgrp| Process { pid, ppid, pgrp };
+        let processes = vec![
+            process(100, 1, 100),
+            // An action in its own process group and a process it started
+            process(4242, 100, 4242),
+            process(4250, 4242, 4242),
+            // A fsck in the group of ALAR, which started a process in another group
+            process(4243, 100, 100),
+            process(4260, 4243, 4260),
+            process(4245, 1, 4245),
+        ];
+        assert_eq!(kill_targets(&processes, 100), vec!["-4242", "4243", "-4260"]);
+    }
+}
@@ -0,0 +1,242 @@
+use crate::constants;
+use crate::helper;
+use crate::mount;
+use crate::runlog;
+use anyhow::{Result, anyhow};
+use log::{debug, error, info};
+use std::fmt::Display;
+use std::path::Path;
+use std::sync::Mutex;
+use std::{env, fs, process};
+
+// Everything ALAR changes on the repair VM is recorded here together with the way to undo it.
+// A step is registered right after its setup succeeded. run() is called once at the end of every run,
+// successful or not, and leaves the repair VM as clean as it was before.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Step {
+    // Copies the run log to the recovered disk mounted at the given root. It runs before anything gets unmounted.
+    CopyRunLog(String),
+    Umount(String),
+    DeactivateVg(String),
+    CloseCrypt(String),
+    // Removes the recovery disk from the SCSI bus, so that its VG no longer collides with the one of the repair VM.
+    // It runs after the dm-crypt mapping is closed and before the VGs of the repair VM get their names back
+    DetachDisk(String),
+    RenameVg { from: String, to: String },
+    // Gives a clone-imported VG its original name back. It runs after the VG is deactivated, while its PVs are still there
+    RestoreVgName { from: String, to: String },
+    // Adds a detached disk back. It has to run after the VG names are restored
+    RescanHost,
+    RemoveFile(String),
+    RemoveDir(String),
+    // Removes a directory ALAR created together with everything in it
+    RemoveTree(String),
+}
+
+impl Step {
+    // The phases are undone one after the other: the run log, mounts, devices and dm-crypt mappings, detached disks
+    // and VG renames, temp files. Within a phase the steps are undone in reverse order of their setup.
+    fn phase(&self) -> u8 {
+        match self {
+            Step::CopyRunLog(_) => 0,
+            Step::Umount(_) => 1,
+            Step::DeactivateVg(_) | Step::RestoreVgName { .. } | Step::CloseCrypt(_) => 2,
+            Step::DetachDisk(_) | Step::RenameVg { .. } | Step::RescanHost => 3,
+            Step::RemoveFile(_) | Step::RemoveDir(_) | Step::RemoveTree(_) => 4,
+        }
+    }
+
+    fn undo(&self) -> Result<()> {
+        match self {
+            Step::CopyRunLog(root) => {
+                if !is_mounted(root) {
+                    debug!("{root} is not mounted, the run log isn't copied");
+                    return Ok(());
+                }
+                runlog::copy_to_recovered_disk(root)
+            }
+            Step::Umount(target) => {
+                if !is_mounted(target) {
+                    debug!("{target} is not mounted anymore");
+                    return Ok(());
+                }
+                let status = process::Command::new("umount").arg("-R").arg(target).status()?;
+                if !status.success() {
+                    return Err(anyhow!("umount -R {target} returned {status}"));
+                }
+                Ok(())
+            }
+            Step::DeactivateVg(vg) => helper::run_cmd(&format!("vgchange -an {vg}")),
+            Step::CloseCrypt(name) => {
+                // Get out of any mount point, otherwise the device may be busy
+                env::set_current_dir("/")?;
+                helper::run_cmd(&format!("cryptsetup close {name}"))
+            }
+            Step::DetachDisk(disk) => mount::disable_broken_disk(disk),
+            Step::RenameVg { from, to } | Step::RestoreVgName { from, to } => {
+                helper::run_cmd(&format!("vgrename {from} {to}"))
+            }
+            Step::RescanHost => mount::rescan_host(),
+            Step::RemoveFile(path) => {
+                if Path::new(path).exists() {
+                    fs::remove_file(path)?;
+                }
+                Ok(())
+            }
+            Step::RemoveDir(path) => {
+                if Path::new(path).exists() {
+                    fs::remove_dir(path)?;
+                }
+                Ok(())
+            }
+            Step::RemoveTree(path) => {
+                if Path::new(path).exists() {
+                    fs::remove_dir_all(path)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl Display for Step {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Step::CopyRunLog(root) => write!(
+                f,
+                "copy the run log to {root}{}",
+                constants::RUN_LOG_DIR.trim_start_matches('/')
+            ),
+            Step::Umount(target) => write!(f, "umount -R {target}"),
+            Step::DeactivateVg(vg) => write!(f, "vgchange -an {vg}"),
+            Step::CloseCrypt(name) => write!(f, "cryptsetup close {name}"),
+            Step::DetachDisk(disk) => write!(
+                f,
+                "echo 1 > /sys/block/{}/device/delete",
+                disk.trim_start_matches("/dev/")
+            ),
+            Step::RenameVg { from, to } | Step::RestoreVgName { from, to } => write!(f, "vgrename {from} {to}"),
+            Step::RescanHost => write!(f, "echo '- - -' > /sys/class/scsi_host/host1/scan; udevadm trigger"),
+            Step::RemoveFile(path) => write!(f, "rm {path}"),
+            Step::RemoveDir(path) => write!(f, "rmdir {path}"),
+            Step::RemoveTree(path) => write!(f, "rm -r {path}"),
+        }
+    }
+}
+
+static STEPS: Mutex<Vec<Step>> = Mutex::new(Vec::new());
+// Held while the steps get undone. The signal handler and main may both call run(), the later one waits for the first.
+static RUNNING: Mutex<()> = Mutex::new(());
+
+fn normalize(path: &str) -> &str {
+    match path.trim_end_matches('/') {
+        "" => "/",
+        path => path,
+    }
+}
+
+pub(crate) fn register(step: Step) {
+    debug!("Registered teardown step: {step}");
+    STEPS.lock().unwrap_or_else(|e| e.into_inner()).push(step);
+}
+
+// Called if a step got undone already as part of the regular flow
+pub(crate) fn forget(step: &Step) {
+    let mut steps = STEPS.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(position) = steps.iter().rposition(|registered| registered == step) {
+        steps.remove(position);
+    }
+}
+
+// Called after a regular umount. A recursive umount also removes every mount below the target
+pub(crate) fn forget_mount(target: &str, recursive: bool) {
+    let target = normalize(target);
+    STEPS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .retain(|step| match step {
+            Step::Umount(path) => {
+                let path = normalize(path);
+                path != target && !(recursive && path.starts_with(&format!("{target}/")))
+            }
+            _ => true,
+        });
+}
+
+pub(crate) fn is_mounted(target: &str) -> bool {
+    let target = normalize(target);
+    fs::read_to_string("/proc/self/mountinfo")
+        .map(|mountinfo| {
+            mountinfo
+                .lines()
+                .filter_map(|line| line.split(' ').nth(4))
+                .any(|mountpoint| mountpoint == target)
+        })
+        .unwrap_or(false)
+}
+
+fn undo_order(mut steps: Vec<Step>) -> Vec<Step> {
+    steps.reverse();
+    // The sort is stable, thus the reverse order within a phase is kept
+    steps.sort_by_key(Step::phase);
+    steps
+}
+
+// Undoes all registered steps. A failing step doesn't stop the remaining ones. Returns false if any step failed.
+pub(crate) fn run() -> bool {
+    let _running = RUNNING.lock().unwrap_or_else(|e| e.into_inner());
+    let steps = std::mem::take(&mut *STEPS.lock().unwrap_or_else(|e| e.into_inner()));
+    if steps.is_empty() {
+        return true;
+    }
+
+    info!("Cleaning up the repair VM");
+    let mut is_clean = true;
+    for step in undo_order(steps) {
+        debug!("Teardown: {step}");
+        if let Err(e) = step.undo() {
+            error!("Teardown step '{step}' failed: {e:#}");
+            is_clean = false;
+        }
+    }
+    if !is_clean {
+        error!("The repair VM could not be cleaned up completely. Please verify the mounts, dm-crypt mappings and VG names.");
+    }
+    is_clean
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn undo_order_by_phase() {
+        let steps = vec![
+            Step::RemoveDir("/tmp/assert".to_string()),
+            Step::CloseCrypt("rescueencrypt".to_string()),
+            Step::RenameVg {
+                from: "oldvg".to_string(),
+                to: "rootvg".to_string(),
+            },
+            Step::DeactivateVg("rootvg".to_string()),
+            Step::Umount("/srv/rescue-root/".to_string()),
+            Step::Umount("/srv/rescue-root/boot".to_string()),
+            Step::CopyRunLog("/srv/rescue-root/".to_string()),
+            Step::RemoveFile("/tmp/LinuxPassPhraseFileName".to_string()),
+        ];
+        let displayed: Vec<String> = undo_order(steps).iter().map(|step| step.to_string()).collect();
+        assert_eq!(
+            displayed,
+            vec![
+                "copy the run log to /srv/rescue-root/var/log/alar",
+                "umount -R /srv/rescue-root/boot",
+                "umount -R /srv/rescue-root/",
+                "vgchange -an rootvg",
+                "cryptsetup close rescueencrypt",
+                "vgrename oldvg rootvg",
+                "rm /tmp/LinuxPassPhraseFileName",
+                "rmdir /tmp/assert",
+            ]
+        );
+    }
+}
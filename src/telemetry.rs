@@ -1,14 +1,51 @@
 use crate::cli;
+use crate::constants;
 use crate::distro;
 use crate::helper;
 use chrono::Utc;
 use log::debug;
 use reqwest::blocking::Client;
 use reqwest::header::{HeaderMap, HeaderValue, CONTENT_TYPE};
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use std::env;
 use std::time::Duration;
 use std::collections::HashMap;
+use std::sync::OnceLock;
+
+// Whether telemetry data is sent at all. The mode is set once at startup
+#[derive(Debug, Default, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub(crate) enum TelemetryMode {
+    #[default]
+    On,
+    Off,
+}
+
+static TELEMETRY_MODE: OnceLock<TelemetryMode> = OnceLock::new();
+
+pub(crate) fn set_mode(mode: TelemetryMode) {
+    let _ = TELEMETRY_MODE.set(mode);
+}
+
+// Interprets the value of the environment variable ALAR_TELEMETRY
+pub(crate) fn parse_mode(value: &str) -> Result<TelemetryMode, String> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "on" | "1" | "true" | "yes" => Ok(TelemetryMode::On),
+        "off" | "0" | "false" | "no" => Ok(TelemetryMode::Off),
+        _ => Err(format!("'{value}' is not a valid telemetry setting, use 'on' or 'off'")),
+    }
+}
+
+// The one line notice printed at startup
+pub(crate) fn notice(mode: TelemetryMode) -> String {
+    match mode {
+        TelemetryMode::On => format!(
+            "Telemetry is enabled. Distro, action and partition details are sent to Azure Application Insights. Use --no-telemetry or {}=off to turn it off.",
+            constants::TELEMETRY_ENV
+        ),
+        TelemetryMode::Off => "Telemetry is disabled. No data leaves this VM.".to_string(),
+    }
+}
 
 #[allow(dead_code)]
 #[derive(Serialize, Debug)]
@@ -274,6 +311,15 @@ pub(crate) fn get_ikey() -> String {
 }
 
 pub(crate) fn send_envelope<T: Serialize>(envelope: &T) -> anyhow::Result<()> {
+    send_envelope_in_mode(envelope, TELEMETRY_MODE.get().copied().unwrap_or_default())
+}
+
+fn send_envelope_in_mode<T: Serialize>(envelope: &T, mode: TelemetryMode) -> anyhow::Result<()> {
+    if mode == TelemetryMode::Off {
+        debug!("Telemetry is turned off, nothing is sent");
+        return Ok(());
+    }
+
     let endpoint = get_endpoint();
 
     let mut headers = HeaderMap::new();
@@ -308,6 +354,7 @@ mod tests {
     use crate::cli;
     use crate::distro;
     use env_logger::Env;
+    use serde::Serializer;
 
     fn test_create_trace_envelope() -> anyhow::Result<()> {
         let severity_level = SeverityLevel::Information;
@@ -353,6 +400,35 @@ mod tests {
         send_envelope(&envelope)?;
         Ok(())
     }
+    #[test]
+    fn telemetry_mode_values() {
+        assert_eq!(parse_mode("off"), Ok(TelemetryMode::Off));
+        assert_eq!(parse_mode(" OFF "), Ok(TelemetryMode::Off));
+        assert_eq!(parse_mode("0"), Ok(TelemetryMode::Off));
+        assert_eq!(parse_mode("on"), Ok(TelemetryMode::On));
+        assert!(parse_mode("maybe").is_err());
+    }
+
+    #[test]
+    fn telemetry_notice() {
+        assert!(notice(TelemetryMode::On).contains(&format!("{}=off", constants::TELEMETRY_ENV)));
+        assert_eq!(notice(TelemetryMode::Off), "Telemetry is disabled. No data leaves this VM.");
+    }
+
+    // An envelope which can't even be serialized. It is never touched if telemetry is turned off.
+    struct Unsendable;
+
+    impl Serialize for Unsendable {
+        fn serialize<S: Serializer>(&self, _serializer: S) -> Result<S::Ok, S::Error> {
+            panic!("The envelope got serialized although telemetry is turned off")
+        }
+    }
+
+    #[test]
+    fn nothing_sent_if_turned_off() {
+        assert!(send_envelope_in_mode(&Unsendable, TelemetryMode::Off).is_ok());
+    }
+
     #[test]
     fn run_tests() {
         env_logger::Builder::from_env(Env::default().default_filter_or("debug")).init();